@@ -1,5 +1,9 @@
-use crate::parser::{Parser, symbol_table::{Symbol, Class}};
+use std::collections::HashMap;
+
+use crate::lexer::{Token, TokenKind};
+use crate::parser::{ParseError, Parser, types::Type};
 // VM instruction set
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(i32)]
 pub enum Opcode {
@@ -11,7 +15,7 @@ pub enum Opcode {
     BZ,       // Branch if zero
     BNZ,      // Branch if not zero
     ENT,      // Enter function
-    ADJ,      // Adjust stack
+    ADJ,      // Adjust stack by a signed word count: positive drops, negative reserves
     LEV,      // Leave function
 
     // Memory access
@@ -49,26 +53,365 @@ pub enum Opcode {
     MSET,     // Memset
     MCMP,     // Memcmp
     EXIT,     // Exit
+    ASRT,     // Assert
+
+    // Unsigned comparisons, used for pointers
+    ULT,      // Less than
+    UGT,      // Greater than
+    ULE,      // Less than or equal
+    UGE,      // Greater than or equal
+
+    // Character I/O
+    GETC,     // Getchar
+    PUTC,     // Putchar
+
+    // Checked array indexing
+    BNDS,     // Trap unless 0 <= ax < the array length in the operand's guard record
+
+    // Padding and placeholders; 0 is never a valid instruction
+    NOP,      // No operation
+
+    // Indirect calls
+    JSRI,     // Jump to subroutine at the address pushed before the operand's count of arguments
+
+    // String output
+    PUTS,     // Puts
+
+    // Character classification
+    ISDG,     // Isdigit
+    ISAL,     // Isalpha
+    ISSP,     // Isspace
+
+    // Signed bytes
+    LSC,      // Load signed char, sign-extending it
+    SSC,      // Store signed char; ax keeps the sign-extended byte
+
+    // Heap
+    RALC,     // Realloc
+
+    // Arithmetic helpers
+    ABS,      // Abs
+    MIN,      // Min
+    MAX,      // Max
+
+    // Logical not, without the PSH and compare of `== 0`
+    LNOT,     // ax = 1 if ax is zero, else 0
+}
+
+// Number of opcodes in the instruction set
+pub const NUM_OPCODES: usize = 60;
+
+impl Opcode {
+    // All opcodes in encoding order (LEA is 1)
+    pub const ALL: [Opcode; NUM_OPCODES] = [
+        Opcode::LEA, Opcode::IMM, Opcode::JMP, Opcode::JSR, Opcode::BZ, Opcode::BNZ,
+        Opcode::ENT, Opcode::ADJ, Opcode::LEV, Opcode::LI, Opcode::LC, Opcode::SI,
+        Opcode::SC, Opcode::PSH, Opcode::OR, Opcode::XOR, Opcode::AND, Opcode::EQ,
+        Opcode::NE, Opcode::LT, Opcode::GT, Opcode::LE, Opcode::GE, Opcode::SHL,
+        Opcode::SHR, Opcode::ADD, Opcode::SUB, Opcode::MUL, Opcode::DIV, Opcode::MOD,
+        Opcode::OPEN, Opcode::READ, Opcode::CLOS, Opcode::PRTF, Opcode::MALC, Opcode::FREE,
+        Opcode::MSET, Opcode::MCMP, Opcode::EXIT, Opcode::ASRT,
+        Opcode::ULT, Opcode::UGT, Opcode::ULE, Opcode::UGE,
+        Opcode::GETC, Opcode::PUTC, Opcode::BNDS, Opcode::NOP, Opcode::JSRI, Opcode::PUTS,
+        Opcode::ISDG, Opcode::ISAL, Opcode::ISSP, Opcode::LSC, Opcode::SSC, Opcode::RALC,
+        Opcode::ABS, Opcode::MIN, Opcode::MAX, Opcode::LNOT,
+    ];
+
+    // Decode an instruction word back into an opcode
+    pub fn from_i32(value: i32) -> Option<Opcode> {
+        if value < 1 {
+            return None;
+        }
+        Self::ALL.get(value as usize - 1).copied()
+    }
+
+    // The unsigned counterpart of a signed comparison
+    pub fn to_unsigned(self) -> Opcode {
+        match self {
+            Opcode::LT => Opcode::ULT,
+            Opcode::GT => Opcode::UGT,
+            Opcode::LE => Opcode::ULE,
+            Opcode::GE => Opcode::UGE,
+            other => other,
+        }
+    }
+
+    // Whether the opcode loads ax from the address in ax
+    pub fn is_load(self) -> bool {
+        matches!(self, Opcode::LI | Opcode::LC | Opcode::LSC)
+    }
+
+    // Whether the opcode is followed by an operand word
+    pub fn has_operand(self) -> bool {
+        matches!(
+            self,
+            Opcode::LEA | Opcode::IMM | Opcode::JMP | Opcode::JSR |
+            Opcode::BZ | Opcode::BNZ | Opcode::ENT | Opcode::ADJ | Opcode::BNDS | Opcode::JSRI
+        )
+    }
+
+    // Map a system function name to the opcode implementing it
+    pub fn for_sys_func(name: &str) -> Option<Opcode> {
+        match name {
+            "open" => Some(Opcode::OPEN),
+            "read" => Some(Opcode::READ),
+            "close" => Some(Opcode::CLOS),
+            "printf" => Some(Opcode::PRTF),
+            "malloc" => Some(Opcode::MALC),
+            "free" => Some(Opcode::FREE),
+            "realloc" => Some(Opcode::RALC),
+            "memset" => Some(Opcode::MSET),
+            "memcmp" => Some(Opcode::MCMP),
+            "exit" => Some(Opcode::EXIT),
+            "assert" => Some(Opcode::ASRT),
+            "getchar" => Some(Opcode::GETC),
+            "putchar" => Some(Opcode::PUTC),
+            "puts" => Some(Opcode::PUTS),
+            "isdigit" => Some(Opcode::ISDG),
+            "isalpha" => Some(Opcode::ISAL),
+            "isspace" => Some(Opcode::ISSP),
+            "abs" => Some(Opcode::ABS),
+            "min" => Some(Opcode::MIN),
+            "max" => Some(Opcode::MAX),
+            _ => None,
+        }
+    }
+}
+
+// Render the instruction at `at` as text, returning it with the number of words it occupies
+pub fn disassemble_instruction(code: &[i32], at: usize) -> (String, usize) {
+    match Opcode::from_i32(code[at]) {
+        Some(op) if op.has_operand() => match code.get(at + 1) {
+            Some(operand) => (format!("{:?} {}", op, operand), 2),
+            None => (format!("{:?} <missing operand>", op), 1),
+        },
+        Some(op) => (format!("{:?}", op), 1),
+        None => (format!(".word {}", code[at]), 1),
+    }
+}
+
+// Render a whole code segment, one instruction per line prefixed with its address
+pub fn disassemble(code: &[i32]) -> String {
+    let mut out = String::new();
+    let mut at = 0;
+    while at < code.len() {
+        let (text, width) = disassemble_instruction(code, at);
+        out.push_str(&format!("{:>4}: {}\n", at, text));
+        at += width;
+    }
+    out
+}
+
+// Assemble a textual program, one instruction per line, into code words. Mnemonics are
+// case-insensitive, `name:` defines a label that operands can use in place of an address,
+// and `;` starts a comment
+pub fn assemble(source: &str) -> Result<Vec<i32>, String> {
+    let mut code = Vec::new();
+    let mut labels = HashMap::new();
+    let mut fixups = Vec::new(); // (operand offset, label, line number)
+
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        // Any number of labels may precede the instruction
+        while let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if labels.insert(label.to_string(), code.len() as i32).is_some() {
+                return Err(format!("line {}: label '{}' defined twice", number, label));
+            }
+            text = rest.trim();
+        }
+
+        let mut words = text.split_whitespace();
+        let Some(mnemonic) = words.next() else {
+            continue;
+        };
+        let op = Opcode::ALL.iter().copied()
+            .find(|op| format!("{:?}", op).eq_ignore_ascii_case(mnemonic))
+            .ok_or_else(|| format!("line {}: unknown instruction '{}'", number, mnemonic))?;
+        code.push(op as i32);
+
+        match (op.has_operand(), words.next()) {
+            (true, Some(operand)) => match operand.parse::<i32>() {
+                Ok(value) => code.push(value),
+                Err(_) => {
+                    fixups.push((code.len(), operand.to_string(), number));
+                    code.push(0);
+                }
+            },
+            (true, None) => return Err(format!("line {}: {:?} needs an operand", number, op)),
+            (false, Some(operand)) => {
+                return Err(format!("line {}: {:?} takes no operand, found '{}'", number, op, operand));
+            }
+            (false, None) => {}
+        }
+        if let Some(extra) = words.next() {
+            return Err(format!("line {}: unexpected '{}'", number, extra));
+        }
+    }
+
+    for (at, label, number) in fixups {
+        code[at] = *labels.get(&label)
+            .ok_or_else(|| format!("line {}: undefined label '{}'", number, label))?;
+    }
+    Ok(code)
+}
+
+// Jump targets of the loop currently being generated
+pub struct LoopContext {
+    pub continue_target: usize,   // Where `continue` jumps to
+    pub break_fixups: Vec<usize>, // Operands of `break` jumps, patched to the loop's end
+}
+
+// A compiled program: code, initial data, and the source line of each code word
+pub struct Program {
+    pub text: Vec<i32>,
+    pub data: Vec<u8>,
+    pub lines: Vec<usize>, // Parallel to `text`; 0 where no source line applies
+}
+
+// Compiled image files start with these bytes, followed by the format version
+pub const IMAGE_MAGIC: &[u8; 4] = b"C4B\0";
+pub const IMAGE_VERSION: u32 = 1;
+
+impl Program {
+    // Number of instructions in the text, counting an opcode and its operand as one
+    pub fn instruction_count(&self) -> usize {
+        let mut count = 0;
+        let mut at = 0;
+        while at < self.text.len() {
+            let with_operand = Opcode::from_i32(self.text[at]).is_some_and(Opcode::has_operand);
+            at += if with_operand { 2 } else { 1 };
+            count += 1;
+        }
+        count
+    }
+
+    // Bytes of initialized data, such as string literals and globals
+    pub fn data_size(&self) -> usize {
+        self.data.len()
+    }
+
+    // Serialize the program: magic, version, the text, data and line counts, then each
+    // segment in turn, all little endian
+    pub fn to_image(&self) -> Vec<u8> {
+        let mut image = IMAGE_MAGIC.to_vec();
+        for word in [IMAGE_VERSION, self.text.len() as u32, self.data.len() as u32, self.lines.len() as u32] {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in &self.text {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+        image.extend_from_slice(&self.data);
+        for line in &self.lines {
+            image.extend_from_slice(&(*line as u32).to_le_bytes());
+        }
+        image
+    }
+
+    // Read back a program written by `to_image`
+    pub fn from_image(image: &[u8]) -> Result<Program, String> {
+        let mut rest = image.strip_prefix(IMAGE_MAGIC.as_slice())
+            .ok_or("not a compiled image: bad magic number")?;
+
+        let version = take_word(&mut rest)?;
+        if version != IMAGE_VERSION {
+            return Err(format!("unsupported image version {} (expected {})", version, IMAGE_VERSION));
+        }
+        let text_len = take_word(&mut rest)? as usize;
+        let data_len = take_word(&mut rest)? as usize;
+        let lines_len = take_word(&mut rest)? as usize;
+
+        let text = (0..text_len).map(|_| take_word(&mut rest).map(|word| word as i32)).collect::<Result<_, _>>()?;
+        if rest.len() < data_len {
+            return Err("truncated image".to_string());
+        }
+        let (data, mut rest) = rest.split_at(data_len);
+        let lines = (0..lines_len).map(|_| take_word(&mut rest).map(|line| line as usize)).collect::<Result<_, _>>()?;
+        if !rest.is_empty() {
+            return Err(format!("{} unexpected bytes at the end of the image", rest.len()));
+        }
+        Ok(Program { text, data: data.to_vec(), lines })
+    }
+}
+
+// Split a little-endian word off the front of an image
+fn take_word(rest: &mut &[u8]) -> Result<u32, String> {
+    let (word, tail) = rest.split_first_chunk::<4>().ok_or("truncated image")?;
+    *rest = tail;
+    Ok(u32::from_le_bytes(*word))
 }
+
+// Default limit on the data segment; addresses must stay well within an i32
+pub const MAX_DATA_SIZE: usize = 16 * 1024 * 1024;
+
 pub struct CodeGenerator {
     pub text: Vec<i32>,        // Code segment
-    pub data: Vec<u8>,         // Data segment
+    pub data: Vec<u8>,         // Data segment; its length is where the next item goes
     pub text_offset: usize,    // Current offset in code segment
-    pub data_offset: usize,    // Current offset in data segment
+    pub max_data: usize,       // Size the data segment may not grow beyond
+    pub call_fixups: Vec<(usize, String)>, // JSR/IMM operands waiting for a function's address
+    pub line: usize,                       // Source line attributed to newly emitted code
+    pub lines: Vec<usize>,                 // Source line of each word in `text`
+    bounds_records: HashMap<(String, usize), usize>, // Address of the bounds record for each array name and length
+    entry_call: Option<usize>,             // Operand of the prologue's JSR to main
+    last_instruction: Option<usize>,       // Offset of the most recently emitted opcode
 }
+
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CodeGenerator {
     pub fn new() -> Self {
         Self {
             text: Vec::new(),
             data: Vec::new(),
             text_offset: 0,
-            data_offset: 0,
+            max_data: MAX_DATA_SIZE,
+            call_fixups: Vec::new(),
+            line: 0,
+            lines: Vec::new(),
+            bounds_records: HashMap::new(),
+            entry_call: None,
+            last_instruction: None,
+        }
+    }
+
+    // Discard all code and data, keeping the configured limits
+    pub fn clear(&mut self) {
+        *self = Self { max_data: self.max_data, ..Self::new() };
+    }
+
+    // Emit the program prologue: push argc and argv (both 0 for now), call main,
+    // then exit with main's return value. The call is patched by `set_entry_point`
+    pub fn emit_prologue(&mut self) {
+        self.emit_imm(Opcode::IMM, 0); // argc
+        self.emit(Opcode::PSH);
+        self.emit_imm(Opcode::IMM, 0); // argv
+        self.emit(Opcode::PSH);
+        self.emit_imm(Opcode::JSR, 0);
+        self.entry_call = Some(self.text_offset - 1);
+        self.emit_imm(Opcode::ADJ, 2);
+        self.emit(Opcode::PSH);
+        self.emit(Opcode::EXIT);
+    }
+
+    // Point the prologue's call at main
+    pub fn set_entry_point(&mut self, addr: usize) {
+        if let Some(at) = self.entry_call {
+            self.text[at] = addr as i32;
         }
     }
 
     // Emit an instruction
     pub fn emit(&mut self, op: Opcode) {
+        self.last_instruction = Some(self.text_offset);
         self.text.push(op as i32);
+        self.lines.push(self.line);
         self.text_offset += 1;
     }
 
@@ -76,354 +419,604 @@ impl CodeGenerator {
     pub fn emit_imm(&mut self, op: Opcode, val: i32) {
         self.emit(op);
         self.text.push(val);
+        self.lines.push(self.line);
         self.text_offset += 1;
     }
 
-    // Allocate space in the data segment
-    pub fn allocate_data(&mut self, size: usize) -> usize {
-        let offset = self.data_offset;
-        self.data_offset += size;
-        self.data.resize(self.data_offset, 0);
-        offset
+    // Load the value at the address in ax, using the width of the given type
+    pub fn emit_load(&mut self, typ: &Type) {
+        match typ {
+            Type::Char => self.emit(Opcode::LC),
+            Type::SChar => self.emit(Opcode::LSC),
+            _ => self.emit(Opcode::LI),
+        }
     }
 
-    // Store a string in the data segment and return its address
-    pub fn store_string(&mut self, s: &str) -> usize {
-        let addr = self.data.len();
+    // Store ax at the address on top of the stack, using the width of the given type
+    pub fn emit_store(&mut self, typ: &Type) {
+        match typ {
+            Type::Char => self.emit(Opcode::SC),
+            Type::SChar => self.emit(Opcode::SSC),
+            _ => self.emit(Opcode::SI),
+        }
+    }
 
-        println!("DEBUG: Storing string '{}' at address {}", s, addr);
-        println!("DEBUG: String bytes: {:?}", s.as_bytes());
+    // The most recently emitted opcode
+    pub fn last_op(&self) -> Option<Opcode> {
+        self.last_instruction.and_then(|at| Opcode::from_i32(self.text[at]))
+    }
 
-        // Add the string to the data segment
-        for byte in s.as_bytes() {
-            self.data.push(*byte);
+    // Remove a trailing LI/LC so the address of an lvalue stays in ax
+    pub fn pop_load(&mut self) -> Option<Opcode> {
+        match self.last_op() {
+            Some(op) if op.is_load() => {
+                self.text.pop();
+                self.lines.pop();
+                self.text_offset -= 1;
+                self.last_instruction = None;
+                Some(op)
+            }
+            _ => None,
         }
+    }
 
-        // Add null terminator
-        self.data.push(0);
+    // Drop everything emitted from `offset` onwards
+    pub fn truncate(&mut self, offset: usize) {
+        self.text.truncate(offset);
+        self.lines.truncate(offset);
+        self.text_offset = self.text.len();
+        self.call_fixups.retain(|(at, _)| *at < offset);
+        if self.last_instruction.is_some_and(|at| at >= offset) {
+            self.last_instruction = None;
+        }
+    }
 
-        println!("DEBUG: Data segment size after storing string: {}", self.data.len());
-        println!("DEBUG: First 10 bytes of data segment: {:?}", &self.data[0..std::cmp::min(10, self.data.len())]);
+    // Evaluate the code emitted from `start` onwards if it only combines constants
+    pub fn eval_constant(&self, start: usize) -> Option<i64> {
+        let mut stack = Vec::new();
+        let mut ax: i32 = 0;
+        let mut at = start;
+        while at < self.text_offset {
+            let op = Opcode::from_i32(self.text[at])?;
+            if op == Opcode::IMM {
+                ax = *self.text.get(at + 1)?;
+                at += 2;
+                continue;
+            }
+            at += 1;
+            if op == Opcode::PSH {
+                stack.push(ax);
+                continue;
+            }
+            if op == Opcode::LNOT {
+                ax = (ax == 0) as i32;
+                continue;
+            }
+            let lhs = stack.pop()?;
+            ax = match op {
+                Opcode::OR => lhs | ax,
+                Opcode::XOR => lhs ^ ax,
+                Opcode::AND => lhs & ax,
+                Opcode::EQ => (lhs == ax) as i32,
+                Opcode::NE => (lhs != ax) as i32,
+                Opcode::LT => (lhs < ax) as i32,
+                Opcode::GT => (lhs > ax) as i32,
+                Opcode::LE => (lhs <= ax) as i32,
+                Opcode::GE => (lhs >= ax) as i32,
+                Opcode::ULT => ((lhs as u32) < ax as u32) as i32,
+                Opcode::UGT => (lhs as u32 > ax as u32) as i32,
+                Opcode::ULE => (lhs as u32 <= ax as u32) as i32,
+                Opcode::UGE => (lhs as u32 >= ax as u32) as i32,
+                Opcode::SHL => lhs.checked_shl(ax as u32)?,
+                Opcode::SHR => lhs.checked_shr(ax as u32)?,
+                Opcode::ADD => lhs.wrapping_add(ax),
+                Opcode::SUB => lhs.wrapping_sub(ax),
+                Opcode::MUL => lhs.wrapping_mul(ax),
+                Opcode::DIV => lhs.checked_div(ax)?,
+                Opcode::MOD => lhs.checked_rem(ax)?,
+                // Anything touching memory or control flow is not a constant
+                _ => return None,
+            };
+        }
+        stack.is_empty().then_some(ax as i64)
+    }
 
-        addr
+    // Current offset in the data segment, where the next item will be placed
+    pub fn data_offset(&self) -> usize {
+        self.data.len()
     }
 
-    // Generate code for a function
-    pub fn gen_function(&mut self, parser: &mut Parser, _symbol: &Symbol) -> Result<(), String> {
-        // Record the function's entry point
-        let entry_point = self.text_offset;
+    // Extend the data segment by `size` zeroed bytes and return where they start, unless
+    // that would take it past `max_data`
+    fn reserve_data(&mut self, size: usize) -> Result<usize, String> {
+        let addr = self.data.len();
+        match addr.checked_add(size) {
+            Some(end) if end <= self.max_data => {
+                self.data.resize(end, 0);
+                Ok(addr)
+            }
+            _ => Err(format!("data segment exceeds the limit of {} bytes", self.max_data)),
+        }
+    }
 
-        // Emit ENT and reserve its slot for locals in one go
-        self.emit_imm(Opcode::ENT, 0); // Placeholder for local variable space
+    // Allocate space in the data segment
+    pub fn allocate_data(&mut self, size: usize) -> Result<usize, String> {
+        self.reserve_data(size)
+    }
 
-        // Parse function body
-        parser.parse_compound_statement()?;
+    // Write initial values into data storage at `addr`, `size` bytes each
+    pub fn init_data(&mut self, addr: usize, size: usize, values: &[i64]) {
+        for (i, value) in values.iter().enumerate() {
+            let at = addr + i * size;
+            self.data[at..at + size].copy_from_slice(&(*value as i32).to_le_bytes()[..size]);
+        }
+    }
 
-        // Emit function epilogue
-        self.emit(Opcode::LEV);
+    // Store a bounds-check record (the length word, then the NUL-terminated array name)
+    // in the data segment and return its address. Every index of the same array shares one
+    pub fn store_bounds(&mut self, name: &str, len: usize) -> Result<usize, String> {
+        let key = (name.to_string(), len);
+        if let Some(&addr) = self.bounds_records.get(&key) {
+            return Ok(addr);
+        }
+        let addr = self.reserve_data(4 + name.len() + 1)?;
+        self.data[addr..addr + 4].copy_from_slice(&(len as i32).to_le_bytes());
+        self.data[addr + 4..addr + 4 + name.len()].copy_from_slice(name.as_bytes());
+        self.bounds_records.insert(key, addr);
+        Ok(addr)
+    }
 
-        // Update the local variable space
-        self.text[entry_point + 1] = parser.local_offset as i32;
+    // Store a string in the data segment and return its address
+    pub fn store_string(&mut self, s: &str) -> Result<usize, String> {
+        // Add the string to the data segment, null terminator included. The lexer reads
+        // literals a byte per char, so every char is below 256 and stands for one byte
+        let len = s.chars().count();
+        let addr = self.reserve_data(len + 1)?;
+        for (i, c) in s.chars().enumerate() {
+            self.data[addr + i] = c as u8;
+        }
 
-        Ok(())
+        Ok(addr)
     }
+}
 
-    // Generate code for an expression
-    pub fn gen_expression(&mut self, parser: &mut Parser) -> Result<(), String> {
-        println!("CODEGEN DEBUG: Entering gen_expression, current token: {:?}", parser.lexer.peek_token());
-        parser.parse_expression()?;
-        println!("DEBUG: Expression result: value={}, class={:?}", parser.current_value, parser.current_class);
-        println!("CODEGEN DEBUG: Before codegen match, current_class={:?}, current_id={:?}", parser.current_class, parser.current_id);
-        match parser.current_class {
-            Some(crate::parser::symbol_table::Class::Global) => {
-                // Global variable
-                self.emit_imm(Opcode::IMM, parser.current_value as i32);
-                self.emit(Opcode::LI);
-                self.emit(Opcode::PSH);
-            },
-            Some(crate::parser::symbol_table::Class::Local) => {
-                // Local variable
-                self.emit_imm(Opcode::LEA, parser.current_value as i32);
-                self.emit(Opcode::LI);
-                self.emit(Opcode::PSH);
-            },
-            Some(crate::parser::symbol_table::Class::Function) => {
-                // Function call: arguments already pushed on stack
-                // Look up the *true* entry point for this function
-                let func_name = parser.current_id.as_ref().expect("current_id should be set for Function");
-                let sym = parser.symbol_table.lookup(func_name).ok_or_else(|| format!("Unknown function `{}`", func_name))?;
-                // Emit the real address
-                self.emit_imm(Opcode::IMM, sym.val as i32);
-                self.emit(Opcode::JSR);
-                // Now pop the arguments off the stack:
-                let arg_count = parser.arg_count as i32;
-                if arg_count > 0 {
-                    self.emit_imm(Opcode::ADJ, arg_count);
-                }
-            },
-            Some(crate::parser::symbol_table::Class::Sys) => {
-                // System call: arguments must already be pushed on stack (by expression parser)
-                match parser.current_id.as_deref() {
-                    Some("printf") => self.emit(Opcode::PRTF),
-                    Some("open") => self.emit(Opcode::OPEN),
-                    Some("read") => self.emit(Opcode::READ),
-                    Some("close") => self.emit(Opcode::CLOS),
-                    Some("malloc") => self.emit(Opcode::MALC),
-                    Some("free") => self.emit(Opcode::FREE),
-                    Some("memset") => self.emit(Opcode::MSET),
-                    Some("memcmp") => self.emit(Opcode::MCMP),
-                    Some("exit") => self.emit(Opcode::EXIT),
-                    _ => println!("DEBUG: Unknown system function: {:?}", parser.current_id),
-                }
-                let arg_count = parser.arg_count as i32;
-                if arg_count > 0 {
-                    self.emit_imm(Opcode::ADJ, arg_count);
-                }
-            },
-            None => {
-                // Literal or result
-                self.emit_imm(Opcode::IMM, parser.current_value as i32);
-                self.emit(Opcode::PSH);
-            },
+impl<'a> Parser<'a> {
+    // Generate code for a function body
+    pub fn gen_function(&mut self, name: &str) -> Result<(), ParseError> {
+        // Record the function's entry point so calls can target it
+        let entry_point = self.code.text_offset;
+        self.symbol_table.update_symbol(name, |symbol| symbol.val = entry_point as i64)?;
+        self.return_type = self.symbol_table.lookup(name).and_then(|symbol| symbol.typ.return_type().cloned());
+
+        // Emit ENT; its operand is patched with the local space once the body is known
+        self.code.emit_imm(Opcode::ENT, 0);
+
+        // Generate the function body
+        self.labels.clear();
+        self.gen_compound_statement()?;
+
+        // Labels are local to the function, and a goto may come before its label
+        for (at, label, span) in std::mem::take(&mut self.goto_fixups) {
+            match self.labels.get(&label) {
+                Some(&target) => self.code.text[at] = target as i32,
+                None => return Err(self.error_at(format!("label '{}' used but not defined", label), span)),
+            }
         }
 
+        // Emit function epilogue for bodies that fall off the end
+        self.code.emit(Opcode::LEV);
+
+        // Update the local variable space
+        self.code.text[entry_point + 1] = self.local_offset;
+
         Ok(())
     }
 
     // Generate code for a statement
-    pub fn gen_statement(&mut self, parser: &mut Parser) -> Result<(), String> {
-        println!("DEBUG: [gen_statement] Entered gen_statement, current token: {:?}", parser.lexer.peek_token());
-        let result = match parser.lexer.peek_token() {
-            Some(crate::lexer::Token::If) => {
-                println!("DEBUG: [gen_statement] Detected IF statement");
-                self.gen_if_statement(parser)
-            },
-            Some(crate::lexer::Token::While) => {
-                println!("DEBUG: [gen_statement] Detected WHILE statement");
-                self.gen_while_statement(parser)
-            },
-            Some(crate::lexer::Token::Return) => {
-                println!("DEBUG: [gen_statement] Detected RETURN statement");
-                self.gen_return_statement(parser)
-            },
-            Some(crate::lexer::Token::OpenBrace) => {
-                println!("DEBUG: [gen_statement] Detected COMPOUND statement");
-                self.gen_compound_statement(parser)
-            },
-            _ => {
-                println!("DEBUG: [gen_statement] Detected EXPRESSION statement");
-                self.gen_expression_statement(parser)
-            },
-        };
-        println!("DEBUG: [gen_statement] Exiting gen_statement, current token: {:?}", parser.lexer.peek_token());
-        result
+    pub fn gen_statement(&mut self) -> Result<(), ParseError> {
+        self.code.line = self.lexer.line;
+        self.nested("statement", |parser| match parser.lexer.peek_token() {
+            Some(Token::If) => parser.gen_if_statement(),
+            Some(Token::While) => parser.gen_while_statement(),
+            Some(Token::For) => parser.gen_for_statement(),
+            Some(Token::Break) => parser.gen_break_statement(),
+            Some(Token::Continue) => parser.gen_continue_statement(),
+            Some(Token::Return) => parser.gen_return_statement(),
+            Some(Token::Goto) => parser.gen_goto_statement(),
+            Some(Token::Id(label)) if parser.lexer.peek_next_token() == Some(Token::Colon) => parser.gen_labeled_statement(label),
+            Some(Token::OpenBrace) => parser.gen_compound_statement(),
+            _ => parser.gen_expression_statement(),
+        })
     }
 
     // Generate code for if statement
-    fn gen_if_statement(&mut self, parser: &mut Parser) -> Result<(), String> {
+    fn gen_if_statement(&mut self) -> Result<(), ParseError> {
         // Consume 'if'
-        parser.lexer.next_token();
+        self.lexer.next_token();
 
         // Expect '('
-        if let Some(crate::lexer::Token::OpenParen) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
-        } else {
-            return Err("Expected '(' after 'if'".to_string());
-        }
+        self.expect(Token::OpenParen, "Expected '(' after 'if'")?;
 
         // Generate code for condition
-        self.gen_expression(parser)?;
+        let inverted = self.parse_condition()?;
 
         // Expect ')'
-        if let Some(crate::lexer::Token::CloseParen) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
-        } else {
-            return Err("Expected ')' after if condition".to_string());
-        }
+        self.expect(Token::CloseParen, "Expected ')' after if condition")?;
 
-        // Emit branch if zero (condition is false)
-        self.emit(Opcode::BZ);
-        let else_jump = self.text_offset;
-        self.emit_imm(Opcode::IMM, 0); // Placeholder for else jump address
+        // Emit branch if the condition is false; for `!x` that is when x is non-zero
+        self.code.emit_imm(if inverted { Opcode::BNZ } else { Opcode::BZ }, 0);
+        let else_jump = self.code.text_offset - 1; // Placeholder for else jump address
 
         // Generate code for then-branch
-        self.gen_statement(parser)?;
+        self.gen_statement()?;
 
         // Check for else-branch
-        if let Some(crate::lexer::Token::Else) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
+        if let Some(Token::Else) = self.lexer.peek_token() {
+            self.lexer.next_token();
 
             // Emit jump to skip else-branch
-            self.emit(Opcode::JMP);
-            let end_jump = self.text_offset;
-            self.emit_imm(Opcode::IMM, 0); // Placeholder for end jump address
+            self.code.emit_imm(Opcode::JMP, 0);
+            let end_jump = self.code.text_offset - 1; // Placeholder for end jump address
 
             // Update else jump address
-            self.text[else_jump] = self.text_offset as i32;
+            self.code.text[else_jump] = self.code.text_offset as i32;
 
             // Generate code for else-branch
-            self.gen_statement(parser)?;
+            self.gen_statement()?;
 
             // Update end jump address
-            self.text[end_jump] = self.text_offset as i32;
+            self.code.text[end_jump] = self.code.text_offset as i32;
         } else {
             // No else-branch, update else jump address to current position
-            self.text[else_jump] = self.text_offset as i32;
+            self.code.text[else_jump] = self.code.text_offset as i32;
         }
 
         Ok(())
     }
 
     // Generate code for while statement
-    fn gen_while_statement(&mut self, parser: &mut Parser) -> Result<(), String> {
+    fn gen_while_statement(&mut self) -> Result<(), ParseError> {
         // Consume 'while'
-        parser.lexer.next_token();
+        self.lexer.next_token();
 
         // Record start of loop for condition
-        let loop_start = self.text_offset;
+        let loop_start = self.code.text_offset;
 
         // Expect '('
-        if let Some(crate::lexer::Token::OpenParen) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
-        } else {
-            return Err("Expected '(' after 'while'".to_string());
-        }
+        self.expect(Token::OpenParen, "Expected '(' after 'while'")?;
 
         // Generate code for condition
-        self.gen_expression(parser)?;
+        let inverted = self.parse_condition()?;
 
         // Expect ')'
-        if let Some(crate::lexer::Token::CloseParen) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
-        } else {
-            return Err("Expected ')' after while condition".to_string());
-        }
+        self.expect(Token::CloseParen, "Expected ')' after while condition")?;
 
-        // Emit branch if zero (condition is false)
-        self.emit(Opcode::BZ);
-        let end_jump = self.text_offset;
-        self.emit_imm(Opcode::IMM, 0); // Placeholder for end jump address
+        // Emit branch if the condition is false; for `!x` that is when x is non-zero
+        self.code.emit_imm(if inverted { Opcode::BNZ } else { Opcode::BZ }, 0);
+        let end_jump = self.code.text_offset - 1; // Placeholder for end jump address
 
-        // Generate code for loop body
-        self.gen_statement(parser)?;
+        // Generate code for loop body; `continue` re-evaluates the condition
+        self.gen_loop_body(loop_start)?;
 
         // Emit jump back to condition
-        self.emit_imm(Opcode::JMP, loop_start as i32);
+        self.code.emit_imm(Opcode::JMP, loop_start as i32);
 
         // Update end jump address
-        self.text[end_jump] = self.text_offset as i32;
+        self.code.text[end_jump] = self.code.text_offset as i32;
+        self.patch_breaks();
 
         Ok(())
     }
-    fn gen_return_statement(&mut self, parser: &mut Parser) -> Result<(), String> {
-        println!("CODEGEN DEBUG: Entering gen_return_statement, current token: {:?}", parser.lexer.peek_token());
-        // Consume 'return'
-        parser.lexer.next_token();
-        println!("CODEGEN DEBUG: After consuming 'return', current token: {:?}", parser.lexer.peek_token());
-        // Always emit IMM for the return value (default 0 if none)
-        if parser.lexer.peek_token() != Some(crate::lexer::Token::Semi) {
-            println!("CODEGEN DEBUG: Generating code for return expression");
-            self.gen_expression(parser)?;
+
+    // Generate code for for statement: for ([init]; [cond]; [post]) statement
+    //
+    // Layout: init; cond: BZ end; JMP body; post: ...; JMP cond; body: ...; JMP post; end:
+    fn gen_for_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'for'
+        self.lexer.next_token();
+
+        // Expect '('
+        self.expect(Token::OpenParen, "Expected '(' after 'for'")?;
+
+        // Initializer
+        if self.lexer.peek_token() != Some(Token::Semi) {
+            self.parse_expression()?;
+        }
+        self.expect_for_semi()?;
+
+        // Condition; an empty condition loops forever
+        let cond_start = self.code.text_offset;
+        let mut end_jump = None;
+        if self.lexer.peek_token() != Some(Token::Semi) {
+            self.parse_expression()?;
+            self.code.emit_imm(Opcode::BZ, 0);
+            end_jump = Some(self.code.text_offset - 1);
+        }
+        self.expect_for_semi()?;
+        self.code.emit_imm(Opcode::JMP, 0);
+        let body_jump = self.code.text_offset - 1;
+
+        // Post-expression, run after every iteration and on `continue`
+        let post_start = self.code.text_offset;
+        if self.lexer.peek_token() != Some(Token::CloseParen) {
+            self.parse_expression()?;
+        }
+        self.code.emit_imm(Opcode::JMP, cond_start as i32);
+
+        // Expect ')'
+        self.expect(Token::CloseParen, "Expected ')' after for clauses")?;
+
+        // Generate code for loop body
+        self.code.text[body_jump] = self.code.text_offset as i32;
+        self.gen_loop_body(post_start)?;
+        self.code.emit_imm(Opcode::JMP, post_start as i32);
+
+        if let Some(end_jump) = end_jump {
+            self.code.text[end_jump] = self.code.text_offset as i32;
+        }
+        self.patch_breaks();
+
+        Ok(())
+    }
+
+    fn expect_for_semi(&mut self) -> Result<(), ParseError> {
+        if let Some(Token::Semi) = self.lexer.peek_token() {
+            self.lexer.next_token();
+            Ok(())
         } else {
-            println!("CODEGEN DEBUG: No return value, emitting IMM 0");
-            self.emit_imm(Opcode::IMM, 0);
+            Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], &format!("Expected ';' in for statement, found: {:?}", self.lexer.peek_token())))
         }
-        println!("CODEGEN DEBUG: After generating return expression, current token: {:?}", parser.lexer.peek_token());
-        // Expect ';'
-        if let Some(crate::lexer::Token::Semi) = parser.lexer.peek_token() {
-            println!("CODEGEN DEBUG: Found semicolon after return, consuming it");
-            parser.lexer.next_token();
+    }
+
+    // Generate a loop body with a fresh loop context; the context stays pushed until patch_breaks
+    fn gen_loop_body(&mut self, continue_target: usize) -> Result<(), ParseError> {
+        self.loops.push(LoopContext {
+            continue_target,
+            break_fixups: Vec::new(),
+        });
+        self.gen_statement()
+    }
+
+    // Point every `break` of the innermost loop at the current offset and leave the loop
+    fn patch_breaks(&mut self) {
+        if let Some(context) = self.loops.pop() {
+            for at in context.break_fixups {
+                self.code.text[at] = self.code.text_offset as i32;
+            }
+        }
+    }
+
+    // Drop code emitted from `offset` onwards, along with any breaks and gotos in it
+    fn discard_code(&mut self, offset: usize) {
+        self.code.truncate(offset);
+        for context in self.loops.iter_mut() {
+            context.break_fixups.retain(|at| *at < offset);
+        }
+        self.goto_fixups.retain(|(at, _, _)| *at < offset);
+    }
+
+    fn gen_goto_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'goto'; the jump's target is filled in at the end of the function
+        self.lexer.next_token();
+        let span = self.lexer.token_span();
+        let label = match self.lexer.peek_token() {
+            Some(Token::Id(label)) => label,
+            _ => return Err(self.expected_error(vec![TokenKind::Identifier], "Expected label after 'goto'")),
+        };
+        self.lexer.next_token();
+        self.code.emit_imm(Opcode::JMP, 0);
+        self.goto_fixups.push((self.code.text_offset - 1, label, span));
+        self.expect_semi_after("goto")
+    }
+
+    fn gen_labeled_statement(&mut self, label: String) -> Result<(), ParseError> {
+        let span = self.lexer.token_span();
+        self.lexer.next_token(); // Consume the label
+        self.lexer.next_token(); // Consume ':'
+        if self.labels.insert(label.clone(), self.code.text_offset).is_some() {
+            return Err(self.error_at(format!("duplicate label '{}'", label), span));
+        }
+        self.gen_statement()
+    }
+
+    fn gen_break_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'break'
+        self.lexer.next_token();
+        self.code.emit_imm(Opcode::JMP, 0);
+        let at = self.code.text_offset - 1;
+        match self.loops.last_mut() {
+            Some(context) => context.break_fixups.push(at),
+            None => return Err("'break' outside of a loop".into()),
+        }
+        self.expect_semi_after("break")
+    }
+
+    fn gen_continue_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'continue'
+        self.lexer.next_token();
+        let target = match self.loops.last() {
+            Some(context) => context.continue_target,
+            None => return Err("'continue' outside of a loop".into()),
+        };
+        self.code.emit_imm(Opcode::JMP, target as i32);
+        self.expect_semi_after("continue")
+    }
+
+    fn expect_semi_after(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if let Some(Token::Semi) = self.lexer.peek_token() {
+            self.lexer.next_token();
+            Ok(())
         } else {
-            println!("CODEGEN DEBUG: Expected semicolon after return but found: {:?}", parser.lexer.peek_token());
-            return Err("Expected ';' after return statement".to_string());
+            Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], &format!("Expected ';' after '{}', found: {:?}", keyword, self.lexer.peek_token())))
+        }
+    }
+
+    fn gen_return_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'return'
+        self.lexer.next_token();
+
+        // The return value is left in ax; a void function returns with ax as it is
+        let is_void = self.return_type == Some(Type::Void);
+        if self.lexer.peek_token() != Some(Token::Semi) {
+            if is_void {
+                return Err(self.error_here("return with a value in function returning void"));
+            }
+            self.parse_expression()?;
+            self.check_value()?;
+            // A char function only returns the low byte, sign-extended for signed char
+            match self.return_type {
+                Some(Type::Char) => {
+                    self.code.emit(Opcode::PSH);
+                    self.code.emit_imm(Opcode::IMM, 0xff);
+                    self.code.emit(Opcode::AND);
+                }
+                Some(Type::SChar) => {
+                    for op in [Opcode::SHL, Opcode::SHR] {
+                        self.code.emit(Opcode::PSH);
+                        self.code.emit_imm(Opcode::IMM, 24);
+                        self.code.emit(op);
+                    }
+                }
+                _ => {}
+            }
+        } else if !is_void {
+            return Err(self.error_here("return with no value in function returning non-void"));
         }
-        println!("CODEGEN DEBUG: Emitting LEV for function epilogue");
-        self.emit(Opcode::LEV);
-        println!("CODEGEN DEBUG: Exiting gen_return_statement");
+
+        // Expect ';'
+        self.expect(Token::Semi, &format!("Expected ';' after return statement, found: {:?}", self.lexer.peek_token()))?;
+
+        self.code.emit(Opcode::LEV);
         Ok(())
     }
-    pub fn gen_compound_statement(&mut self, parser: &mut Parser) -> Result<(), String> {
-        let entry_token = parser.lexer.peek_token();
-        println!("DEBUG: [gen_compound_statement] ENTER: token = {:?}", entry_token);
-        if entry_token != Some(crate::lexer::Token::OpenBrace) {
-            println!("ERROR: gen_compound_statement called but token is not OpenBrace! Token: {:?}", entry_token);
-            return Err(format!("Expected '{{' at start of compound statement, got {:?}", entry_token));
-        }
-        parser.lexer.next_token(); // Consume '{'
-        println!("DEBUG: Entering gen_compound_statement");
-        // Consume '{'
-        parser.lexer.next_token();
+
+    pub fn gen_compound_statement(&mut self) -> Result<(), ParseError> {
+        // Expect '{'
+        self.expect(Token::OpenBrace, &format!("Expected '{{' at start of compound statement, got {:?}", self.lexer.peek_token()))?;
+
         // Enter a new scope
-        parser.symbol_table.enter_scope();
-        println!("DEBUG: Entered a new scope in gen_compound_statement");
-        // Reset local offset for this scope
-        let saved_local_offset = parser.local_offset;
-        parser.local_offset = 0;
+        self.symbol_table.enter_scope();
+
         // Generate code for declarations and statements
-        let mut stmt_count = 0;
-        while let Some(token) = parser.lexer.peek_token() {
-            println!("DEBUG: [gen_compound_statement] Statement #{}: token BEFORE = {:?}", stmt_count, token);
-            if token == crate::lexer::Token::CloseBrace {
+        let mut unreachable_from = None;
+        while let Some(token) = self.lexer.peek_token() {
+            if token == Token::CloseBrace {
                 break;
             }
-            // Local variable declaration
-            if matches!(token, crate::lexer::Token::Int | crate::lexer::Token::CharType) {
-                println!("DEBUG: Found local variable declaration in gen_compound_statement");
-                parser.parse_local_declaration()?;
-                if let Some(ref var_name) = parser.current_id {
-                    println!("DEBUG: Processed local variable '{}' with offset {}", var_name, parser.local_offset - parser.current_type.as_ref().unwrap().size());
-                    let size = parser.current_type.as_ref().unwrap().size() as i32;
-                    self.emit_imm(Opcode::ADJ, -size);
-                    println!("DEBUG: Allocated {} bytes on stack for local variable '{}'", size, var_name);
-                    if parser.current_value != 0 {
-                        println!("DEBUG: Initializing local variable '{}' with value {}", var_name, parser.current_value);
-                        self.emit_imm(Opcode::IMM, parser.current_value as i32);
-                        self.emit(Opcode::SI);
-                    }
-                }
+            // Local variable declaration; its stack slot is reserved by the function's ENT
+            let point = self.recovery_point();
+            let result = if token.is_type_specifier() {
+                self.parse_local_declaration()
             } else {
-                println!("DEBUG: [gen_compound_statement] Entering gen_statement for statement #{}", stmt_count);
-                self.gen_statement(parser)?;
-                println!("DEBUG: [gen_compound_statement] Exited gen_statement for statement #{}", stmt_count);
+                self.gen_statement()
+            };
+            if self.recover_from(result, point)? {
+                if self.lexer.peek_token() == Some(Token::Eof) {
+                    break;
+                }
+                continue;
             }
-            let after_token = parser.lexer.peek_token();
-            println!("DEBUG: [gen_compound_statement] Statement #{}: token AFTER = {:?}", stmt_count, after_token);
-            stmt_count += 1;
-        }
-        parser.local_offset = saved_local_offset;
-        parser.symbol_table.exit_scope();
-        println!("DEBUG: Exited scope in gen_compound_statement");
-        if let Some(crate::lexer::Token::CloseBrace) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
-            println!("DEBUG: Consumed closing brace in gen_compound_statement");
+
+            // Everything after an unconditional jump out of the block is still parsed,
+            // but its code could never run, so drop it. A label makes it reachable again
+            if let Some(dead) = unreachable_from {
+                if self.labels.values().any(|&at| at >= dead) {
+                    unreachable_from = None;
+                } else {
+                    self.discard_code(dead);
+                }
+            } else if matches!(token, Token::Return | Token::Break | Token::Continue | Token::Goto) {
+                unreachable_from = Some(self.code.text_offset);
+            }
+        }
+
+        self.symbol_table.exit_scope();
+
+        if let Some(Token::CloseBrace) = self.lexer.peek_token() {
+            self.lexer.next_token();
             Ok(())
         } else {
-            Err("Expected '}' at end of compound statement".to_string())
+            Err(self.expected_error(vec![TokenKind::Token(Token::CloseBrace)], "Expected '}' at end of compound statement"))
         }
     }
-    fn gen_expression_statement(&mut self, parser: &mut Parser) -> Result<(), String> {
-        println!("CODEGEN DEBUG: Entering gen_expression_statement");
 
+    fn gen_expression_statement(&mut self) -> Result<(), ParseError> {
         // Empty statement (just a semicolon)
-        if let Some(crate::lexer::Token::Semi) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
+        if let Some(Token::Semi) = self.lexer.peek_token() {
+            self.lexer.next_token();
             return Ok(());
         }
 
-        // Generate code for the full expression
-        self.gen_expression(parser)?;
+        // Generate code for the full expression; its value is left in ax
+        self.parse_expression()?;
 
         // Expect ';'
-        if let Some(crate::lexer::Token::Semi) = parser.lexer.peek_token() {
-            parser.lexer.next_token();
-            // Only ADJ if not a function or system call (result unused)
-            match parser.current_class {
-                Some(crate::parser::symbol_table::Class::Function) | Some(crate::parser::symbol_table::Class::Sys) => {},
-                _ => self.emit_imm(Opcode::ADJ, 1),
-            }
+        if let Some(Token::Semi) = self.lexer.peek_token() {
+            self.lexer.next_token();
             Ok(())
         } else {
-            Err("Expected ';' after expression statement".to_string())
+            Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], &format!("Expected ';' after expression statement, found: {:?}", self.lexer.peek_token())))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let code = vec![Opcode::IMM as i32, 5, Opcode::PSH as i32, Opcode::EXIT as i32];
+        assert_eq!(disassemble(&code), "   0: IMM 5\n   2: PSH\n   3: EXIT\n");
+        assert_eq!(disassemble(&[Opcode::LNOT as i32]), "   0: LNOT\n");
+        assert_eq!(assemble("imm 0\nlnot").unwrap(), [Opcode::IMM as i32, 0, Opcode::LNOT as i32]);
+    }
+
+    #[test]
+    fn test_assemble_labeled_loop() {
+        // Sum 1..=4 using a local counter and total
+        let code = assemble(
+            "
+                ent 2
+                lea -1          ; counter = 4
+                psh
+                imm 4
+                si
+            loop:
+                lea -1
+                li
+                bz end
+                lea -2          ; total += counter
+                psh
+                li
+                psh
+                lea -1
+                li
+                add
+                si
+                lea -1          ; counter -= 1
+                psh
+                li
+                psh
+                imm 1
+                sub
+                si
+                jmp loop
+            end: lea -2
+                li
+                lev
+            ",
+        ).unwrap();
+        assert_eq!(code[..2], [Opcode::ENT as i32, 2]);
+        let mut vm = crate::vm::VM::new(code, Vec::new(), 1024, false);
+        assert_eq!(vm.run(), Ok(10));
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(assemble("IMM"), Err("line 1: IMM needs an operand".to_string()));
+        assert_eq!(assemble("PSH\nFOO"), Err("line 2: unknown instruction 'FOO'".to_string()));
+        assert_eq!(assemble("JMP nowhere"), Err("line 1: undefined label 'nowhere'".to_string()));
+        assert_eq!(assemble("ADD 3"), Err("line 1: ADD takes no operand, found '3'".to_string()));
+    }
+}
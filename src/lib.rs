@@ -0,0 +1,175 @@
+pub mod lexer;
+pub mod parser;
+pub mod codegen;
+pub mod vm;
+
+use std::fmt;
+
+use codegen::{Opcode, Program};
+use parser::symbol_table::{Class, SymbolTable};
+use parser::{ParseError, Parser};
+use vm::{VmConfig, VM, WORD};
+
+// Description of the target VM, for tools that generate or inspect its code
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineInfo {
+    pub version: &'static str,
+    pub word_bits: usize,                // Width of a stack cell, int and pointer
+    pub opcodes: Vec<Opcode>,            // Every instruction, in encoding order
+    pub syscalls: Vec<(String, Opcode)>, // Builtin functions by name, with the opcode each calls
+}
+
+// Describe the VM this compiler targets
+pub fn machine_info() -> MachineInfo {
+    let mut symbols = SymbolTable::new();
+    symbols.init_builtins();
+    let mut syscalls: Vec<_> = symbols.all_symbols()
+        .filter(|(_, symbol)| symbol.class == Class::Sys)
+        .filter_map(|(name, _)| Opcode::for_sys_func(name).map(|op| (name.clone(), op)))
+        .collect();
+    syscalls.sort_by(|a, b| a.0.cmp(&b.0));
+    MachineInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        word_bits: WORD * 8,
+        opcodes: Opcode::ALL.to_vec(),
+        syscalls,
+    }
+}
+
+// One `key: value` line per property; opcodes and syscalls follow their count, one per line
+impl fmt::Display for MachineInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        writeln!(f, "word-bits: {}", self.word_bits)?;
+        writeln!(f, "opcodes: {}", self.opcodes.len())?;
+        for op in &self.opcodes {
+            writeln!(f, "  {:<4} {}", format!("{:?}", op), *op as i32)?;
+        }
+        writeln!(f, "syscalls: {}", self.syscalls.len())?;
+        for (name, op) in &self.syscalls {
+            writeln!(f, "  {:<8} {:?}", name, op)?;
+        }
+        Ok(())
+    }
+}
+
+// Evaluate a single C expression, such as "2 + 3 * 4", by compiling and running it as the
+// value main returns
+pub fn eval(src: &str) -> Result<i64, String> {
+    let source = format!("int main() {{ return ({}); }}", src);
+    compile_and_run(source.as_bytes(), false).map(i64::from)
+}
+
+// Compile C code, reporting every error found rather than only the first; the program is
+// only returned when there were none
+pub fn compile_with_diagnostics(source: &[u8]) -> (Option<Program>, Vec<ParseError>) {
+    Parser::new(source).compile_with_diagnostics()
+}
+
+// Function to compile and run C code directly
+pub fn compile_and_run(source: &[u8], debug_mode: bool) -> Result<i32, String> {
+    compile_and_run_with(source, &VmConfig { debug_mode, ..VmConfig::default() })
+}
+
+// Compile and run C code with explicit VM settings
+pub fn compile_and_run_with(source: &[u8], config: &VmConfig) -> Result<i32, String> {
+    let debug_mode = config.debug_mode;
+
+    // Create parser
+    let mut parser = Parser::new(source);
+    parser.bounds_checks = config.bounds_checks;
+
+    // Parse source code and get code and data segments with their line table
+    let program = parser.compile().map_err(|err| err.to_string())?;
+
+    if debug_mode {
+        println!("DEBUG: Generated code size: {} instructions", program.text.len());
+        println!("DEBUG: Generated data size: {} bytes", program.data.len());
+        if !program.data.is_empty() {
+            println!("DEBUG: First 10 bytes of data segment: {:?}", &program.data[0..std::cmp::min(10, program.data.len())]);
+        }
+    }
+
+    // Create VM
+    let mut vm = VM::from_program(program, config);
+
+    // Run VM
+    vm.run().map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hello_world() {
+        let source = r#"
+            int main() {
+                printf("Hello, World!\n");
+                return 0;
+            }
+        "#;
+
+        let result = compile_and_run(source.as_bytes(), true);
+        if let Err(e) = &result {
+            eprintln!("compile_and_run error: {}", e);
+        }
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_factorial() {
+        let source = r#"
+            int factorial(int n) {
+                if (n <= 1) return 1;
+                return n * factorial(n - 1);
+            }
+
+            int main() {
+                printf("Factorial of 5: %d\n", factorial(5));
+                return 0;
+            }
+        "#;
+
+        let result = compile_and_run(source.as_bytes(), true);
+        if let Err(e) = &result {
+            eprintln!("compile_and_run error: {}", e);
+        }
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("2+3*4"), Ok(14));
+        assert_eq!(eval("(1+2)*3"), Ok(9));
+        assert_eq!(eval("-7 / 2 + sizeof(int)"), Ok(1));
+        assert_eq!(eval("'a' == 97 ? 1 << 4 : 0"), Ok(16));
+        assert!(eval("1 +").is_err());
+        assert_eq!(eval("1 / 0"), Err("Division by zero (line 1)".to_string()));
+    }
+
+    #[test]
+    fn test_machine_info() {
+        let info = machine_info();
+        assert_eq!(info.word_bits, 32);
+        assert_eq!(info.opcodes.len(), codegen::NUM_OPCODES);
+        assert!(info.syscalls.contains(&("printf".to_string(), Opcode::PRTF)));
+        assert!(info.syscalls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn test_address_of_local() {
+        let source = r#"
+            int main() {
+                int x = 7;
+                int *p = &x;
+                return *p;
+            }
+        "#;
+
+        let result = compile_and_run(source.as_bytes(), false);
+        assert_eq!(result, Ok(7));
+    }
+}
@@ -1,171 +1,147 @@
-mod lexer;
-mod parser;
-mod codegen;
-mod vm;
-
-use std::env;
-use std::fs;
-// No need for std::io import
-use std::process;
-
-use parser::Parser;
-use vm::VM;
-
-fn main() {
-    // Parse command line arguments
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 2 {
-        eprintln!("Usage: {} <source_file> [options]", args[0]);
-        eprintln!("Options:");
-        eprintln!("  -d    Debug mode (print VM instructions)");
-        process::exit(1);
-    }
-
-    let source_file = &args[1];
-    let debug_mode = args.iter().any(|arg| arg == "-d");
-
-    // Read source file
-    let source = match fs::read(source_file) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("Error reading file '{}': {}", source_file, err);
-            process::exit(1);
-        }
-    };
-
-    // Debug: Print the source code
-    if debug_mode {
-        println!("Source code:\n{}", String::from_utf8_lossy(&source));
-    }
-
-    // Debug: Tokenize the source code and print tokens
-    if debug_mode {
-        println!("\nTokens:");
-        let mut lexer = crate::lexer::Lexer::new(&source);
-        lexer.next_token();
-        while let Some(token) = lexer.peek_token() {
-            if token == crate::lexer::Token::Eof {
-                println!("  Token::Eof");
-                break;
-            }
-            println!("  {:?}", token);
-            lexer.next_token();
-        }
-        println!();
-    }
-
-    // Create parser
-    let mut parser = Parser::new(&source);
-
-    // Parse source code and get code and data segments
-    let (code, data) = match parser.parse() {
-        Ok((code, data)) => (code, data),
-        Err(err) => {
-            eprintln!("Compilation error: {}", err);
-            process::exit(1);
-        }
-    };
-
-    if debug_mode {
-        println!("DEBUG: Generated code size: {} instructions", code.len());
-        println!("DEBUG: Generated data size: {} bytes", data.len());
-        if !data.is_empty() {
-            println!("DEBUG: First 10 bytes of data segment: {:?}", &data[0..std::cmp::min(10, data.len())]);
-        }
-    }
-
-    // Create VM
-    let mut vm = VM::new(
-        code,
-        data,               // Use the data segment from the code generator
-        1024 * 1024,        // 1MB stack
-        debug_mode,
-    );
-
-    // Run VM
-    match vm.run() {
-        Ok(exit_code) => {
-            if debug_mode {
-                println!("Program exited with code: {}", exit_code);
-            }
-            process::exit(exit_code as i32);
-        }
-        Err(err) => {
-            eprintln!("Runtime error: {}", err);
-            process::exit(1);
-        }
-    }
-}
-
-// Function to compile and run C code directly
-pub fn compile_and_run(source: &[u8], debug_mode: bool) -> Result<i32, String> {
-    // Create parser
-    let mut parser = Parser::new(source);
-
-    // Parse source code and get code and data segments
-    let (code, data) = parser.parse()?;
-
-    if debug_mode {
-        println!("DEBUG: Generated code size: {} instructions", code.len());
-        println!("DEBUG: Generated data size: {} bytes", data.len());
-        if !data.is_empty() {
-            println!("DEBUG: First 10 bytes of data segment: {:?}", &data[0..std::cmp::min(10, data.len())]);
-        }
-    }
-
-    // Create VM
-    let mut vm = VM::new(
-        code,
-        data,               // Use the data segment from the code generator
-        1024 * 1024,        // 1MB stack
-        debug_mode,
-    );
-
-    // Run VM
-    vm.run()
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_hello_world() {
-        let source = r#"
-            int main() {
-                printf("Hello, World!\n");
-                return 0;
-            }
-        "#;
-
-        let result = compile_and_run(source.as_bytes(), true);
-        if let Err(e) = &result {
-            eprintln!("compile_and_run error: {}", e);
-        }
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
-    }
-
-    #[test]
-    fn test_factorial() {
-        let source = r#"
-            int factorial(int n) {
-                if (n <= 1) return 1;
-                return n * factorial(n - 1);
-            }
-
-            int main() {
-                printf("Factorial of 5: %d\n", factorial(5));
-                return 0;
-            }
-        "#;
-
-        let result = compile_and_run(source.as_bytes(), true);
-        if let Err(e) = &result {
-            eprintln!("compile_and_run error: {}", e);
-        }
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
-    }
-}
+use std::env;
+use std::fs;
+// No need for std::io import
+use std::process;
+
+use c4_rust::lexer::{Lexer, Token};
+use c4_rust::parser::Parser;
+use c4_rust::vm::{VmConfig, VM};
+
+fn main() {
+    // Parse command line arguments
+    let args: Vec<String> = env::args().collect();
+
+    // Describing the target needs no source file
+    if args.iter().any(|arg| arg == "--target-info") {
+        print!("{}", c4_rust::machine_info());
+        process::exit(0);
+    }
+
+    // `-o <file>` saves the compiled image instead of running it
+    let output_at = args.iter().position(|arg| arg == "-o");
+    let output = match output_at.map(|at| args.get(at + 1)) {
+        Some(Some(file)) => Some(file),
+        Some(None) => {
+            eprintln!("Option -o needs a file name");
+            process::exit(1);
+        }
+        None => None,
+    };
+
+    // The first argument that is not an option or an option's value names the source file
+    let source_file = match args.iter().enumerate().skip(1)
+        .find(|&(at, arg)| !arg.starts_with('-') && output_at.is_none_or(|o| at != o + 1))
+        .map(|(_, arg)| arg)
+    {
+        Some(file) => file,
+        None => {
+            eprintln!("Usage: {} <source_file> [options]", args[0]);
+            eprintln!("Options:");
+            eprintln!("  -d         Debug mode (print VM instructions)");
+            eprintln!("  --check    Compile only and report whether it succeeded (alias: --no-run)");
+            eprintln!("  -o <file>  Save the compiled image to <file> instead of running it");
+            eprintln!("  --dump-symbols  Print the symbol table to stderr after compiling");
+            eprintln!("  --target-info   Print the version, word size, opcodes and syscalls of the VM");
+            eprintln!("Files ending in .c4b are run as compiled images.");
+            process::exit(1);
+        }
+    };
+    let debug_mode = args.iter().any(|arg| arg == "-d");
+    let check_only = args.iter().any(|arg| arg == "--check" || arg == "--no-run");
+    let config = VmConfig { debug_mode, ..VmConfig::default() };
+
+    // Precompiled images skip the compiler entirely
+    if source_file.ends_with(".c4b") {
+        match VM::from_image(source_file, &config) {
+            Ok(vm) => run(vm, debug_mode),
+            Err(err) => {
+                eprintln!("Error loading image: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Read source file
+    let source = match fs::read(source_file) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("Error reading file '{}': {}", source_file, err);
+            process::exit(1);
+        }
+    };
+
+    // Debug: Print the source code
+    if debug_mode {
+        println!("Source code:\n{}", String::from_utf8_lossy(&source));
+    }
+
+    // Debug: Tokenize the source code and print tokens
+    if debug_mode {
+        println!("\nTokens:");
+        let mut lexer = Lexer::new(&source);
+        lexer.next_token();
+        while let Some(token) = lexer.peek_token() {
+            if token == Token::Eof {
+                println!("  Token::Eof");
+                break;
+            }
+            println!("  {:?}", token);
+            lexer.next_token();
+        }
+        println!();
+    }
+
+    // Create parser
+    let mut parser = Parser::new(&source);
+    parser.dump_symbols = args.iter().any(|arg| arg == "--dump-symbols");
+
+    // Parse source code and get code and data segments
+    let program = match parser.compile() {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("Compilation error: {}", err);
+            process::exit(1);
+        }
+    };
+
+    if check_only {
+        println!("OK");
+        process::exit(0);
+    }
+
+    if let Some(output) = output {
+        if let Err(err) = fs::write(output, program.to_image()) {
+            eprintln!("Error writing image '{}': {}", output, err);
+            process::exit(1);
+        }
+        process::exit(0);
+    }
+
+    if debug_mode {
+        println!("DEBUG: Generated code size: {} instructions", program.text.len());
+        println!("DEBUG: Generated data size: {} bytes", program.data.len());
+        if !program.data.is_empty() {
+            println!("DEBUG: First 10 bytes of data segment: {:?}", &program.data[0..std::cmp::min(10, program.data.len())]);
+        }
+    }
+
+    // Create VM
+    run(VM::from_program(program, &config), debug_mode);
+}
+
+// Run the program and exit with its status
+fn run(mut vm: VM, debug_mode: bool) -> ! {
+    match vm.run() {
+        Ok(exit_code) => {
+            if debug_mode {
+                println!("Program exited with code: {}", exit_code);
+            }
+            // Like a C program's exit status, only the low 8 bits reach the shell
+            process::exit(exit_code & 0xff);
+        }
+        Err(err) => {
+            eprintln!("Runtime error: {}", err);
+            process::exit(1);
+        }
+    }
+}
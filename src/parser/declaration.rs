@@ -1,233 +1,565 @@
-use crate::lexer::Token;
-use super::{Parser, symbol_table::{Symbol, Class}, types::Type};
+use crate::lexer::{Token, TokenKind};
+use super::{ParseError, Parser, symbol_table::{Symbol, Class}, types::{StructDef, Type, MAX_OBJECT_SIZE}};
 
 impl<'a> Parser<'a> {
-    pub fn parse_global_declaration(&mut self) -> Result<(), String> {
-        println!("DEBUG: Parsing global declaration, current token: {:?}", self.lexer.peek_token());
-
-        // In the second pass, we might start with an identifier
-        if let Some(Token::Id(id)) = self.lexer.peek_token() {
-            println!("DEBUG: Found identifier: {}", id);
-            self.current_id = Some(id.clone());
-            self.lexer.next_token(); // Consume identifier
-
-            // If this is a known function, set its type from the symbol table
-            if let Some(symbol) = self.symbol_table.lookup(&id) {
-                self.current_type = Some(symbol.typ.clone());
-                self.current_class = Some(symbol.class.clone());
+    pub fn parse_global_declaration(&mut self) -> Result<(), ParseError> {
+        // Only declarations may appear at the top level; anything else (such as a stray
+        // closing brace) is reported here rather than as a missing type
+        match self.lexer.peek_token() {
+            Some(token) if token.is_type_specifier() || matches!(token, Token::Static | Token::Extern) => {}
+            Some(token) => return Err(format!("unexpected token at top level: {:?}", token).into()),
+            None => return Err("unexpected end of input at top level".into()),
+        }
 
-                // Function declaration/definition
-                if let Some(Token::OpenParen) = self.lexer.peek_token() {
-                    self.parse_function_declaration()?;
-                } else {
-                    // Global variable declaration
-                    self.parse_global_variable()?;
-                }
-                return Ok(());
-            }
+        // Storage-class qualifiers: with a single file, static changes nothing, while
+        // extern declares a variable without defining it
+        let mut is_extern = false;
+        while let Some(token @ (Token::Static | Token::Extern)) = self.lexer.peek_token() {
+            is_extern |= token == Token::Extern;
+            self.lexer.next_token();
         }
 
-        // If we didn't find an identifier or it wasn't in the symbol table,
-        // parse as a new declaration
         self.parse_type()?;
-		
-        println!("DEBUG: After parse_type, current token: {:?}", self.lexer.peek_token());
+
+        // A struct declaration on its own, such as `struct point { int x; int y; };`
+        if matches!(self.current_type, Some(Type::Struct(_))) && self.lexer.peek_token() == Some(Token::Semi) {
+            self.lexer.next_token();
+            return Ok(());
+        }
 
         // Parse declarator
-        if let Some(Token::Id(id)) = self.lexer.peek_token() {
-            println!("DEBUG: Found identifier: {}", id);
+        let span = self.lexer.token_span();
+        let id = if let Some(Token::Id(id)) = self.lexer.peek_token() {
             self.current_id = Some(id.clone());
             self.lexer.next_token(); // Consume identifier
-
-            // Create symbol for function or variable
-            let symbol = Symbol {
-                name: id.clone(),
-                class: Class::Function, // We'll update this if it's not a function
-                typ: self.current_type.clone().unwrap(),
-                val: 0,
-                offset: 0,
-            };
-
-            // Add to symbol table
-            self.symbol_table.add_symbol(symbol)?;
+            id
+        } else if let Some(Token::OpenParen) = self.lexer.peek_token() {
+            // A function pointer variable
+            let (id, span) = self.parse_function_pointer_declarator()?;
+            self.current_id = Some(id.clone());
+            self.current_class = Some(Class::Global);
+            return self.parse_global_variable(span, is_extern);
         } else {
-            println!("DEBUG: Expected identifier but found: {:?}", self.lexer.peek_token());
-            return Err("Expected identifier in declaration".to_string());
-        }
+            return Err(self.expected_error(vec![TokenKind::Identifier], "Expected identifier in declaration"));
+        };
+
+        // A program's own function or global variable replaces a builtin of the same name
+        let builtin = match self.symbol_table.lookup_current_scope(&id) {
+            Some(symbol) if !self.second_pass && symbol.class == Class::Sys => self.symbol_table.remove_global(&id),
+            _ => None,
+        };
 
         // Function declaration/definition
         if let Some(Token::OpenParen) = self.lexer.peek_token() {
-            self.parse_function_declaration()?;
+            // Functions are registered in the first pass so calls can precede the definition
+            if !self.second_pass && self.symbol_table.lookup_current_scope(&id).is_none() {
+                let ret = self.current_type.clone().ok_or("Missing return type")?;
+                if let Type::Struct(_) = ret {
+                    return Err(self.error_at(format!("function '{}' cannot return a struct by value", id), span));
+                }
+                let symbol = Symbol {
+                    name: id.clone(),
+                    class: Class::Function,
+                    // The parameters are filled in once the parameter list has been parsed
+                    typ: Type::Func { ret: Box::new(ret), params: Vec::new(), variadic: false },
+                    val: 0, // Entry point, set when the body is generated
+                    offset: 0,
+                    span,
+                    is_const: false,
+                };
+                self.symbol_table.add_symbol(symbol)?;
+            }
+            self.current_class = Some(Class::Function);
+            let defined = self.parse_function_declaration(&id)?;
+
+            // Declaring a builtin, as in `int abs(int n);`, keeps it
+            if let Some(builtin) = builtin.filter(|_| !defined) {
+                self.symbol_table.remove_global(&id);
+                self.symbol_table.add_symbol(builtin)?;
+            }
         } else {
             // Global variable declaration
-            self.parse_global_variable()?;
+            self.current_class = Some(Class::Global);
+            self.parse_array_declarator()?;
+            self.parse_global_variable(span, is_extern)?;
         }
 
         Ok(())
     }
 
-    pub fn parse_type(&mut self) -> Result<(), String> {
-        println!("DEBUG: Parsing type, current token: {:?}", self.lexer.peek_token());
+    pub fn parse_type(&mut self) -> Result<(), ParseError> {
+        // `const` may come before or after the base type; either way it applies to the
+        // variable only when no `*` follows, since `const char *s` is a pointer to const
+        self.current_const = self.skip_const();
         if let Some(token) = self.lexer.peek_token() {
             match token {
                 Token::Int => {
-                    println!("DEBUG: Found Int type");
                     self.current_type = Some(Type::Int);
                     self.lexer.next_token();
                 }
                 Token::CharType => {
-                    println!("DEBUG: Found Char type");
                     self.current_type = Some(Type::Char);
                     self.lexer.next_token();
                 }
+                Token::Void => {
+                    self.current_type = Some(Type::Void);
+                    self.lexer.next_token();
+                }
+                Token::Struct => {
+                    self.lexer.next_token();
+                    self.current_type = Some(self.parse_struct_specifier()?);
+                }
+                Token::Signed | Token::Unsigned => {
+                    // Only char comes in both flavours; plain char loads like unsigned char
+                    let signed = token == Token::Signed;
+                    self.lexer.next_token();
+                    self.current_type = Some(match self.lexer.peek_token() {
+                        Some(Token::CharType) => {
+                            self.lexer.next_token();
+                            if signed { Type::SChar } else { Type::Char }
+                        }
+                        Some(Token::Int) if signed => {
+                            self.lexer.next_token();
+                            Type::Int
+                        }
+                        _ if signed => Type::Int,
+                        _ => return Err(self.error_here("unsigned int is not supported")),
+                    });
+                }
                 _ => {
-                    println!("DEBUG: Expected type specifier but found: {:?}", token);
-                    return Err(format!("Expected type specifier, found: {:?}", token));
+                    return Err(self.expected_error(vec![TokenKind::TypeSpecifier], &format!("Expected type specifier, found: {:?}", token)));
                 }
             }
+            self.current_const |= self.skip_const();
 
-            // Handle pointer types
+            // Handle pointer types; `* const` makes the pointer itself const
             while let Some(Token::Mul) = self.lexer.peek_token() {
-                println!("DEBUG: Found pointer type");
                 if let Some(typ) = self.current_type.take() {
                     self.current_type = Some(Type::Ptr(Box::new(typ)));
                 }
                 self.lexer.next_token();
+                self.current_const = self.skip_const();
             }
 
-            println!("DEBUG: Finished parsing type, current token: {:?}", self.lexer.peek_token());
             Ok(())
         } else {
-            println!("DEBUG: Unexpected end of input while parsing type");
-            Err("Unexpected end of input while parsing type".to_string())
+            Err("Unexpected end of input while parsing type".into())
         }
     }
 
-    fn parse_global_variable(&mut self) -> Result<(), String> {
-        // Create symbol for global variable
-        let symbol = Symbol {
-            name: self.current_id.clone().unwrap(),
-            class: Class::Global,
-            typ: self.current_type.clone().unwrap(),
-            val: 0, // Will be set to the address in data section
-            offset: 0,
-        };
+    // Consume any `const` qualifiers, returning whether there were some
+    fn skip_const(&mut self) -> bool {
+        let mut found = false;
+        while self.lexer.peek_token() == Some(Token::Const) {
+            self.lexer.next_token();
+            found = true;
+        }
+        found
+    }
 
-        // Add to symbol table
-        self.symbol_table.add_symbol(symbol)?;
+    // Parse what follows the `struct` keyword: a tag, optionally followed by the definition
+    // of its members. Tags share one namespace across the whole program
+    fn parse_struct_specifier(&mut self) -> Result<Type, ParseError> {
+        let span = self.lexer.token_span();
+        let name = match self.lexer.peek_token() {
+            Some(Token::Id(name)) => name,
+            _ => return Err(self.expected_error(vec![TokenKind::Identifier], "Expected struct tag")),
+        };
+        self.lexer.next_token();
+        let def = self.structs.entry(name.clone()).or_insert_with(|| StructDef::new(&name)).clone();
+        if self.lexer.peek_token() != Some(Token::OpenBrace) {
+            return Ok(Type::Struct(def));
+        }
+        self.lexer.next_token(); // Consume '{'
 
-        // Handle initialization if present
-        if let Some(Token::Assign) = self.lexer.peek_token() {
-            self.lexer.next_token(); // Consume '='
+        // Both passes read the definition, but only the first one lays it out
+        if def.is_complete() && !self.second_pass {
+            return Err(self.error_at(format!("redefinition of 'struct {}'", name), span));
+        }
 
-            // Parse initializer expression
-            // TODO: Implement expression parsing
+        let mut members: Vec<(String, Type)> = Vec::new();
+        while self.lexer.peek_token() != Some(Token::CloseBrace) {
+            self.parse_type()?;
+            let base = self.current_type.clone().ok_or("Missing member type")?;
+
+            // One or more declarators sharing the type, e.g. `int x, y[2];`
+            loop {
+                self.current_type = Some(base.clone());
+                let (member, span) = match self.lexer.peek_token() {
+                    Some(Token::Id(member)) => {
+                        let span = self.lexer.token_span();
+                        self.lexer.next_token();
+                        self.parse_array_declarator()?;
+                        (member, span)
+                    }
+                    Some(Token::OpenParen) => self.parse_function_pointer_declarator()?,
+                    _ => return Err(self.expected_error(vec![TokenKind::Identifier], "Expected member name")),
+                };
+                let typ = self.current_type.clone().ok_or("Missing member type")?;
+                if !typ.is_complete() {
+                    return Err(self.error_at(format!("member '{}' has incomplete type '{}'", member, typ), span));
+                }
+                if members.iter().any(|(existing, _)| *existing == member) {
+                    return Err(self.error_at(format!("duplicate member '{}' in 'struct {}'", member, name), span));
+                }
+                members.push((member, typ));
 
-            // For now, just skip until semicolon
-            while let Some(token) = self.lexer.peek_token() {
-                if token == Token::Semi {
+                if let Some(Token::Comma) = self.lexer.peek_token() {
+                    self.lexer.next_token();
+                } else {
                     break;
                 }
-                self.lexer.next_token();
             }
+            if self.lexer.peek_token() == Some(Token::Colon) {
+                return Err(self.error_here("bit-fields are not supported"));
+            }
+            self.expect(Token::Semi, "Expected ';' after struct member")?;
         }
+        self.lexer.next_token(); // Consume '}'
 
-        // Expect semicolon
-        if let Some(Token::Semi) = self.lexer.peek_token() {
+        if members.is_empty() {
+            return Err(self.error_at(format!("'struct {}' has no members", name), span));
+        }
+        if !self.second_pass {
+            def.define(members).map_err(|message| self.error_at(message, span))?;
+        }
+        Ok(Type::Struct(def))
+    }
+
+    // Parse optional `[size]` suffixes after a variable name, turning current_type into an
+    // array; `int m[2][3]` is an array of two arrays of three ints
+    pub fn parse_array_declarator(&mut self) -> Result<(), ParseError> {
+        // Evaluating the sizes overwrites current_type, so hold on to the element type
+        let elem_type = self.current_type.clone();
+        let mut dims = Vec::new();
+        while let Some(Token::Brak) = self.lexer.peek_token() {
+            self.lexer.next_token(); // Consume '['
+
+            // The size can be any constant expression
+            let len = self.parse_expression_value()
+                .map_err(|e| format!("Invalid array size: {}", e))?;
+            if len <= 0 {
+                return Err(format!("Array size must be positive, got {}", len).into());
+            }
+
+            self.expect(Token::CloseBrak, "Expected ']' after array size")?;
+            dims.push(len as usize);
+        }
+
+        if !dims.is_empty() {
+            let mut typ = elem_type.ok_or("Missing array element type")?;
+            for len in dims.into_iter().rev() {
+                if typ.size() as i64 * len as i64 > MAX_OBJECT_SIZE {
+                    return Err(format!("array of {} elements of type '{}' is too large", len, typ).into());
+                }
+                typ = Type::Array(Box::new(typ), len);
+            }
+            self.current_type = Some(typ);
+        }
+        Ok(())
+    }
+
+    // Parse a function pointer declarator, `(*name)(parameter types)`, applying it to the
+    // return type in current_type. Returns the name and its source range
+    pub fn parse_function_pointer_declarator(&mut self) -> Result<(String, (usize, usize)), ParseError> {
+        let ret = self.current_type.clone().ok_or("Missing return type")?;
+        self.lexer.next_token(); // Consume '('
+        if let Some(Token::Mul) = self.lexer.peek_token() {
             self.lexer.next_token();
-            Ok(())
         } else {
-            Err("Expected ';' after variable declaration".to_string())
+            let expected = vec![TokenKind::Token(Token::Mul)];
+            return Err(self.error_here("Expected '*' in function pointer declarator").expecting(expected));
+        }
+
+        let span = self.lexer.token_span();
+        let name = match self.lexer.peek_token() {
+            Some(Token::Id(name)) => name,
+            _ => {
+                let expected = vec![TokenKind::Identifier];
+                return Err(self.error_here("Expected name in function pointer declarator").expecting(expected));
+            }
+        };
+        self.lexer.next_token();
+
+        for expected in [Token::CloseParen, Token::OpenParen] {
+            if self.lexer.peek_token() != Some(expected.clone()) {
+                let msg = format!("Expected {:?} in function pointer declarator", expected);
+                return Err(self.error_here(&msg).expecting(vec![TokenKind::Token(expected)]));
+            }
+            self.lexer.next_token();
+        }
+
+        // Parameter types, optionally named, up to ')'
+        let mut params = Vec::new();
+        let mut variadic = false;
+        while self.lexer.peek_token() != Some(Token::CloseParen) {
+            if !params.is_empty() && self.lexer.peek_token() == Some(Token::Ellipsis) {
+                self.lexer.next_token();
+                variadic = true;
+                if self.lexer.peek_token() != Some(Token::CloseParen) {
+                    let expected = vec![TokenKind::Token(Token::CloseParen)];
+                    return Err(self.error_here("Expected ')' after '...'").expecting(expected));
+                }
+                break;
+            }
+            self.parse_type()?;
+            let typ = self.current_type.clone().ok_or("Missing parameter type")?;
+            if let Some(Token::Id(_)) = self.lexer.peek_token() {
+                self.lexer.next_token();
+            }
+            // `(void)` declares that there are no parameters
+            if !(params.is_empty() && typ == Type::Void && self.lexer.peek_token() == Some(Token::CloseParen)) {
+                params.push(typ);
+            }
+            match self.lexer.peek_token() {
+                Some(Token::Comma) => self.lexer.next_token(),
+                Some(Token::CloseParen) => {}
+                _ => {
+                    let expected = vec![TokenKind::Token(Token::Comma), TokenKind::Token(Token::CloseParen)];
+                    return Err(self.error_here("Expected ',' or ')' in function pointer parameters").expecting(expected));
+                }
+            }
         }
+        self.lexer.next_token(); // Consume ')'
+
+        let func = Type::Func { ret: Box::new(ret), params, variadic };
+        self.current_type = Some(func.to_pointer());
+        Ok((name, span))
     }
 
-    fn parse_function_declaration(&mut self) -> Result<(), String> {
-        println!("DEBUG: Parsing function declaration, current token: {:?}", self.lexer.peek_token());
-        // Consume '('
-        if let Some(Token::OpenParen) = self.lexer.peek_token() {
+    // Parse an initializer for a variable of type `typ`, appending the value of every scalar
+    // it covers to `values`. Arrays take a braced list, nested for nested arrays; elements
+    // left out are zero
+    pub fn parse_initializer(&mut self, typ: &Type, values: &mut Vec<i64>) -> Result<(), ParseError> {
+        let (elem, len) = match typ {
+            Type::Array(elem, len) => (elem, *len),
+            Type::Struct(_) => return Err(self.error_here("struct initializers are not supported")),
+            _ => {
+                values.push(self.parse_expression_value()?);
+                return Ok(());
+            }
+        };
+
+        if let Some(Token::OpenBrace) = self.lexer.peek_token() {
             self.lexer.next_token();
         } else {
-            return Err("Expected '(' in function declaration".to_string());
+            let expected = vec![TokenKind::Token(Token::OpenBrace)];
+            return Err(self.error_here("Expected '{' to initialize an array").expecting(expected));
         }
 
-        // Parse parameter list
-        self.parse_parameter_list()?;
+        let start = values.len();
+        let mut count = 0;
+        while self.lexer.peek_token() != Some(Token::CloseBrace) {
+            if count == len {
+                return Err(self.error_here(&format!("Too many initializers for array of length {}", len)));
+            }
+            self.parse_initializer(elem, values)?;
+            count += 1;
 
-        // Consume ')'
-        if let Some(Token::CloseParen) = self.lexer.peek_token() {
+            // A trailing comma before '}' is allowed
+            if let Some(Token::Comma) = self.lexer.peek_token() {
+                self.lexer.next_token();
+            } else {
+                break;
+            }
+        }
+
+        if let Some(Token::CloseBrace) = self.lexer.peek_token() {
             self.lexer.next_token();
         } else {
-            return Err("Expected ')' after parameter list".to_string());
+            let expected = vec![TokenKind::Token(Token::CloseBrace)];
+            return Err(self.error_here("Expected '}' after initializer list").expecting(expected));
+        }
+
+        let scalars = (typ.size() / typ.scalar().size()) as usize;
+        values.resize(start + scalars, 0);
+        Ok(())
+    }
+
+    fn parse_global_variable(&mut self, span: (usize, usize), is_extern: bool) -> Result<(), ParseError> {
+        let name = self.current_id.clone().ok_or("Missing global variable name")?;
+        let typ = self.current_type.clone().ok_or("Missing global variable type")?;
+        if typ.scalar() == &Type::Void {
+            return Err(format!("variable '{}' declared void", name).into());
+        }
+        if !typ.is_complete() {
+            return Err(self.error_at(format!("variable '{}' has incomplete type '{}'", name, typ), span));
+        }
+
+        let mut addr = None;
+        if self.second_pass {
+            // Give the variable its storage in the data section at its first declaration,
+            // so that code in between can use it even if the definition comes later
+            let val = self.symbol_table.lookup(&name).map_or(-1, |symbol| symbol.val);
+            if val < 0 {
+                let at = self.code.allocate_data(typ.size() as usize)?;
+                self.symbol_table.update_symbol(&name, |symbol| symbol.val = at as i64)?;
+                addr = Some(at);
+            } else {
+                addr = Some(val as usize);
+            }
+        } else if let Some(existing) = self.symbol_table.lookup_current_scope(&name) {
+            // Declaring a global again is fine alongside extern declarations of the same type
+            let redeclaration = existing.class == Class::Global && existing.typ == typ
+                && (is_extern || self.extern_globals.contains(&name));
+            if !redeclaration {
+                return Err(format!("Symbol '{}' already defined in current scope", name).into());
+            }
+            if !is_extern {
+                self.extern_globals.remove(&name);
+            }
+        } else {
+            // Create symbol for global variable
+            let symbol = Symbol {
+                name: name.clone(),
+                class: Class::Global,
+                typ: typ.clone(),
+                val: -1, // Will be set to the address in data section
+                offset: 0,
+                span,
+                is_const: self.current_const,
+            };
+
+            // Add to symbol table
+            self.symbol_table.add_symbol(symbol)?;
+            if is_extern {
+                self.extern_globals.insert(name.clone());
+            }
         }
 
+        // Handle initialization if present
+        if let Some(Token::Assign) = self.lexer.peek_token() {
+            self.lexer.next_token(); // Consume '='
+            if is_extern {
+                return Err(format!("extern variable '{}' cannot have an initializer", name).into());
+            }
+
+            // Globals start out with constant values written into their storage
+            let mut values = Vec::new();
+            self.parse_initializer(&typ, &mut values)?;
+            if let Some(addr) = addr {
+                self.code.init_data(addr, typ.scalar().size() as usize, &values);
+            }
+        }
+
+        // Expect semicolon
+        if let Some(Token::Semi) = self.lexer.peek_token() {
+            self.lexer.next_token();
+            Ok(())
+        } else {
+            Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], "Expected ';' after variable declaration"))
+        }
+    }
+
+    // Returns whether the function was defined, i.e. had a body
+    fn parse_function_declaration(&mut self, name: &str) -> Result<bool, ParseError> {
+        // Consume '('
+        self.expect(Token::OpenParen, "Expected '(' in function declaration")?;
+
+        // Parameters and locals live in the function's own scope, which is removed
+        // again even when the function fails to parse
+        self.symbol_table.enter_scope();
+        let result = self.parse_function_rest(name);
+        self.symbol_table.exit_scope();
+        result
+    }
+
+    // Parse the parameter list and the body (or ';') of a function inside its scope
+    fn parse_function_rest(&mut self, name: &str) -> Result<bool, ParseError> {
+        // Parse parameter list and record it in the function's type
+        let (param_types, is_variadic) = self.parse_parameter_list()?;
+        self.symbol_table.update_global(name, |symbol| {
+            if let Type::Func { params, variadic, .. } = &mut symbol.typ {
+                *params = param_types;
+                *variadic = is_variadic;
+            }
+        })?;
+
+        // Consume ')'
+        self.expect(Token::CloseParen, "Expected ')' after parameter list")?;
+
         // Function definition (has a body)
-        println!("DEBUG: Checking for function body, current token: {:?}", self.lexer.peek_token());
         if let Some(Token::OpenBrace) = self.lexer.peek_token() {
-            // Enter new scope for function body
-            self.symbol_table.enter_scope();
+            // Parameters are found at fixed offsets above bp, which the extra arguments
+            // of a variadic call would shift
+            if is_variadic {
+                return Err(self.error_here(&format!("definition of variadic function '{}' is not supported", name)));
+            }
 
-            // Reset local offset for function parameters and local variables
+            // Reset local offset for the function's local variables
             self.local_offset = 0;
 
-            // Parse statements in the function body
-            println!("DEBUG: Parsing function body statements");
-
-            // Parse the compound statement
-            self.parse_compound_statement()?;
-
-            // Exit function scope
-            self.symbol_table.exit_scope();
+            if self.second_pass {
+                // Generate code for the function body
+                self.gen_function(name)?;
+            } else {
+                // Parse the compound statement
+                self.parse_compound_statement()?;
+            }
+            Ok(true)
         }
         // Function declaration (no body, just semicolon)
         else if let Some(Token::Semi) = self.lexer.peek_token() {
             self.lexer.next_token();
+            Ok(false)
         } else {
-            return Err("Expected '{' or ';' after function declaration".to_string());
+            let expected = vec![TokenKind::Token(Token::OpenBrace), TokenKind::Token(Token::Semi)];
+            Err(self.expected_error(expected, "Expected '{' or ';' after function declaration"))
         }
-
-        Ok(())
     }
 
-    fn parse_parameter_list(&mut self) -> Result<(), String> {
+    // Parse the parameters into the function's scope and return their types, and whether
+    // they end in `...`
+    fn parse_parameter_list(&mut self) -> Result<(Vec<Type>, bool), ParseError> {
+        let mut params = Vec::new();
+        let mut variadic = false;
+
         // Parse parameters until we hit ')'
         while let Some(token) = self.lexer.peek_token() {
             if token == Token::CloseParen {
                 break;
             }
 
-            // Parse parameter type
-            if let Some(Token::Id(id)) = self.lexer.peek_token() {
-                // In second pass, just consume the identifier
+            // `...` takes any further arguments and must come last
+            if token == Token::Ellipsis {
+                if params.is_empty() {
+                    let expected = vec![TokenKind::Identifier];
+                    return Err(self.error_here("Expected a named parameter before '...'").expecting(expected));
+                }
                 self.lexer.next_token();
-            } else {
-                // In first pass, parse the type
-                self.parse_type()?;
+                if self.lexer.peek_token() != Some(Token::CloseParen) {
+                    let expected = vec![TokenKind::Token(Token::CloseParen)];
+                    return Err(self.error_here("Expected ')' after '...'").expecting(expected));
+                }
+                variadic = true;
+                break;
+            }
 
-                // Parse parameter name
-                if let Some(Token::Id(id)) = self.lexer.peek_token() {
-                    let param_name = id.clone();
-                    self.lexer.next_token();
+            // Parse parameter type
+            self.parse_type()?;
 
-                    // Only add to symbol table in first pass
-                    if !self.second_pass {
-                        // Create symbol for parameter
-                        let symbol = Symbol {
-                            name: param_name,
-                            class: Class::Local,
-                            typ: self.current_type.clone().unwrap(),
-                            val: 0,
-                            offset: self.local_offset,
-                        };
-
-                        // Add parameter to symbol table
-                        self.symbol_table.add_symbol(symbol)?;
-
-                        // Update local offset for next parameter
-                        self.local_offset += self.current_type.as_ref().unwrap().size();
-                    }
-                } else {
-                    return Err("Expected parameter name".to_string());
+            // `(void)` declares that there are no parameters
+            if params.is_empty() && self.current_type == Some(Type::Void)
+                && self.lexer.peek_token() == Some(Token::CloseParen) {
+                break;
+            }
+
+            // Parse parameter name
+            if let Some(Token::OpenParen) = self.lexer.peek_token() {
+                let (id, span) = self.parse_function_pointer_declarator()?;
+                params.push((id, self.current_type.clone().ok_or("Missing parameter type")?, span));
+            } else if let Some(Token::Id(id)) = self.lexer.peek_token() {
+                let typ = self.current_type.clone().ok_or("Missing parameter type")?;
+                if typ == Type::Void {
+                    return Err(format!("parameter '{}' declared void", id).into());
+                }
+                if let Type::Struct(_) = typ {
+                    return Err(self.error_here(&format!("passing struct parameter '{}' by value is not supported", id)));
                 }
+                params.push((id, typ, self.lexer.token_span()));
+                self.lexer.next_token();
+            } else {
+                return Err(self.expected_error(vec![TokenKind::Identifier], "Expected parameter name"));
             }
 
             // Check for comma
@@ -236,12 +568,30 @@ impl<'a> Parser<'a> {
             } else if let Some(Token::CloseParen) = self.lexer.peek_token() {
                 break;
             } else {
-                return Err("Expected ',' or ')' in parameter list".to_string());
+                return Err(self.expected_error(vec![TokenKind::Token(Token::Comma), TokenKind::Token(Token::CloseParen)], "Expected ',' or ')' in parameter list"));
             }
         }
 
-        Ok(())
-    }
+        // Arguments are pushed left to right, so the first one ends up furthest above
+        // bp, past the saved bp and the return address
+        let count = params.len() as i32;
+        let types = params.iter().map(|(_, typ, _)| typ.clone()).collect();
+        for (i, (name, typ, span)) in params.into_iter().enumerate() {
+            let symbol = Symbol {
+                name,
+                class: Class::Local,
+                typ,
+                val: 0,
+                offset: count + 1 - i as i32,
+                span,
+                // const parameters are accepted but not enforced
+                is_const: false,
+            };
 
-    // These functions are already defined above, so we don't need to redefine them
+            // Add parameter to symbol table
+            self.symbol_table.add_symbol(symbol)?;
+        }
+
+        Ok((types, variadic))
+    }
 }
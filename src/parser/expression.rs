@@ -1,8 +1,9 @@
-use crate::lexer::Token;
-use super::{Parser, types::Type};
+use crate::codegen::Opcode;
+use crate::lexer::{Token, TokenKind};
+use super::{ParseError, Parser, symbol_table::{Class, Symbol}, types::Type};
 
 // Operator precedence levels
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum Precedence {
     Assignment = 1,  // =
     Conditional,     // ?:
@@ -17,476 +18,832 @@ enum Precedence {
     Additive,        // +, -
     Multiplicative,  // *, /, %
     Unary,           // !, ~, -, +, *, &, sizeof
-    Postfix,         // (), [], ->, ., ++, --
-    Primary,         // literals, identifiers, (expression)
 }
 
-
+// What a parsed expression evaluates to; its code leaves the value in ax
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprResult {
+    pub typ: Type,
+    pub class: Option<Class>, // Class of a lone identifier or call; None for any other value
+    pub value: i64,           // Value of a literal, or the symbol's value for an identifier
+    pub is_lvalue: bool,      // Loaded from an address that an assignment can store through
+}
 
 impl<'a> Parser<'a> {
-    // Entry point for expression parsing
-    pub fn parse_expression(&mut self) -> Result<(), String> {
-
-        println!("DEBUG: Entering parse_expression, current token: {:?}", self.lexer.peek_token());
-        // Save class before parsing
-        println!("DEBUG: [parse_expression] class BEFORE: {:?}", self.current_class);
-        let prev_class = self.current_class.clone();
-        // For both statements and conditions, stop at ';' or ')'
-        self.parse_expr_with_precedence(
-            Precedence::Assignment,
-            Some(&[Token::Semi, Token::CloseParen, Token::Comma]),
-        )?;
-        // If class is None but previous was Function or Sys, restore it
-        if self.current_class.is_none() {
-            if let Some(super::symbol_table::Class::Function) | Some(super::symbol_table::Class::Sys) = prev_class {
-                self.current_class = prev_class;
-            }
+    // Entry point for expression parsing; the generated code leaves the result in ax
+    pub fn parse_expression(&mut self) -> Result<ExprResult, ParseError> {
+        let start = self.lexer.token_start;
+        let result = self.parse_expr_with_precedence(Precedence::Assignment)?;
+        self.expr_span = (start, self.lexer.prev_end);
+        Ok(result)
+    }
+
+    // Describe the expression whose code was emitted last
+    fn expr_result(&self) -> ExprResult {
+        ExprResult {
+            typ: self.current_type.clone().unwrap_or(Type::Int),
+            class: self.current_class.clone(),
+            value: self.current_value,
+            // `?:`, `&&` and `||` can end with the load of their last operand, which is still
+            // only a value
+            is_lvalue: self.code.last_op().is_some_and(Opcode::is_load)
+                && self.operator_end != Some(self.code.text_offset),
+        }
+    }
+
+    // Parse an expression that must be a compile-time constant and return its value
+    pub fn parse_expression_value(&mut self) -> Result<i64, ParseError> {
+        let start = self.code.text_offset;
+        self.parse_expr_with_precedence(Precedence::Conditional)?;
+        let value = self.code.eval_constant(start);
+        self.code.truncate(start);
+        self.current_type = Some(Type::Int);
+        value.ok_or_else(|| "Expected a constant expression".into())
+    }
+
+    // Precedence climbing: binary operators bind while their level is at least `precedence`
+    fn parse_expr_with_precedence(&mut self, precedence: Precedence) -> Result<ExprResult, ParseError> {
+        self.nested("expression", |parser| {
+            // Parse the first operand
+            parser.parse_primary_expr()?;
+            parser.parse_binary_operators(precedence)
+        })
+    }
+
+    // Parse an expression whose value is used, which rules out calls to void functions
+    fn parse_operand(&mut self, precedence: Precedence) -> Result<ExprResult, ParseError> {
+        let result = self.parse_expr_with_precedence(precedence)?;
+        self.check_value()?;
+        Ok(result)
+    }
+
+    // Fail if the expression just parsed has no value
+    pub fn check_value(&self) -> Result<(), ParseError> {
+        match self.current_type {
+            Some(Type::Void) => Err("void value not ignored as it ought to be".into()),
+            // A struct stands for its address, which only member access and & may use
+            Some(Type::Struct(_)) => Err("used struct type value where scalar is required".into()),
+            _ => Ok(()),
+        }
+    }
+
+    // Check that a value of type `value` may be stored in a `target`; mixing integers and
+    // pointers is let through as C4 programs rely on it, but pointer targets must agree
+    pub fn check_assignable(&self, target: &Type, value: &Type) -> Result<(), ParseError> {
+        if target.is_pointer() && value.decay().is_pointer() && !target.is_compatible(value) {
+            return Err(format!("incompatible types: cannot assign {} to {}", value.decay(), target).into());
         }
-        println!("DEBUG: Finished parse_expression, current token: {:?}", self.lexer.peek_token());
-        println!("DEBUG: [parse_expression] current_class at end: {:?}", self.current_class);
         Ok(())
     }
 
-    // Precedence climbing algorithm with stop tokens
-    fn parse_expr_with_precedence(&mut self, precedence: Precedence, stop_tokens: Option<&[Token]>) -> Result<(), String> {
-        println!("DEBUG: [parse_expr_with_precedence] class at start: {:?}", self.current_class);
-        // Parse the first operand
-        self.parse_primary_expr(stop_tokens)?;
+    // Parse an if/while condition. A condition that is just `!operand` is left un-negated
+    // and reported as inverted, so the caller can branch with BNZ instead of BZ
+    pub fn parse_condition(&mut self) -> Result<bool, ParseError> {
+        if self.lexer.peek_token() != Some(Token::Not) {
+            self.parse_expression()?;
+            return Ok(false);
+        }
 
-        // Save current_class before operator parsing
-        let saved_class = self.current_class.clone();
-        // Keep processing operators while their precedence is high enough
-        while let Some(token) = self.lexer.peek_token() {
-            // Stop if we hit a stop token
-            if let Some(stops) = stop_tokens {
-                if stops.iter().any(|t| t == &token) {
-                    break;
-                }
-            }
+        self.lexer.next_token(); // Consume '!'
+        self.parse_expr_with_precedence(Precedence::Unary)?;
+        if self.lexer.peek_token() == Some(Token::CloseParen) {
+            self.current_type = Some(Type::Int);
+            return Ok(true);
+        }
+
+        // The negation is only part of a larger condition
+        self.emit_logical_not();
+        self.parse_binary_operators(Precedence::Assignment)?;
+        Ok(false)
+    }
 
-            // Get the precedence of the next token
-            let token_precedence = self.get_token_precedence(&token);
+    // Replace ax with 1 if it is zero and 0 otherwise
+    fn emit_logical_not(&mut self) {
+        self.code.emit(Opcode::LNOT);
+        self.current_type = Some(Type::Int);
+        self.current_class = None;
+    }
 
-            // If the next token is not an operator or has lower precedence, we're done
-            if token_precedence < precedence {
-                break;
-            }
-            // If the token is not an operator at all, also break (do NOT error)
-            if token_precedence == Precedence::Primary && !matches!(token,
-                Token::Add | Token::Sub | Token::Mul | Token::Div | Token::Mod | Token::Assign | Token::Cond | Token::Eq | Token::Ne | Token::Lt | Token::Gt | Token::Le | Token::Ge | Token::Shl | Token::Shr | Token::And | Token::Or | Token::Xor | Token::Lor | Token::Lan) {
-                break;
+    // Apply binary operators to the operand already in ax while their precedence is high enough
+    fn parse_binary_operators(&mut self, precedence: Precedence) -> Result<ExprResult, ParseError> {
+        // Keep processing operators while their precedence is high enough. Right operands are
+        // parsed one level up, so `a - b - c` applies the first `-` before seeing the second
+        while let Some(token) = self.lexer.peek_token() {
+            match self.get_token_precedence(&token) {
+                Some(token_precedence) if token_precedence >= precedence => {}
+                _ => break,
             }
-            // Otherwise, handle as operator
-            // Consume the operator token
+
+            // Consume the operator token; the operator's code is attributed to its line
+            self.check_value()?;
+            let lhs = self.expr_result();
+            let lhs_type = lhs.typ;
+            let line = self.lexer.line;
             self.lexer.next_token();
-            // Before parsing the right-hand side, save the current_class
-            let lhs_class = self.current_class.clone();
-            // Generate code for the operator
+            self.indexed_array = None;
+
             match token {
-                Token::Add => {
-                    // Parse the right-hand side with higher precedence
-                    self.parse_expr_with_precedence(Precedence::Multiplicative, stop_tokens)?;
-                }
-                Token::Sub => {
-                    // Parse the right-hand side with higher precedence
-                    self.parse_expr_with_precedence(Precedence::Multiplicative, stop_tokens)?;
-                }
-                Token::Mul => {
-                    // Parse the right-hand side with higher precedence
-                    self.parse_expr_with_precedence(Precedence::Unary, stop_tokens)?;
-                }
-                Token::Div => {
-                    // Parse the right-hand side with higher precedence
-                    self.parse_expr_with_precedence(Precedence::Unary, stop_tokens)?;
-                }
-                Token::Mod => {
-                    // Parse the right-hand side with higher precedence
-                    self.parse_expr_with_precedence(Precedence::Unary, stop_tokens)?;
-                }
                 Token::Assign => {
-                    // Parse the right-hand side with precedence just below assignment
-                    self.parse_expr_with_precedence(Precedence::Conditional, stop_tokens)?;
+                    // Only a variable, a dereference or an array element is loaded from an
+                    // address; drop the load to keep the address, anything else is an rvalue
+                    if !lhs.is_lvalue {
+                        return Err("cannot assign to rvalue".into());
+                    }
+                    if let Some(name) = self.read_only_target() {
+                        return Err(format!("assignment to read-only variable '{}'", name).into());
+                    }
+                    self.code.pop_load();
+                    self.code.emit(Opcode::PSH);
+                    // Assignment is right-associative
+                    let rhs = self.parse_operand(Precedence::Assignment)?;
+                    self.check_assignable(&lhs_type, &rhs.typ)?;
+                    self.code.line = line;
+                    // The store leaves the assigned value in ax, so the assignment can itself
+                    // be an operand, as in `while ((c = getchar()) != -1)`
+                    self.code.emit_store(&lhs_type);
+                    self.current_type = Some(lhs_type);
                 }
                 Token::Cond => {
+                    // Skip the middle expression when the condition is false
+                    self.code.emit_imm(Opcode::BZ, 0);
+                    let else_jump = self.code.text_offset - 1;
+
                     // Parse the middle expression (between ? and :)
-                    self.parse_expr_with_precedence(Precedence::Assignment, stop_tokens)?;
+                    self.parse_operand(Precedence::Assignment)?;
 
                     // Expect and consume the colon
-                    if let Some(Token::Unknown(b':')) = self.lexer.peek_token() {
+                    if let Some(Token::Colon) = self.lexer.peek_token() {
                         self.lexer.next_token();
                     } else {
-                        return Err("Expected ':' in conditional expression".to_string());
+                        let expected = vec![TokenKind::Token(Token::Colon)];
+                        return Err(self.error_here("Expected ':' in conditional expression").expecting(expected));
                     }
 
-                    // Parse the right-hand side with precedence just below assignment
-                    self.parse_expr_with_precedence(Precedence::Conditional, stop_tokens)?;
+                    self.code.emit_imm(Opcode::JMP, 0);
+                    let end_jump = self.code.text_offset - 1;
+                    self.code.text[else_jump] = self.code.text_offset as i32;
+
+                    // The else branch is a whole conditional expression, which takes in any
+                    // further `?`, so `a ? b : c ? d : e` groups as `a ? b : (c ? d : e)`
+                    self.parse_operand(Precedence::Conditional)?;
+                    self.code.text[end_jump] = self.code.text_offset as i32;
                 }
-                // Handle other operators similarly...
-                _ => {
-                    // For simplicity, we'll just parse the right-hand side with the current precedence
-                    self.parse_expr_with_precedence(Precedence::Assignment, stop_tokens)?;
+                Token::Lor => {
+                    // Short-circuit: a non-zero left side is the result
+                    self.code.emit_imm(Opcode::BNZ, 0);
+                    let end_jump = self.code.text_offset - 1;
+                    self.parse_operand(Precedence::LogicalAnd)?;
+                    self.code.text[end_jump] = self.code.text_offset as i32;
+                    self.current_type = Some(Type::Int);
                 }
-            }
-            // After parsing the right-hand side, only update current_class if it is Function or Sys; otherwise, preserve lhs_class if it was Function or Sys
-            // After parsing each operator, restore class if needed
-            if self.current_class.is_none() {
-                if let Some(super::symbol_table::Class::Function) | Some(super::symbol_table::Class::Sys) = saved_class {
-                    self.current_class = saved_class.clone();
+                Token::Lan => {
+                    // Short-circuit: a zero left side is the result
+                    self.code.emit_imm(Opcode::BZ, 0);
+                    let end_jump = self.code.text_offset - 1;
+                    self.parse_operand(Precedence::BitwiseOr)?;
+                    self.code.text[end_jump] = self.code.text_offset as i32;
+                    self.current_type = Some(Type::Int);
+                }
+                Token::Add => {
+                    self.code.emit(Opcode::PSH);
+                    // Parse the right-hand side with higher precedence
+                    self.parse_operand(Precedence::Multiplicative)?;
+                    self.code.line = line;
+                    // Pointer arithmetic advances by whole elements
+                    self.emit_pointer_scale(&lhs_type);
+                    self.code.emit(Opcode::ADD);
+                    self.current_type = Some(lhs_type.promote());
+                }
+                Token::Sub => {
+                    self.code.emit(Opcode::PSH);
+                    // Parse the right-hand side with higher precedence
+                    let rhs = self.parse_operand(Precedence::Multiplicative)?;
+                    self.code.line = line;
+                    if lhs_type.is_pointer() && rhs.typ.is_pointer() {
+                        // Pointer difference counts elements
+                        self.code.emit(Opcode::SUB);
+                        let size = self.element_size(&lhs_type);
+                        if size > 1 {
+                            self.code.emit(Opcode::PSH);
+                            self.code.emit_imm(Opcode::IMM, size);
+                            self.code.emit(Opcode::DIV);
+                        }
+                        self.current_type = Some(Type::Int);
+                    } else {
+                        self.emit_pointer_scale(&lhs_type);
+                        self.code.emit(Opcode::SUB);
+                        self.current_type = Some(lhs_type.promote());
+                    }
+                }
+                _ => {
+                    // Remaining operators all evaluate both sides: left on the stack, right in ax
+                    let (rhs_precedence, op) = match token {
+                        Token::Or => (Precedence::BitwiseXor, Opcode::OR),
+                        Token::Xor => (Precedence::BitwiseAnd, Opcode::XOR),
+                        Token::And => (Precedence::Equality, Opcode::AND),
+                        Token::Eq => (Precedence::Relational, Opcode::EQ),
+                        Token::Ne => (Precedence::Relational, Opcode::NE),
+                        Token::Lt => (Precedence::Shift, Opcode::LT),
+                        Token::Gt => (Precedence::Shift, Opcode::GT),
+                        Token::Le => (Precedence::Shift, Opcode::LE),
+                        Token::Ge => (Precedence::Shift, Opcode::GE),
+                        Token::Shl => (Precedence::Additive, Opcode::SHL),
+                        Token::Shr => (Precedence::Additive, Opcode::SHR),
+                        Token::Mul => (Precedence::Unary, Opcode::MUL),
+                        Token::Div => (Precedence::Unary, Opcode::DIV),
+                        Token::Mod => (Precedence::Unary, Opcode::MOD),
+                        _ => return Err(format!("Unexpected operator in expression: {:?}", token).into()),
+                    };
+                    self.code.emit(Opcode::PSH);
+                    let rhs = self.parse_operand(rhs_precedence)?;
+                    self.code.line = line;
+                    // Addresses are unsigned, so ordering pointers needs unsigned comparisons
+                    if lhs_type.is_pointer() || rhs.typ.is_pointer() {
+                        self.code.emit(op.to_unsigned());
+                    } else {
+                        self.code.emit(op);
+                    }
+                    self.current_type = Some(Type::Int);
                 }
             }
-        }
 
-        // Final check - if we still don't have a class but saved_class was a function/sys, restore it
-        if self.current_class.is_none() {
-            if let Some(super::symbol_table::Class::Function) | Some(super::symbol_table::Class::Sys) = saved_class {
-                self.current_class = saved_class;
-            }
+            // The result of an operator is a plain value
+            self.current_class = None;
+            self.current_value = 0;
+            self.operator_end = Some(self.code.text_offset);
         }
 
-        Ok(())
+        Ok(self.expr_result())
     }
 
     // Parse primary expressions (literals, identifiers, parenthesized expressions)
-    fn parse_primary_expr(&mut self, stop_tokens: Option<&[Token]>) -> Result<(), String> {
-        println!("DEBUG: Entering parse_primary_expr, current token: {:?}", self.lexer.peek_token());
-
-        // --- new: if this token is one of our stops (e.g. ';'), just return ---
-        if let Some(stops) = stop_tokens {
-            if let Some(tok) = self.lexer.peek_token() {
-                if stops.iter().any(|t| t == &tok) {
-                    println!("DEBUG: parse_primary_expr saw stop token: {:?}, ending expr", tok);
-                    return Ok(());
+    fn parse_primary_expr(&mut self) -> Result<(), ParseError> {
+        let token = match self.lexer.peek_token() {
+            Some(token) => token,
+            None => return Err(self.error_here("Unexpected end of input in expression")),
+        };
+
+        self.indexed_array = None;
+        self.operator_end = None;
+        self.code.line = self.lexer.line;
+        match token.clone() {
+            // Numeric literal
+            Token::Num(val) => {
+                // Without long, a literal must fit in 32 bits; those above INT_MAX keep their
+                // bits, as an unsigned int would
+                if val > u32::MAX as i64 {
+                    let (start, end) = self.lexer.token_span();
+                    let text = String::from_utf8_lossy(&self.lexer.src[start..end]).into_owned();
+                    return Err(self.error_here(&format!("integer literal {} is too large for int (long is not supported)", text)));
                 }
+                self.current_value = val;
+                self.current_type = Some(Type::Int);
+                self.current_class = None;
+                self.code.emit_imm(Opcode::IMM, val as i32);
+                self.lexer.next_token();
             }
-        }
 
-        if let Some(token) = self.lexer.peek_token() {
-            match token.clone() {
-                // Handle type tokens during the second pass
-                Token::Int | Token::CharType => {
-                    // Skip type tokens during expression parsing
-                    // This happens during the second pass when we're generating code
-                    self.lexer.next_token();
-                    // Skip the identifier and any initialization
-                    while let Some(t) = self.lexer.peek_token() {
-                        if t == Token::Semi {
-                            self.lexer.next_token(); // Consume semicolon
-                            break;
-                        }
-                        self.lexer.next_token();
-                    }
-                    return Ok(());
-                },
-                // Numeric literal
-                Token::Num(val) => {
-                    println!("DEBUG: Found numeric literal: {}", val);
-                    self.current_value = val;
-                    self.current_type = Some(Type::Int);
+            // Character literal
+            Token::Char(c) => {
+                self.current_value = c as i64;
+                self.current_type = Some(Type::Char);
+                self.current_class = None;
+                self.code.emit_imm(Opcode::IMM, c as i32);
+                self.lexer.next_token();
+            }
+
+            Token::Invalid(msg) => return Err(self.error_here(&msg)),
+
+            // String literal
+            Token::Str(s) => {
+                // Adjacent string literals form a single string
+                let mut s = s;
+                self.lexer.next_token();
+                while let Some(Token::Str(next)) = self.lexer.peek_token() {
+                    s.push_str(&next);
                     self.lexer.next_token();
-                    return Ok(());
                 }
 
-                // Character literal
-                Token::Char(c) => {
-                    println!("DEBUG: Found character literal: {}", c);
-                    self.current_value = c as i64;
-                    self.current_type = Some(Type::Char);
-                    self.lexer.next_token();
-                    return Ok(());
+                // Add the string to the data section and load its address
+                self.current_value = self.add_string(&s)? as i64;
+                self.current_type = Some(Type::Ptr(Box::new(Type::Char)));
+                self.current_class = None;
+                self.code.emit_imm(Opcode::IMM, self.current_value as i32);
+            }
+
+            // Identifier
+            Token::Id(id) => {
+                let span = self.lexer.token_span();
+                self.lexer.next_token();
+                // Look up the identifier in the symbol table
+                let symbol = match self.symbol_table.lookup(&id) {
+                    Some(symbol) => symbol.clone(),
+                    // The first pass has not seen functions defined further down yet
+                    None if !self.second_pass && self.lexer.peek_token() == Some(Token::OpenParen) => Symbol {
+                        name: id.clone(),
+                        class: Class::Function,
+                        typ: Type::Func { ret: Box::new(Type::Int), params: Vec::new(), variadic: true },
+                        val: 0,
+                        offset: 0,
+                        span,
+                        is_const: false,
+                    },
+                    None => return Err(self.error_at(format!("Undefined identifier: {}", id), span)),
+                };
+                self.parse_identifier(symbol)?;
+                self.parse_postfix_operators()?;
+            }
+
+            // System function calls
+            Token::Printf | Token::Open | Token::Read | Token::Close |
+            Token::Malloc | Token::Free | Token::Memset | Token::Memcmp | Token::Exit => {
+                // Get the function name from the token
+                let func_name = match &token {
+                    Token::Printf => "printf",
+                    Token::Open => "open",
+                    Token::Read => "read",
+                    Token::Close => "close",
+                    Token::Malloc => "malloc",
+                    Token::Free => "free",
+                    Token::Memset => "memset",
+                    Token::Memcmp => "memcmp",
+                    Token::Exit => "exit",
+                    _ => unreachable!(),
+                };
+
+                // Look up the system function in the symbol table
+                let symbol = match self.symbol_table.lookup(func_name) {
+                    Some(symbol) => symbol.clone(),
+                    None => return Err(format!("System function not found in symbol table: {}", func_name).into()),
+                };
+                self.lexer.next_token();
+                self.parse_identifier(symbol)?;
+                self.parse_postfix_operators()?;
+            }
+
+            // sizeof operator
+            Token::Sizeof => {
+                self.lexer.next_token();
+                // Check if the next token is an open parenthesis
+                self.expect(Token::OpenParen, "Expected '(' after sizeof")?;
+
+                // Parse the type or expression inside sizeof
+                if self.lexer.peek_token().is_some_and(|token| token.is_type_specifier()) {
+                    // sizeof a type
+                    self.parse_type()?; // Using the public method from declaration.rs
+                } else {
+                    // sizeof an expression: only its type matters, so drop the code it generated
+                    let start = self.code.text_offset;
+                    self.decayed_array = None;
+                    self.parse_expr_with_precedence(Precedence::Assignment)?;
+
+                    // An array on its own keeps its full size. Any operator applied after it
+                    // decayed would have emitted code, so the offsets tell the two apart
+                    let end = self.code.text_offset;
+                    if let Some((_, array)) = self.decayed_array.take().filter(|(at, _)| *at == end) {
+                        self.current_type = Some(array);
+                    }
+                    self.code.truncate(start);
                 }
 
-                // String literal
-                Token::Str(s) => {
-                    println!("DEBUG: Found string literal: {}", s);
-                    // Add the string to the data section and set the current value to its index
-                    self.current_value = self.add_string(&s) as i64;
-                    self.current_type = Some(Type::Ptr(Box::new(Type::Char)));
+                // Expect closing parenthesis
+                self.expect(Token::CloseParen, "Expected ')' after sizeof expression")?;
+
+                // Set the result to the size of the type
+                self.current_value = self.current_type.as_ref().map_or(4, |typ| typ.size()) as i64;
+                self.current_type = Some(Type::Int);
+                self.current_class = None;
+                self.code.emit_imm(Opcode::IMM, self.current_value as i32);
+            }
+
+            // Parenthesized expression
+            Token::OpenParen => {
+                self.lexer.next_token();
+
+                // Parse the expression inside parentheses
+                self.parse_expr_with_precedence(Precedence::Assignment)?;
+
+                // Expect closing parenthesis
+                if let Some(Token::CloseParen) = self.lexer.peek_token() {
                     self.lexer.next_token();
-                    return Ok(());
+                } else {
+                    let expected = vec![TokenKind::Token(Token::CloseParen)];
+                    return Err(self.error_here("Expected ')' after expression").expecting(expected));
                 }
 
-                // Identifier
-                Token::Id(id) => {
-                    println!("DEBUG: Found identifier: {}", id);
-                    // Look up the identifier in the symbol table
-                    if let Some(symbol) = self.symbol_table.lookup(&id) {
-                        self.current_id = Some(id.clone());
-                        self.current_class = Some(symbol.class.clone());
-                        self.current_type = Some(symbol.typ.clone());
-                        self.current_value = symbol.val;
-                        self.lexer.next_token();
-                        let func_class = self.current_class.clone();
-                        let func_id = self.current_id.clone();
-                        // Check for function call or array indexing
-                        let mut did_call = false;
-                        if let Some(Token::OpenParen) = self.lexer.peek_token() {
-                            self.parse_function_call()?;
-                            // Always restore class/id after parsing arguments
-                            self.current_class = func_class.clone();
-                            self.current_id = func_id.clone();
-                            // Explicitly set class to function's class after parsing call
-                            self.current_class = func_class;
-                        }
-                        // Handle postfix operators (e.g., array indexing) after restoration
-                        if let Some(Token::Brak) = self.lexer.peek_token() {
-                            self.parse_postfix_operators(stop_tokens)?;
-                        }
-                        return Ok(());
-                    } else {
-                        return Err(format!("Undefined identifier: {}", id));
-                    }
-                }
+                self.parse_postfix_operators()?;
+            }
 
-                // System function calls
-                Token::Printf | Token::Open | Token::Read | Token::Close |
-                Token::Malloc | Token::Free | Token::Memset | Token::Memcmp | Token::Exit => {
-                    // Get the function name from the token
-                    let func_name = match &token {
-                        Token::Printf => "printf",
-                        Token::Open => "open",
-                        Token::Read => "read",
-                        Token::Close => "close",
-                        Token::Malloc => "malloc",
-                        Token::Free => "free",
-                        Token::Memset => "memset",
-                        Token::Memcmp => "memcmp",
-                        Token::Exit => "exit",
-                        _ => unreachable!(),
-                    };
+            // Logical not
+            Token::Not => {
+                self.lexer.next_token();
+                self.parse_expr_with_precedence(Precedence::Unary)?;
+                self.emit_logical_not();
+            }
 
-                    println!("DEBUG: Found system function: {}", func_name);
+            // Pre-increment/decrement
+            Token::Inc | Token::Dec => {
+                self.lexer.next_token();
+                self.parse_expr_with_precedence(Precedence::Unary)?;
+                self.emit_increment(token == Token::Inc)?;
+                self.current_class = None;
+            }
 
-                    // Look up the system function in the symbol table
-                    if let Some(symbol) = self.symbol_table.lookup(func_name) {
-                        self.current_id = Some(func_name.to_string());
-                        self.current_class = Some(symbol.class.clone());
-                        self.current_type = Some(symbol.typ.clone());
-                        self.current_value = symbol.val;
-                        self.lexer.next_token();
-                        let func_class = self.current_class.clone();
-                        let func_id = self.current_id.clone();
-                        if let Some(Token::OpenParen) = self.lexer.peek_token() {
-                            self.parse_function_call()?;
-                            // Always restore class/id after parsing arguments
-                            self.current_class = func_class.clone();
-                            self.current_id = func_id.clone();
-                            // Explicitly set class to function's class after parsing call
-                            self.current_class = func_class;
-                        } else {
-                            return Err(format!("Expected '(' after system function: {}", func_name));
+            // Unary operators
+            Token::Add | Token::Sub | Token::Mul | Token::And => {
+                self.lexer.next_token();
+                // Parse the operand with unary precedence
+                let start = self.code.text_offset;
+                let operand = self.parse_expr_with_precedence(Precedence::Unary)?;
+
+                // Handle the unary operator
+                match token {
+                    Token::Add => {
+                        // Unary + only promotes its operand
+                        self.check_value()?;
+                        self.current_type = Some(operand.typ.promote());
+                    }
+                    Token::Sub => {
+                        self.check_value()?;
+                        // Function names, strings and arrays also load a lone IMM, but they
+                        // stand for addresses, which have no negative
+                        if operand.typ.decay().is_pointer() {
+                            return Err(format!("wrong type argument to unary minus: {}", operand.typ.decay()).into());
                         }
-                        // Handle postfix operators (e.g., array indexing) after restoration
-                        if let Some(Token::Brak) = self.lexer.peek_token() {
-                            self.parse_postfix_operators(stop_tokens)?;
+                        let constant = matches!(operand.class, None | Some(Class::Num));
+                        if constant && self.code.text_offset == start + 2 && self.code.last_op() == Some(Opcode::IMM) {
+                            // Negate a lone constant in place
+                            let operand = self.code.text_offset - 1;
+                            self.code.text[operand] = self.code.text[operand].wrapping_neg();
+                            self.current_value = -self.current_value;
+                        } else {
+                            // Otherwise multiply by -1 at run time, as C4 does
+                            self.code.emit(Opcode::PSH);
+                            self.code.emit_imm(Opcode::IMM, -1);
+                            self.code.emit(Opcode::MUL);
                         }
-                        return Ok(());
-                    } else {
-                        return Err(format!("System function not found in symbol table: {}", func_name));
+                        self.current_type = Some(operand.typ.promote());
                     }
-                }
-
-                // sizeof operator
-                Token::Sizeof => {
-                    self.lexer.next_token();
-                    // Check if the next token is an open parenthesis
-                    if let Some(Token::OpenParen) = self.lexer.peek_token() {
-                        self.lexer.next_token();
-                        // Parse the type or expression inside sizeof
-                        if matches!(self.lexer.peek_token(), Some(Token::Int) | Some(Token::CharType)) {
-                            // sizeof a type
-                            self.parse_type()?; // Using the public method from declaration.rs
-                            // Set the result to the size of the type
-                            if let Some(typ) = &self.current_type {
-                                self.current_value = typ.size() as i64;
-                                self.current_type = Some(Type::Int);
+                    Token::Mul => {
+                        // Dereference a pointer; a function pointer dereferences to the
+                        // function, which is called through the same address
+                        if let Some(Type::Ptr(base_type)) = self.current_type.clone() {
+                            if !matches!(*base_type, Type::Func { .. } | Type::Struct(_)) {
+                                self.code.emit_load(&base_type);
                             }
+                            self.current_type = Some(*base_type);
                         } else {
-                            // sizeof an expression
-                            self.parse_expr_with_precedence(Precedence::Assignment, stop_tokens)?;
-                            // Set the result to the size of the expression's type
-                            if let Some(typ) = &self.current_type {
-                                self.current_value = typ.size() as i64;
-                                self.current_type = Some(Type::Int);
-                            }
+                            return Err("Cannot dereference non-pointer type".into());
                         }
-                        // Expect closing parenthesis
-                        if let Some(Token::CloseParen) = self.lexer.peek_token() {
-                            self.lexer.next_token();
-                        } else {
-                            return Err("Expected ')' after sizeof expression".to_string());
+                    }
+                    Token::And if operand.class == Some(Class::Function) => {
+                        // A function name already evaluates to its address
+                    }
+                    Token::And if matches!(operand.typ, Type::Struct(_)) => {
+                        // So does a struct
+                        self.current_type = Some(operand.typ.to_pointer());
+                    }
+                    Token::And => {
+                        // Take the address of an lvalue: keep the address instead of loading through it
+                        if !operand.is_lvalue {
+                            return Err("Cannot take the address of a non-lvalue".into());
                         }
-                    } else {
-                        return Err("Expected '(' after sizeof".to_string());
+                        self.code.pop_load();
+                        self.current_type = Some(operand.typ.to_pointer());
                     }
-                    return Ok(());
+                    _ => unreachable!(),
                 }
+                self.current_class = None;
+            }
 
-                // Parenthesized expression
-                Token::OpenParen => {
-                    self.lexer.next_token();
-                    // If next token is ')', treat as empty parenthesized expression
-                    if let Some(Token::CloseParen) = self.lexer.peek_token() {
-                        self.lexer.next_token();
-                        self.current_value = 0;
-                        self.current_type = Some(Type::Int);
-                        return Ok(());
-                    }
-
-                    // Parse the expression inside parentheses
-                    self.parse_expr_with_precedence(Precedence::Assignment, Some(&[Token::CloseParen]))?;
+            Token::Colon => return Err(self.error_here("unexpected ':'")),
 
-                    // Expect closing parenthesis
-                    if let Some(Token::CloseParen) = self.lexer.peek_token() {
-                        self.lexer.next_token();
-                    } else {
-                        return Err("Expected ')' after expression".to_string());
-                    }
+            _ => {
+                // Unknown token in expression
+                return Err(self.error_here(&format!("Unexpected token in expression: {:?}", token)));
+            }
+        }
 
-                    return Ok(());
-                }
+        Ok(())
+    }
 
-                // Unary operators
-                Token::Add | Token::Sub | Token::Mul | Token::And => {
-                    let op = token.clone();
-                    self.lexer.next_token();
-                    // Parse the operand with unary precedence
-                    self.parse_expr_with_precedence(Precedence::Unary, stop_tokens)?;
+    // Generate code for a resolved identifier: a variable load or a call
+    fn parse_identifier(&mut self, symbol: Symbol) -> Result<(), ParseError> {
+        self.read_only_load = None;
+        self.current_id = Some(symbol.name.clone());
+        self.current_class = Some(symbol.class.clone());
+        self.current_type = Some(symbol.typ.clone());
+        self.current_value = symbol.val;
+
+        // Remember the array's length so that indexing it can be bounds checked
+        if let Type::Array(_, len) = &symbol.typ {
+            self.indexed_array = Some((symbol.name.clone(), *len));
+        }
 
-                    // Handle the unary operator
-                    match op {
-                        Token::Add => {
-                            // Unary + is a no-op
-                        }
-                        Token::Sub => {
-                            // Negate the result
-                            self.current_value = -self.current_value;
-                        }
-                        Token::Mul => {
-                            // Dereference a pointer
-                            if let Some(Type::Ptr(base_type)) = &self.current_type {
-                                self.current_type = Some(*base_type.clone());
-                            } else {
-                                return Err("Cannot dereference non-pointer type".to_string());
-                            }
-                        }
-                        Token::And => {
-                            // Take the address of a variable
-                            if let Some(typ) = &self.current_type {
-                                self.current_type = Some(Type::Ptr(Box::new(typ.clone())));
-                            }
-                        }
-                        _ => unreachable!(),
+        match symbol.class {
+            Class::Function if self.lexer.peek_token() != Some(Token::OpenParen) => {
+                // A function name on its own stands for its address
+                self.code.emit_imm(Opcode::IMM, symbol.val as i32);
+                if self.second_pass && symbol.val == 0 {
+                    let at = self.code.text_offset - 1;
+                    self.code.call_fixups.push((at, symbol.name.clone()));
+                }
+                self.current_type = Some(symbol.typ.decay());
+            }
+            Class::Function | Class::Sys => {
+                // Arguments may hold calls of their own, which are complete, ADJ included,
+                // by the time they are pushed
+                let arg_count = if let Some(Token::OpenParen) = self.lexer.peek_token() {
+                    let arg_count = self.parse_function_call()?;
+                    self.check_arity(&symbol.name, &symbol.typ, arg_count)?;
+                    arg_count
+                } else {
+                    return Err(self.expected_error(vec![TokenKind::Token(Token::OpenParen)], &format!("Expected '(' after function name: {}", symbol.name)));
+                };
+
+                if symbol.class == Class::Sys {
+                    let op = Opcode::for_sys_func(&symbol.name)
+                        .ok_or_else(|| format!("Unknown system function: {}", symbol.name))?;
+                    self.code.emit(op);
+                } else {
+                    self.code.emit_imm(Opcode::JSR, symbol.val as i32);
+                    // Functions emitted later get their address patched in at the end
+                    if self.second_pass && symbol.val == 0 {
+                        let at = self.code.text_offset - 1;
+                        self.code.call_fixups.push((at, symbol.name.clone()));
                     }
-                    return Ok(());
                 }
 
-                _ => {
-                    // Unknown token in expression
-                    println!("DEBUG: [parse_primary_expr] current_class at end: {:?}", self.current_class);
-                    return Err(format!("Unexpected token in expression: {:?}", token));
+                // Now pop the arguments off the stack
+                if arg_count > 0 {
+                    self.code.emit_imm(Opcode::ADJ, arg_count as i32);
+                }
+
+                // Restore class/id after parsing arguments; the call's value has the return type
+                self.current_id = Some(symbol.name);
+                self.current_class = Some(symbol.class);
+                self.current_value = symbol.val;
+                self.current_type = Some(symbol.typ.return_type().unwrap_or(&symbol.typ).clone());
+            }
+            Class::Num => {
+                self.code.emit_imm(Opcode::IMM, symbol.val as i32);
+            }
+            Class::Local | Class::Global => {
+                if symbol.class == Class::Local {
+                    self.code.emit_imm(Opcode::LEA, symbol.offset);
+                } else {
+                    self.code.emit_imm(Opcode::IMM, symbol.val as i32);
+                }
+                self.emit_variable_load(&symbol.typ);
+                // Remember where the load ends, so storing through it can be refused
+                if symbol.is_const {
+                    self.read_only_load = Some((self.code.text_offset, symbol.name));
                 }
             }
+        }
+
+        Ok(())
+    }
+
+    // Load a variable whose address is in ax; arrays evaluate to their address, and so do
+    // structs, whose members are reached from it
+    fn emit_variable_load(&mut self, typ: &Type) {
+        if typ.is_array() {
+            self.decayed_array = Some((self.code.text_offset, typ.clone()));
+            self.current_type = Some(typ.decay());
+        } else if let Type::Struct(_) = typ {
+            self.current_type = Some(typ.clone());
         } else {
-            return Err("Unexpected end of input in expression".to_string());
+            self.code.emit_load(typ);
         }
     }
 
-    // Parse postfix operators (++, --, [])
-    fn parse_postfix_operators(&mut self, stop_tokens: Option<&[Token]>) -> Result<(), String> {
+    // Parse postfix operators (++, --, [], calls, . and ->)
+    fn parse_postfix_operators(&mut self) -> Result<(), ParseError> {
         while let Some(token) = self.lexer.peek_token() {
             match token {
-                Token::Inc => {
+                Token::Inc | Token::Dec => {
                     self.lexer.next_token();
-                    // Handle post-increment
-                    // For now, just note that we've seen it
+                    // Post-increment/decrement: update the variable, then undo the step in ax
+                    // so the expression yields the old value
+                    let (stride, op) = self.emit_increment(token == Token::Inc)?;
+                    self.code.emit(Opcode::PSH);
+                    self.code.emit_imm(Opcode::IMM, stride);
+                    self.code.emit(if op == Opcode::ADD { Opcode::SUB } else { Opcode::ADD });
                 }
-                Token::Dec => {
-                    self.lexer.next_token();
-                    // Handle post-decrement
-                    // For now, just note that we've seen it
+                Token::OpenParen => {
+                    // Call through a function pointer: the target is pushed before the
+                    // arguments, and JSRI finds it just above them
+                    let Some(func) = self.current_type.as_ref().and_then(Type::as_function).cloned() else {
+                        return Err(self.error_here("Called object is not a function"));
+                    };
+                    let name = self.current_id.clone().unwrap_or_default();
+                    self.code.emit(Opcode::PSH);
+                    let arg_count = self.parse_function_call()?;
+                    self.check_arity(&name, &func, arg_count)?;
+                    self.code.emit_imm(Opcode::JSRI, arg_count as i32);
+                    self.code.emit_imm(Opcode::ADJ, arg_count as i32 + 1);
+                    self.current_type = func.return_type().cloned();
+                    self.current_class = None;
                 }
                 Token::Brak => {
                     self.lexer.next_token();
+                    let base_type = self.current_type.clone().unwrap_or(Type::Int);
+                    let array = self.indexed_array.take();
+                    self.code.emit(Opcode::PSH);
                     // Parse the index expression
-                    self.parse_expr_with_precedence(Precedence::Assignment, stop_tokens)?;
+                    self.parse_expr_with_precedence(Precedence::Assignment)?;
+                    // In checked mode, trap on an index outside the array before scaling it
+                    if let (true, Some((name, len))) = (self.bounds_checks, array) {
+                        let guard = self.code.store_bounds(&name, len)?;
+                        self.code.emit_imm(Opcode::BNDS, guard as i32);
+                    }
                     // Expect closing bracket
-                    if let Some(Token::Unknown(b']')) = self.lexer.peek_token() {
+                    if let Some(Token::CloseBrak) = self.lexer.peek_token() {
                         self.lexer.next_token();
                     } else {
-                        return Err("Expected ']' after array index".to_string());
+                        let expected = vec![TokenKind::Token(Token::CloseBrak)];
+                        return Err(self.error_here("Expected ']' after array index").expecting(expected));
                     }
-                    // Handle array indexing
-                    if let Some(Type::Ptr(base_type)) = self.current_type.clone() {
-                        self.current_type = Some(*base_type);
+                    // Handle array indexing: the element's address is computed and then loaded;
+                    // `&` and `=` drop that trailing load to use the element as an lvalue
+                    if let Type::Ptr(elem_type) = &base_type {
+                        self.emit_pointer_scale(&base_type);
+                        self.code.emit(Opcode::ADD);
+                        self.current_type = Some(*elem_type.clone());
+                        self.emit_variable_load(elem_type);
                     } else {
-                        return Err("Cannot index non-pointer type".to_string());
+                        return Err("Cannot index non-pointer type".into());
                     }
                 }
+                Token::Dot | Token::Arrow => {
+                    self.lexer.next_token();
+                    self.emit_member_access(token == Token::Arrow)?;
+                }
                 _ => break,
             }
         }
         Ok(())
     }
 
-    // Parse function call
-    pub fn parse_function_call(&mut self) -> Result<(), String> {
-        println!("DEBUG: Parsing function call");
+    // Step from the struct whose address is in ax (or, for `->`, the pointer to it) to the
+    // member named next, and load it with the member's width
+    fn emit_member_access(&mut self, arrow: bool) -> Result<(), ParseError> {
+        let span = self.lexer.token_span();
+        let name = match self.lexer.peek_token() {
+            Some(Token::Id(name)) => name,
+            _ => return Err(self.expected_error(vec![TokenKind::Identifier], "Expected member name")),
+        };
+        self.lexer.next_token();
+
+        let typ = self.current_type.clone().unwrap_or(Type::Int);
+        let target = if arrow { typ.get_base_type() } else { Some(&typ) };
+        let def = match target {
+            Some(Type::Struct(def)) if arrow == typ.is_pointer() => def.clone(),
+            _ if arrow => return Err(self.error_at(format!("invalid type argument of '->' (have '{}')", typ), span)),
+            _ => return Err(self.error_at(format!("request for member '{}' in something not a structure", name), span)),
+        };
+        if !def.is_complete() {
+            return Err(self.error_at(format!("dereferencing pointer to incomplete type 'struct {}'", def.name), span));
+        }
+        let Some(member) = def.member(&name) else {
+            return Err(self.error_at(format!("'struct {}' has no member named '{}'", def.name, name), span));
+        };
+
+        if member.offset > 0 {
+            self.code.emit(Opcode::PSH);
+            self.code.emit_imm(Opcode::IMM, member.offset);
+            self.code.emit(Opcode::ADD);
+        }
+        self.indexed_array = match &member.typ {
+            Type::Array(_, len) => Some((member.name.clone(), *len)),
+            _ => None,
+        };
+        self.current_type = Some(member.typ.clone());
+        self.current_class = None;
+        self.emit_variable_load(&member.typ);
+        Ok(())
+    }
+
+    // Parse function call; each argument is pushed as soon as it is evaluated. Returns the
+    // number of arguments
+    pub fn parse_function_call(&mut self) -> Result<usize, ParseError> {
         self.lexer.next_token(); // consume '('
         let mut arg_count = 0;
 
         // Special-case empty argument list
         if let Some(Token::CloseParen) = self.lexer.peek_token() {
-            println!("DEBUG: No arguments in function call (empty argument list)");
             self.lexer.next_token(); // consume ')'
         } else {
             // Parse comma-separated arguments
             loop {
                 // Parse the full expression for this argument
-                self.parse_expr_with_precedence(Precedence::Assignment, Some(&[Token::Comma, Token::CloseParen]))?;
+                self.parse_operand(Precedence::Assignment)?;
+                self.code.emit(Opcode::PSH);
                 arg_count += 1;
 
                 match self.lexer.peek_token() {
                     Some(Token::Comma) => {
-                        println!("DEBUG: Found comma, consuming and continuing");
                         self.lexer.next_token(); // consume ','
                     },
                     Some(Token::CloseParen) => {
-                        println!("DEBUG: Found closing parenthesis, end of arguments");
                         self.lexer.next_token(); // consume ')'
                         break;
                     },
                     other => {
-                        println!("DEBUG: Expected ',' or ')' but found: {:?}", other);
-                        return Err(format!("Expected ',' or ')' in function call, found: {:?}", other));
+                        let expected = vec![TokenKind::Token(Token::Comma), TokenKind::Token(Token::CloseParen)];
+                        return Err(self.error_here(&format!("Expected ',' or ')' in function call, found: {:?}", other)).expecting(expected));
                     }
                 }
             }
         }
 
-        // Update both argument count trackers
-        self.current_value = arg_count;
-        self.arg_count = arg_count as usize;
-        println!("DEBUG: [parse_function_call] parsed {} args, class at end: {:?}", arg_count, self.current_class);
-        Ok(())
+        Ok(arg_count)
+    }
+
+    // Check the argument count of the call just parsed against the function's parameters;
+    // a variadic function takes any number of arguments beyond its fixed ones
+    fn check_arity(&self, name: &str, typ: &Type, given: usize) -> Result<(), ParseError> {
+        let Some(Type::Func { params, variadic, .. }) = typ.as_function() else {
+            return Ok(()); // Only function types have parameters to check
+        };
+        let expected = params.len();
+        if given < expected {
+            Err(self.error_here(&format!("too few arguments to function '{}': expected {}, got {}", name, expected, given)))
+        } else if given > expected && !variadic {
+            Err(self.error_here(&format!("too many arguments to function '{}': expected {}, got {}", name, expected, given)))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Add or subtract one step to the lvalue just parsed, leaving the new value in ax.
+    // Pointers step by their element size. Returns the stride and the opcode used
+    fn emit_increment(&mut self, increment: bool) -> Result<(i32, Opcode), ParseError> {
+        let typ = self.current_type.clone().unwrap_or(Type::Int);
+        if let Some(name) = self.read_only_target() {
+            let operation = if increment { "increment" } else { "decrement" };
+            return Err(self.error_here(&format!("{} of read-only variable '{}'", operation, name)));
+        }
+        if !self.expr_result().is_lvalue {
+            return Err(self.error_here("Invalid operand of '++'/'--': expected an lvalue"));
+        }
+        self.code.pop_load();
+        let stride = if typ.is_pointer() { self.element_size(&typ) } else { 1 };
+        let op = if increment { Opcode::ADD } else { Opcode::SUB };
+
+        // Address stays on the stack for the store; load, step, store back
+        self.code.emit(Opcode::PSH);
+        self.code.emit_load(&typ);
+        self.code.emit(Opcode::PSH);
+        self.code.emit_imm(Opcode::IMM, stride);
+        self.code.emit(op);
+        self.code.emit_store(&typ);
+        self.current_type = Some(typ);
+        Ok((stride, op))
+    }
+
+    // Name of the const variable whose load was emitted last, which cannot be stored to
+    fn read_only_target(&self) -> Option<String> {
+        match &self.read_only_load {
+            Some((at, name)) if *at == self.code.text_offset => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    // Size of the element a pointer type points to
+    fn element_size(&self, typ: &Type) -> i32 {
+        typ.get_base_type().map_or(1, |base| base.size())
+    }
+
+    // Scale the integer in ax by the pointee size when offsetting a pointer
+    fn emit_pointer_scale(&mut self, typ: &Type) {
+        if typ.is_pointer() {
+            let size = self.element_size(typ);
+            if size > 1 {
+                self.code.emit(Opcode::PSH);
+                self.code.emit_imm(Opcode::IMM, size);
+                self.code.emit(Opcode::MUL);
+            }
+        }
     }
 
-    // Get the precedence of a token
-    fn get_token_precedence(&self, token: &Token) -> Precedence {
+    // Get the precedence of a binary operator token
+    fn get_token_precedence(&self, token: &Token) -> Option<Precedence> {
         match token {
-            Token::Assign => Precedence::Assignment,
-            Token::Cond => Precedence::Conditional,
-            Token::Lor => Precedence::LogicalOr,
-            Token::Lan => Precedence::LogicalAnd,
-            Token::Or => Precedence::BitwiseOr,
-            Token::Xor => Precedence::BitwiseXor,
-            Token::And => Precedence::BitwiseAnd,
-            Token::Eq | Token::Ne => Precedence::Equality,
-            Token::Lt | Token::Gt | Token::Le | Token::Ge => Precedence::Relational,
-            Token::Shl | Token::Shr => Precedence::Shift,
-            Token::Add | Token::Sub => Precedence::Additive,
-            Token::Mul | Token::Div | Token::Mod => Precedence::Multiplicative,
-            Token::Inc | Token::Dec | Token::OpenParen | Token::Brak => Precedence::Postfix,
-            _ => Precedence::Primary,
+            Token::Assign => Some(Precedence::Assignment),
+            Token::Cond => Some(Precedence::Conditional),
+            Token::Lor => Some(Precedence::LogicalOr),
+            Token::Lan => Some(Precedence::LogicalAnd),
+            Token::Or => Some(Precedence::BitwiseOr),
+            Token::Xor => Some(Precedence::BitwiseXor),
+            Token::And => Some(Precedence::BitwiseAnd),
+            Token::Eq | Token::Ne => Some(Precedence::Equality),
+            Token::Lt | Token::Gt | Token::Le | Token::Ge => Some(Precedence::Relational),
+            Token::Shl | Token::Shr => Some(Precedence::Shift),
+            Token::Add | Token::Sub => Some(Precedence::Additive),
+            Token::Mul | Token::Div | Token::Mod => Some(Precedence::Multiplicative),
+            _ => None,
         }
     }
 }
@@ -4,19 +4,95 @@ pub mod declaration;
 pub mod expression;
 pub mod statement;
 
-use crate::lexer::{Lexer, Token};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::codegen::{CodeGenerator, LoopContext, Program};
+use crate::lexer::{Lexer, Token, TokenKind};
 use self::symbol_table::{Class, SymbolTable};
-use self::types::Type;
+use self::types::{StructDef, Type};
+
+// A compile error. Errors raised as plain messages get the position where parsing stopped
+// once they reach `compile`; a missing token also records what would have been accepted.
+// The details are boxed to keep results small, as the parser recurses deeply
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(Box<ErrorDetails>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorDetails {
+    pub message: String,
+    pub expected: Vec<TokenKind>, // Empty unless a particular token was missing
+    pub found: Token,             // The token parsing stopped at
+    pub line: usize,              // 1-based; 0 until the error has been located
+    pub col: usize,
+}
+
+impl Deref for ParseError {
+    type Target = ErrorDetails;
+
+    fn deref(&self) -> &ErrorDetails {
+        &self.0
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ParseError {
+    // Record which tokens would have been accepted where this error was raised
+    pub fn expecting(mut self, expected: Vec<TokenKind>) -> Self {
+        self.0.expected = expected;
+        self
+    }
+}
+
+impl From<String> for ParseError {
+    fn from(message: String) -> Self {
+        ParseError(Box::new(ErrorDetails { message, expected: Vec::new(), found: Token::Eof, line: 0, col: 0 }))
+    }
+}
+
+impl From<&str> for ParseError {
+    fn from(message: &str) -> Self {
+        ParseError::from(message.to_string())
+    }
+}
+
+// Default limit on nested expressions and statements, well within the native stack
+pub const MAX_NESTING: usize = 200;
 
 pub struct Parser<'a> {
     pub lexer: Lexer<'a>,
     pub symbol_table: SymbolTable,
+    pub code: CodeGenerator,
     pub current_id: Option<String>,
     pub current_class: Option<Class>,
     pub current_type: Option<Type>,
     pub current_value: i64,
-    pub arg_count: usize,
+    pub current_const: bool, // The declared variable itself is const, as in `const int x`
+    pub read_only_load: Option<(usize, String)>, // Code offset just past the last load of a const variable, and its name
+    pub operator_end: Option<usize>, // Code offset just past the last operator's result, which is never an lvalue
     pub local_offset: i32,
+    pub loops: Vec<LoopContext>, // Enclosing loops, innermost last
+    pub labels: HashMap<String, usize>, // Labels of the function being generated and their addresses
+    pub goto_fixups: Vec<(usize, String, (usize, usize))>, // Operands of gotos, with their label and span
+    pub return_type: Option<Type>, // Return type of the function being generated
+    pub expr_span: (usize, usize), // Source byte range of the last complete expression
+    pub indexed_array: Option<(String, usize)>, // Array named by the last primary expression
+    pub decayed_array: Option<(usize, Type)>, // Last array decayed to a pointer, with the code offset there
+    pub bounds_checks: bool, // Guard array indexing with BNDS
+    pub dump_symbols: bool, // Print the symbol table to stderr once compiled
+    pub extern_globals: HashSet<String>, // Globals only declared extern so far, in the first pass
+    pub structs: HashMap<String, Rc<StructDef>>, // Struct tags seen so far, defined or not
+    pub recover: bool, // Record errors in statements and declarations and carry on past them
+    pub diagnostics: Vec<ParseError>, // Errors recorded so far while recovering
+    pub max_depth: usize, // How deeply expressions and statements may nest
+    pub depth: usize,     // Current nesting of expressions and statements
     pub line: usize,
     pub second_pass: bool,
 }
@@ -29,147 +105,323 @@ impl<'a> Parser<'a> {
         Self {
             lexer,
             symbol_table: SymbolTable::new(),
+            code: CodeGenerator::new(),
             current_id: None,
             current_class: None,
             current_type: None,
             current_value: 0,
-            arg_count: 0,
+            current_const: false,
+            read_only_load: None,
+            operator_end: None,
             local_offset: 0,
+            loops: Vec::new(),
+            labels: HashMap::new(),
+            goto_fixups: Vec::new(),
+            return_type: None,
+            expr_span: (0, 0),
+            indexed_array: None,
+            decayed_array: None,
+            bounds_checks: false,
+            dump_symbols: false,
+            extern_globals: HashSet::new(),
+            structs: HashMap::new(),
+            recover: false,
+            diagnostics: Vec::new(),
+            max_depth: MAX_NESTING,
+            depth: 0,
             line: 1,
             second_pass: false,
         }
     }
 
+    // Prepare the parser to compile another source, reusing its allocations
+    pub fn reset(&mut self, src: &'a [u8]) {
+        self.lexer = Lexer::new(src);
+        self.lexer.next_token(); // Initialize with first token
+        self.symbol_table.reset();
+        self.code.clear();
+        self.current_id = None;
+        self.current_class = None;
+        self.current_type = None;
+        self.current_value = 0;
+        self.current_const = false;
+        self.read_only_load = None;
+        self.operator_end = None;
+        self.local_offset = 0;
+        self.loops.clear();
+        self.labels.clear();
+        self.goto_fixups.clear();
+        self.return_type = None;
+        self.expr_span = (0, 0);
+        self.indexed_array = None;
+        self.decayed_array = None;
+        self.extern_globals.clear();
+        self.clear_structs();
+        self.diagnostics.clear();
+        self.depth = 0;
+        self.line = 1;
+        self.second_pass = false;
+    }
+
+    // Forget every struct tag. A struct with a member pointing back to it keeps itself
+    // alive, so the layouts are dropped first
+    fn clear_structs(&mut self) {
+        for def in self.structs.values() {
+            def.layout.take();
+        }
+        self.structs.clear();
+    }
+
+    // Attach a source byte range to an error message
+    pub fn error_at(&self, msg: String, span: (usize, usize)) -> ParseError {
+        let line = self.lexer.src[..span.0.min(self.lexer.src.len())].iter().filter(|&&c| c == b'\n').count() + 1;
+        ParseError(Box::new(ErrorDetails {
+            message: format!("{} at bytes {}..{}", msg, span.0, span.1),
+            expected: Vec::new(),
+            found: self.lexer.peek_token().unwrap_or(Token::Eof),
+            line,
+            col: self.lexer.line_of(span.0).1,
+        }))
+    }
+
+    // Attach the current token's source range to an error message
+    pub fn error_here(&self, msg: &str) -> ParseError {
+        self.error_at(msg.to_string(), self.lexer.token_span())
+    }
+
+    // A missing-token error at the current token, recording what was expected instead
+    pub fn expected_error(&self, expected: Vec<TokenKind>, msg: &str) -> ParseError {
+        // Outside a conditional expression a colon may only follow a label, so a stray one
+        // is reported as such rather than as whatever else was missing
+        if self.lexer.peek_token() == Some(Token::Colon) && !expected.contains(&TokenKind::Token(Token::Colon)) {
+            return self.locate(ParseError::from("unexpected ':'")).expecting(expected);
+        }
+        self.locate(ParseError::from(msg)).expecting(expected)
+    }
+
+    // Consume `token`, or fail with `msg` if the current token is anything else
+    pub fn expect(&mut self, token: Token, msg: &str) -> Result<(), ParseError> {
+        if self.lexer.peek_token().as_ref() != Some(&token) {
+            return Err(self.expected_error(vec![TokenKind::Token(token)], msg));
+        }
+        self.lexer.next_token();
+        Ok(())
+    }
+
+    // Give an error that has no position yet the position of the current token
+    pub fn locate(&self, mut error: ParseError) -> ParseError {
+        if error.line == 0 {
+            error.0.found = self.lexer.peek_token().unwrap_or(Token::Eof);
+            error.0.line = self.lexer.line;
+            error.0.col = self.lexer.line_of(self.lexer.token_start).1;
+        }
+        error
+    }
+
+    // Run a nested parse of an expression or statement, failing cleanly once nesting passes
+    // `max_depth` instead of overflowing the native stack on pathological input
+    pub fn nested<T>(&mut self, what: &str, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(self.error_here(&format!("{} too deeply nested", what)));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Where a statement or declaration starts: the open scopes and loops to return to if it
+    // fails
+    pub fn recovery_point(&self) -> (usize, usize) {
+        (self.symbol_table.depth(), self.loops.len())
+    }
+
+    // Handle the result of a statement or declaration started at `point`. When recovering,
+    // an error is recorded and the rest of the construct skipped, and true is returned;
+    // otherwise the error is passed on
+    pub fn recover_from(&mut self, result: Result<(), ParseError>, point: (usize, usize)) -> Result<bool, ParseError> {
+        let err = match result {
+            Ok(()) => return Ok(false),
+            Err(err) if !self.recover => return Err(err),
+            Err(err) => self.locate(err),
+        };
+
+        // An unfinished block fails once for every enclosing one, at the same place
+        if !self.diagnostics.iter().any(|seen| (seen.line, seen.col) == (err.line, err.col)) {
+            self.diagnostics.push(err);
+        }
+        let (scopes, loops) = point;
+        while self.symbol_table.depth() > scopes {
+            self.symbol_table.exit_scope();
+        }
+        self.loops.truncate(loops);
+        self.skip_to_boundary(scopes == 1);
+        Ok(true)
+    }
+
+    // Skip past the next ';' or block at this level. Inside a block, a '}' closing it is
+    // left for the block; at the top level a stray one is skipped too
+    fn skip_to_boundary(&mut self, top_level: bool) {
+        let mut blocks = 0;
+        loop {
+            match self.lexer.peek_token() {
+                None | Some(Token::Eof) => return,
+                Some(Token::Semi) if blocks == 0 => {
+                    self.lexer.next_token();
+                    return;
+                }
+                Some(Token::OpenBrace) => blocks += 1,
+                Some(Token::CloseBrace) if blocks == 0 && !top_level => return,
+                Some(Token::CloseBrace) if blocks <= 1 => {
+                    self.lexer.next_token();
+                    return;
+                }
+                Some(Token::CloseBrace) => blocks -= 1,
+                _ => {}
+            }
+            self.lexer.next_token();
+        }
+    }
+
     // Add a string to the data segment and return its address
-    pub fn add_string(&mut self, s: &str) -> usize {
-        // Create a code generator to store the string
-        let mut code_gen = crate::codegen::CodeGenerator::new();
-        let addr = code_gen.store_string(s);
+    pub fn add_string(&mut self, s: &str) -> Result<usize, ParseError> {
+        let addr = self.code.store_string(s)?;
+
+        Ok(addr)
+    }
+
+    // Address of main, the program's entry point, once it is known to be a function;
+    // 0 until its body has been generated
+    pub fn entry_point(&self) -> Option<usize> {
+        match self.symbol_table.lookup("main") {
+            Some(symbol) if symbol.class == Class::Function => Some(symbol.val as usize),
+            _ => None,
+        }
+    }
+
+    // Compile the source into its code and data segments
+    pub fn parse(&mut self) -> Result<(Vec<i32>, Vec<u8>), ParseError> {
+        self.compile().map(|program| (program.text, program.data))
+    }
+
+    // Compile the source into a program, including the source line of every code word
+    pub fn compile(&mut self) -> Result<Program, ParseError> {
+        self.compile_program().map_err(|err| self.locate(err))
+    }
+
+    // Compile the source, carrying on past errors in statements and declarations so that
+    // as many as possible are reported. The program is only produced if there were none
+    pub fn compile_with_diagnostics(&mut self) -> (Option<Program>, Vec<ParseError>) {
+        self.recover = true;
+        let result = self.compile();
+        self.recover = false;
+
+        let mut errors = std::mem::take(&mut self.diagnostics);
+        match result {
+            Ok(program) if errors.is_empty() => (Some(program), errors),
+            Ok(_) => (None, errors),
+            Err(err) => {
+                // Compiling stops with the first recorded error once a pass is over
+                if !errors.contains(&err) {
+                    errors.push(err);
+                }
+                (None, errors)
+            }
+        }
+    }
 
-        println!("DEBUG: Stored string '{}' at address {}", s, addr);
-        addr
+    // Stop after a pass that recorded errors; the next pass would only repeat them
+    fn check_diagnostics(&self) -> Result<(), ParseError> {
+        match self.diagnostics.first() {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
     }
 
-    pub fn parse(&mut self) -> Result<(Vec<i32>, Vec<u8>), String> {
+    fn compile_program(&mut self) -> Result<Program, ParseError> {
         // Initialize symbol table with built-in types and functions
         self.symbol_table.init_builtins();
 
-        println!("DEBUG: First pass - building symbol table");
         // First pass: Parse all declarations to build the symbol table
-        let mut main_symbol = None;
-
-        // Store the current position to reset after first pass
-        let initial_pos = self.lexer.pos;
-
-        // Parse declarations at the global scope
         while let Some(token) = self.lexer.peek_token() {
             if token == Token::Eof {
                 break;
             }
 
             // Parse the next global declaration
-            self.parse_global_declaration()?;
-
-            // Check if we found main
-            if let Some(ref id) = self.current_id {
-                if id == "main" && matches!(self.current_class, Some(symbol_table::Class::Function)) {
-                    println!("DEBUG: Found main function in first pass");
-                    // Store the main symbol for later
-                    if let Some(symbol) = self.symbol_table.lookup("main") {
-                        println!("DEBUG: Main symbol found in symbol table: {:?}", symbol);
-                        main_symbol = Some(symbol.clone());
-                    } else {
-                        println!("DEBUG: Main symbol NOT found in symbol table!");
-                    }
-                }
-            }
+            let point = self.recovery_point();
+            let result = self.parse_global_declaration();
+            self.recover_from(result, point)?;
+            debug_assert_eq!(self.symbol_table.depth(), 1, "scope left open after a top-level declaration");
         }
 
-        println!("DEBUG: Symbol table after first pass: {:?}", self.symbol_table);
-        println!("DEBUG: All symbols after first pass:");
-        for (name, symbol) in self.symbol_table.all_symbols() {
-            println!("DEBUG: symbol: '{}' class: {:?}", name, symbol.class);
-        }
+        self.check_diagnostics()?;
 
-        // Check if we found main after the first pass
-        if main_symbol.is_none() {
-            // Try to look it up directly in the symbol table
-            if let Some(symbol) = self.symbol_table.lookup("main") {
-                println!("DEBUG: Found main function in symbol table after first pass");
-                main_symbol = Some(symbol.clone());
-            }
+        // Without main there is nothing to run; this also covers an empty or all-whitespace
+        // source, so don't bother generating code
+        if self.entry_point().is_none() {
+            return Err("no main function defined".into());
         }
 
         // Save the symbol table state after the first pass
         let saved_symbol_table = self.symbol_table.clone();
-        println!("DEBUG: All symbols before second pass:");
-        for (name, symbol) in self.symbol_table.all_symbols() {
-            println!("DEBUG: symbol: '{}' class: {:?}", name, symbol.class);
-        }
-
-        // Reset lexer position for second pass
-        self.lexer.pos = initial_pos;
-        self.lexer.next_token(); // Get the first token again
-
-        // Print the first 10 tokens for debug
-        println!("DEBUG: First 10 tokens after lexer reset for second pass:");
-        let mut preview_pos = self.lexer.pos;
-        for i in 0..10 {
-            let token = self.lexer.peek_token();
-            println!("DEBUG: token[{}]: {:?}", i, token);
-            if token == Some(Token::Eof) { break; }
-            self.lexer.next_token();
-        }
-        // Reset lexer again for actual codegen
-        self.lexer.pos = initial_pos;
-        self.lexer.next_token();
 
-        println!("DEBUG: Second pass - generating code");
-        // Create code generator
-        let mut code_gen = crate::codegen::CodeGenerator::new();
+        // Read the source again from the start for the second pass
+        self.lexer.reset_to(0);
+
+        // Discard anything emitted while building the symbol table
+        self.code.clear();
 
         // Restore the symbol table and set second pass flag
         self.symbol_table = saved_symbol_table;
         self.second_pass = true;
 
-        // Walk through all declarations
+        // Entry sequence calling main; its target is patched once main has been emitted
+        self.code.emit_prologue();
+
+        // Walk through all declarations, generating function bodies as we go
         while let Some(token) = self.lexer.peek_token() {
             if token == Token::Eof {
                 break;
             }
 
-            // Parse the declaration
-            self.parse_global_declaration()?;
-
-            // If it's a function, generate code for it
-            if let Some(id) = &self.current_id {
-                if let Some(Class::Function) = self.current_class {
-                    println!("DEBUG: Emitting function `{}` at addr {}", id, code_gen.text_offset);
-
-                    // Get the symbol for this function and clone it
-                    let sym = self.symbol_table.lookup(id)
-                        .ok_or_else(|| format!("Function {} not found in symbol table", id))?
-                        .clone();
+            let point = self.recovery_point();
+            let result = self.parse_global_declaration();
+            self.recover_from(result, point)?;
+            debug_assert_eq!(self.symbol_table.depth(), 1, "scope left open after a top-level declaration");
+        }
+        self.check_diagnostics()?;
 
-                    // Generate code for the function
-                    code_gen.gen_function(self, &sym)?;
-                }
+        // Resolve calls to functions that were emitted after the call site. A function that
+        // was only declared has no address, and calling 0 would re-enter the prologue
+        for (at, name) in std::mem::take(&mut self.code.call_fixups) {
+            let sym = self.symbol_table.lookup(&name)
+                .ok_or_else(|| format!("Function {} not found in symbol table", name))?;
+            if sym.val == 0 {
+                return Err(format!("undefined function '{}'", name).into());
             }
+            self.code.text[at] = sym.val as i32;
         }
 
-        // Ensure program exits cleanly
-        code_gen.emit(crate::codegen::Opcode::EXIT);
-
-        println!("DEBUG: Generated {} instructions", code_gen.text.len());
-        println!("DEBUG: Generated {} bytes of data", code_gen.data.len());
+        // The program starts by calling main, which must have a body by now
+        match self.entry_point() {
+            Some(entry) if entry > 0 => self.code.set_entry_point(entry),
+            _ => return Err("no main function defined".into()),
+        }
 
-        // Print out the generated instructions for debugging
-        println!("DEBUG: Generated instructions:");
-        for (i, instr) in code_gen.text.iter().enumerate() {
-            println!("DEBUG:   [{}]: {}", i, instr);
+        // Addresses are final now, so the table is worth showing
+        if self.dump_symbols {
+            eprint!("{}", self.symbol_table.dump());
         }
 
-        // Return both the code and data segments
-        Ok((code_gen.text, code_gen.data))
+        // Return the code and data segments along with their line table
+        let code = std::mem::take(&mut self.code);
+        Ok(Program { text: code.text, data: code.data, lines: code.lines })
+    }
+}
+
+impl Drop for Parser<'_> {
+    fn drop(&mut self) {
+        self.clear_structs();
     }
 }
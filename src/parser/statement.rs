@@ -1,70 +1,57 @@
-use crate::lexer::Token;
-use super::{Parser, symbol_table::{Symbol, Class}};
+use crate::codegen::Opcode;
+use crate::lexer::{Token, TokenKind};
+use super::{ParseError, Parser, symbol_table::{Symbol, Class}, types::{Type, MAX_OBJECT_SIZE}};
 
 impl<'a> Parser<'a> {
     // Parse a statement
-    pub fn parse_statement(&mut self) -> Result<(), String> {
-        if let Some(token) = self.lexer.peek_token() {
-            match token {
-                Token::Int | Token::CharType => {
-                    if self.second_pass {
-                        // Skip type
-                        self.lexer.next_token();
-
-                        // Skip identifier
-                        if let Some(Token::Id(_)) = self.lexer.peek_token() {
-                            self.lexer.next_token();
-
-                            // Skip initialization if present
-                            if let Some(Token::Assign) = self.lexer.peek_token() {
-                                self.lexer.next_token(); // Skip =
-                                if let Some(Token::Num(_)) = self.lexer.peek_token() {
-                                    self.lexer.next_token(); // Skip number
-                                }
-                            }
-
-                            // Skip semicolon
-                            if let Some(Token::Semi) = self.lexer.peek_token() {
-                                self.lexer.next_token();
-                            }
-                        }
-                        Ok(())
-                    } else {
-                        self.parse_local_declaration()
-                    }
-                },
-                Token::If => self.parse_if_statement(),
-                Token::While => self.parse_while_statement(),
-                Token::Return => self.parse_return_statement(),
-                Token::OpenBrace => self.parse_compound_statement(),
-                _ => self.parse_expression_statement(),
-            }
-        } else {
-            Err("Unexpected end of input while parsing statement".to_string())
+    pub fn parse_statement(&mut self) -> Result<(), ParseError> {
+        self.nested("statement", |parser| match parser.lexer.peek_token() {
+            Some(token) => match token {
+                _ if token.is_type_specifier() => parser.parse_local_declaration(),
+                Token::If => parser.parse_if_statement(),
+                Token::While => parser.parse_while_statement(),
+                Token::For => parser.parse_for_statement(),
+                Token::Break | Token::Continue => parser.parse_jump_statement(),
+                Token::Goto => parser.parse_goto_statement(),
+                Token::Id(_) if parser.lexer.peek_next_token() == Some(Token::Colon) => parser.parse_labeled_statement(),
+                Token::Return => parser.parse_return_statement(),
+                Token::OpenBrace => parser.parse_compound_statement(),
+                _ => parser.parse_expression_statement(),
+            },
+            None => Err("Unexpected end of input while parsing statement".into()),
+        })
+    }
+
+    // Parse goto statement: goto label;
+    pub fn parse_goto_statement(&mut self) -> Result<(), ParseError> {
+        self.lexer.next_token(); // Consume 'goto'
+        match self.lexer.peek_token() {
+            Some(Token::Id(_)) => self.lexer.next_token(),
+            _ => return Err(self.expected_error(vec![TokenKind::Identifier], "Expected label after 'goto'")),
         }
+        self.expect(Token::Semi, "Expected ';' after goto statement")
+    }
+
+    // Parse labeled statement: label: statement
+    pub fn parse_labeled_statement(&mut self) -> Result<(), ParseError> {
+        self.lexer.next_token(); // Consume the label
+        self.lexer.next_token(); // Consume ':'
+        self.parse_statement()
     }
 
     // Parse if statement: if (expression) statement [else statement]
-    pub fn parse_if_statement(&mut self) -> Result<(), String> {
+    pub fn parse_if_statement(&mut self) -> Result<(), ParseError> {
         // Consume 'if'
         self.lexer.next_token();
 
         // Expect '('
-        if let Some(Token::OpenParen) = self.lexer.peek_token() {
-            self.lexer.next_token();
-        } else {
-            return Err("Expected '(' after 'if'".to_string());
-        }
+        self.expect(Token::OpenParen, "Expected '(' after 'if'")?;
 
         // Parse condition
         self.parse_expression()?;
 
         // Expect ')'
-        if let Some(Token::CloseParen) = self.lexer.peek_token() {
-            self.lexer.next_token();
-        } else {
-            return Err("Expected ')' after if condition".to_string());
-        }
+        self.expect(Token::CloseParen, "Expected ')' after if condition")?;
 
         // Parse then-branch
         self.parse_statement()?;
@@ -79,26 +66,18 @@ impl<'a> Parser<'a> {
     }
 
     // Parse while statement: while (expression) statement
-    pub fn parse_while_statement(&mut self) -> Result<(), String> {
+    pub fn parse_while_statement(&mut self) -> Result<(), ParseError> {
         // Consume 'while'
         self.lexer.next_token();
 
         // Expect '('
-        if let Some(Token::OpenParen) = self.lexer.peek_token() {
-            self.lexer.next_token();
-        } else {
-            return Err("Expected '(' after 'while'".to_string());
-        }
+        self.expect(Token::OpenParen, "Expected '(' after 'while'")?;
 
         // Parse condition
         self.parse_expression()?;
 
         // Expect ')'
-        if let Some(Token::CloseParen) = self.lexer.peek_token() {
-            self.lexer.next_token();
-        } else {
-            return Err("Expected ')' after while condition".to_string());
-        }
+        self.expect(Token::CloseParen, "Expected ')' after while condition")?;
 
         // Parse body
         self.parse_statement()?;
@@ -106,239 +85,215 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    // Parse return statement: return [expression];
-    pub fn parse_return_statement(&mut self) -> Result<(), String> {
-        println!("DEBUG: Entering parse_return_statement, current token: {:?}", self.lexer.peek_token());
-        // Consume 'return'
+    // Parse for statement: for ([expression]; [expression]; [expression]) statement
+    pub fn parse_for_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'for'
         self.lexer.next_token();
-        println!("DEBUG: After consuming 'return', current token: {:?}", self.lexer.peek_token());
-
-        // Parse return expression (if any)
-        if self.lexer.peek_token() != Some(Token::Semi) {
-            println!("DEBUG: Parsing return expression");
-            self.current_class = None; // Reset class before parsing return expression
-            // Special case for numeric literals
-            if let Some(Token::Num(n)) = self.lexer.peek_token() {
-                println!("DEBUG: Found numeric literal in return: {}", n);
-                self.current_value = n;
-                self.current_type = Some(super::types::Type::Int);
-                self.lexer.next_token();
 
-				// Check for and consume semicolon right away
-				if let Some(Token::Semi) = self.lexer.peek_token() {
-					self.lexer.next_token();  // Consume the semicolon
-					return Ok(());
-				} else {
-					return Err(format!("Expected ';' after return statement, found: {:?}", self.lexer.peek_token()));
-				}
+        // Expect '('
+        self.expect(Token::OpenParen, "Expected '(' after 'for'")?;
 
-            } else {
+        // Initializer and condition, each ended by ';'
+        for _ in 0..2 {
+            if self.lexer.peek_token() != Some(Token::Semi) {
                 self.parse_expression()?;
             }
+            self.expect(Token::Semi, &format!("Expected ';' in for statement, found: {:?}", self.lexer.peek_token()))?;
         }
 
-        println!("DEBUG: After parsing return expression, current token: {:?}", self.lexer.peek_token());
+        // Post-expression
+        if self.lexer.peek_token() != Some(Token::CloseParen) {
+            self.parse_expression()?;
+        }
+
+        // Expect ')'
+        self.expect(Token::CloseParen, "Expected ')' after for clauses")?;
+
+        // Parse body
+        self.parse_statement()?;
+
+        Ok(())
+    }
+
+    // Parse break/continue statement: break; or continue;
+    pub fn parse_jump_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'break' or 'continue'
+        self.lexer.next_token();
 
-        // Expect ';'
         if let Some(Token::Semi) = self.lexer.peek_token() {
-            println!("DEBUG: Found semicolon after return, consuming it");
             self.lexer.next_token();
-            println!("DEBUG: After return statement, next token: {:?}", self.lexer.peek_token());
             Ok(())
         } else {
-            println!("DEBUG: Expected semicolon after return but found: {:?}", self.lexer.peek_token());
-            Err(format!("Expected ';' after return statement, found: {:?}", self.lexer.peek_token()))
+            Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], &format!("Expected ';' after break/continue, found: {:?}", self.lexer.peek_token())))
         }
     }
 
-    // Parse compound statement: { [statement]* }
-    pub fn parse_compound_statement(&mut self) -> Result<(), String> {
-        println!("DEBUG: Entering parse_compound_statement, current token: {:?}", self.lexer.peek_token());
+    // Parse return statement: return [expression];
+    pub fn parse_return_statement(&mut self) -> Result<(), ParseError> {
+        // Consume 'return'
+        self.lexer.next_token();
 
-        // Expect '{'
-        if let Some(Token::OpenBrace) = self.lexer.peek_token() {
+        // Parse return expression (if any)
+        if self.lexer.peek_token() != Some(Token::Semi) {
+            self.parse_expression()?;
+        }
+
+        // Expect ';'
+        if let Some(Token::Semi) = self.lexer.peek_token() {
             self.lexer.next_token();
+            Ok(())
         } else {
-            return Err("Expected '{' at start of compound statement".to_string());
+            Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], &format!("Expected ';' after return statement, found: {:?}", self.lexer.peek_token())))
         }
+    }
+
+    // Parse compound statement: { [statement]* }
+    pub fn parse_compound_statement(&mut self) -> Result<(), ParseError> {
+        // Expect '{'
+        self.expect(Token::OpenBrace, "Expected '{' at start of compound statement")?;
 
         // Enter new scope
-        println!("DEBUG: Entered a new scope");
         self.symbol_table.enter_scope();
 
         // Parse statements
-        println!("DEBUG: Parsing statements in compound statement");
         while let Some(token) = self.lexer.peek_token() {
             if token == Token::CloseBrace {
                 break;
             }
 
-            println!("DEBUG: Processing token in compound statement: {:?}", token);
-
-            match token {
-                Token::Int | Token::CharType => {
-                    println!("DEBUG: Parsing local declaration");
-                    if self.second_pass {
-                        // Skip type
-                        self.lexer.next_token();
-
-                        // Skip identifier
-                        if let Some(Token::Id(_)) = self.lexer.peek_token() {
-                            self.lexer.next_token();
-
-                            // Skip initialization if present
-                            if let Some(Token::Assign) = self.lexer.peek_token() {
-                                self.lexer.next_token(); // Skip =
-                                if let Some(Token::Num(_)) = self.lexer.peek_token() {
-                                    self.lexer.next_token(); // Skip number
-                                }
-                            }
-
-                            // Skip semicolon
-                            if let Some(Token::Semi) = self.lexer.peek_token() {
-                                self.lexer.next_token();
-                            }
-                        }
-                    } else {
-                        self.parse_local_declaration()?;
-                    }
-                },
-                Token::If => self.parse_if_statement()?,
-                Token::While => self.parse_while_statement()?,
-                Token::Return => {
-                    println!("DEBUG: Parsing return statement");
-                    self.parse_return_statement()?
-                },
-                Token::OpenBrace => self.parse_compound_statement()?,
-                _ => {
-                    println!("DEBUG: Parsing expression statement with token: {:?}", token);
-                    self.parse_expression_statement()?
-                },
+            let point = self.recovery_point();
+            let result = self.parse_statement();
+            if self.recover_from(result, point)? && self.lexer.peek_token() == Some(Token::Eof) {
+                break;
             }
         }
 
         // Expect '}'
         if let Some(Token::CloseBrace) = self.lexer.peek_token() {
-            println!("DEBUG: Found closing brace, exiting compound statement");
             self.lexer.next_token();
         } else {
-            return Err("Expected '}' at end of compound statement".to_string());
+            return Err(self.expected_error(vec![TokenKind::Token(Token::CloseBrace)], "Expected '}' at end of compound statement"));
         }
 
         // Exit scope
-        println!("DEBUG: Exited scope");
         self.symbol_table.exit_scope();
 
-        println!("DEBUG: Consumed closing brace, next token: {:?}", self.lexer.peek_token());
         Ok(())
     }
 
     // Parse expression statement: [expression];
-    pub fn parse_expression_statement(&mut self) -> Result<(), String> {
-        println!("DEBUG: Entering parse_expression_statement, current token: {:?}", self.lexer.peek_token());
-
+    pub fn parse_expression_statement(&mut self) -> Result<(), ParseError> {
         // Empty statement (just a semicolon)
         if let Some(Token::Semi) = self.lexer.peek_token() {
-            println!("DEBUG: Empty statement (just a semicolon)");
             self.lexer.next_token();
             return Ok(());
         }
 
         // Parse expression
-        println!("DEBUG: Parsing expression in statement");
-        match self.parse_expression() {
-            Ok(_) => {
-                println!("DEBUG: After parsing expression, current token: {:?}", self.lexer.peek_token());
-
-                // Expect ';'
-                match self.lexer.peek_token() {
-                    Some(Token::Semi) => {
-                        println!("DEBUG: Found semicolon, consuming it");
-                        self.lexer.next_token();
-                        println!("DEBUG: After semicolon, next token: {:?}", self.lexer.peek_token());
-                        Ok(())
-                    },
-                    Some(other) => {
-                        println!("DEBUG: Expected semicolon but found: {:?}", other);
-                        Err(format!("Expected ';' after expression statement, found: {:?}", other))
-                    },
-                    None => {
-                        println!("DEBUG: Unexpected end of input after expression");
-                        Err("Unexpected end of input after expression".to_string())
-                    }
-                }
+        self.parse_expression()?;
+
+        // Expect ';'
+        match self.lexer.peek_token() {
+            Some(Token::Semi) => {
+                self.lexer.next_token();
+                Ok(())
+            },
+            Some(other) => {
+                Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], &format!("Expected ';' after expression statement, found: {:?}", other)))
             },
-            Err(e) => {
-                println!("DEBUG: Error parsing expression: {}", e);
-                Err(e)
+            None => {
+                Err("Unexpected end of input after expression".into())
             }
         }
     }
 
     // Parse a local variable declaration
-    pub fn parse_local_declaration(&mut self) -> Result<(), String> {
-        println!("DEBUG: Entering parse_local_declaration, current token: {:?}", self.lexer.peek_token());
-
+    pub fn parse_local_declaration(&mut self) -> Result<(), ParseError> {
         // Parse type specifier
         self.parse_type()?; // Using the public method from declaration.rs
-        println!("DEBUG: After parse_type, current token: {:?}", self.lexer.peek_token());
 
         // Parse declarator
-        if let Some(Token::Id(id)) = self.lexer.peek_token() {
-            let var_name = id.clone();
-            println!("DEBUG: Found local variable name: {}", var_name);
+        let declarator = match self.lexer.peek_token() {
+            Some(Token::Id(id)) => {
+                let span = self.lexer.token_span();
+                self.lexer.next_token();
+                self.parse_array_declarator()?;
+                Some((id, span))
+            }
+            Some(Token::OpenParen) => Some(self.parse_function_pointer_declarator()?),
+            _ => None,
+        };
+        if let Some((var_name, span)) = declarator {
             self.current_id = Some(var_name.clone()); // Set current_id for code generation
-            self.lexer.next_token();
+
+            // Reserve whole stack words below bp; the first local sits at bp - 1
+            let typ = self.current_type.clone().ok_or("Missing variable type")?;
+            if typ.scalar() == &Type::Void {
+                return Err(format!("variable '{}' declared void", var_name).into());
+            }
+            if !typ.is_complete() {
+                return Err(self.error_at(format!("variable '{}' has incomplete type '{}'", var_name, typ), span));
+            }
+            self.local_offset += (typ.size() + 3) / 4;
+            if self.local_offset as i64 * 4 > MAX_OBJECT_SIZE {
+                return Err(self.error_at("local variables take up too much stack space".to_string(), span));
+            }
+            let offset = -self.local_offset;
 
             // Create symbol for local variable
             let symbol = Symbol {
                 name: var_name.clone(),
                 class: Class::Local,
-                typ: self.current_type.clone().unwrap(),
+                typ: typ.clone(),
                 val: 0,
-                offset: self.local_offset,
+                offset,
+                span,
+                is_const: self.current_const,
             };
 
-            // Update local offset for next variable
-            self.local_offset += self.current_type.as_ref().unwrap().size();
-
             // Add to symbol table
-            println!("DEBUG: Adding local variable '{}' to symbol table", var_name);
             self.symbol_table.add_symbol(symbol)?;
 
             // Handle initialization if present
             if let Some(Token::Assign) = self.lexer.peek_token() {
-                println!("DEBUG: Found initialization for local variable");
                 self.lexer.next_token(); // Consume '='
-
-                // Parse initializer expression
-                if let Some(Token::Num(n)) = self.lexer.peek_token() {
-                    println!("DEBUG: Initializing with numeric literal: {}", n);
-                    self.current_value = n;
-                    self.lexer.next_token();
-
-                    // Store the value in the symbol table
-                    self.symbol_table.update_symbol(&var_name, |symbol| {
-                        symbol.val = n;
-                    })?;
+                if typ.is_array() || matches!(typ, Type::Struct(_)) {
+                    // The stack is not cleared, so every element is stored, zeros included
+                    let mut values = Vec::new();
+                    self.parse_initializer(&typ, &mut values)?;
+                    let scalar = typ.scalar().clone();
+                    for (i, value) in values.into_iter().enumerate() {
+                        let at = i as i32 * scalar.size();
+                        self.code.emit_imm(Opcode::LEA, offset);
+                        if at > 0 {
+                            self.code.emit(Opcode::PSH);
+                            self.code.emit_imm(Opcode::IMM, at);
+                            self.code.emit(Opcode::ADD);
+                        }
+                        self.code.emit(Opcode::PSH);
+                        self.code.emit_imm(Opcode::IMM, value as i32);
+                        self.code.emit_store(&scalar);
+                    }
                 } else {
-                    println!("DEBUG: Initializing with expression");
-                    self.parse_expression()?;
+                    // Store the initializer through the variable's address
+                    self.code.emit_imm(Opcode::LEA, offset);
+                    self.code.emit(Opcode::PSH);
+                    let value = self.parse_expression()?;
+                    self.check_value()?;
+                    self.check_assignable(&typ, &value.typ)?;
+                    self.code.emit_store(&typ);
                 }
             }
 
             // Expect semicolon
             if let Some(Token::Semi) = self.lexer.peek_token() {
-                println!("DEBUG: Found semicolon after local declaration");
                 self.lexer.next_token();
                 Ok(())
             } else {
-                println!("DEBUG: Expected semicolon after local declaration but found: {:?}", self.lexer.peek_token());
-                Err("Expected ';' after variable declaration".to_string())
+                Err(self.expected_error(vec![TokenKind::Token(Token::Semi)], "Expected ';' after variable declaration"))
             }
         } else {
-            println!("DEBUG: Expected identifier in local declaration but found: {:?}", self.lexer.peek_token());
-            Err("Expected identifier in local declaration".to_string())
+            Err(self.expected_error(vec![TokenKind::Identifier], "Expected identifier in local declaration"))
         }
     }
 }
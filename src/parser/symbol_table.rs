@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use super::types::Type;
+use crate::vm::OPEN_FLAGS;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Class {
@@ -7,6 +8,7 @@ pub enum Class {
     Local,
     Function,
     Sys,
+    Num, // Enumeration constant; its value is in `val`
 }
 
 #[derive(Debug, Clone)]
@@ -16,19 +18,34 @@ pub struct Symbol {
     pub typ: Type,
     pub val: i64,
     pub offset: i32, // Offset for local variables or function parameters
+    pub span: (usize, usize), // Source byte range of the declaring identifier
+    pub is_const: bool, // Declared const, so it cannot be assigned to
+}
+
+impl Symbol {
+    // Whether the symbol is a function taking arguments beyond its fixed parameters
+    pub fn is_variadic(&self) -> bool {
+        matches!(self.typ.as_function(), Some(Type::Func { variadic: true, .. }))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SymbolTable {
-    symbols: HashMap<String, Symbol>,
-    scopes: Vec<Vec<String>>, // Stack of scopes (each scope is a list of symbol names)
+    symbols: HashMap<String, Vec<Symbol>>, // Declarations of each name, innermost last
+    scopes: Vec<HashSet<String>>,          // Stack of scopes (each scope is a set of symbol names)
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         Self {
             symbols: HashMap::new(),
-            scopes: vec![vec![]], // Initialize with global scope
+            scopes: vec![HashSet::new()], // Initialize with global scope
         }
     }
 
@@ -37,20 +54,71 @@ impl SymbolTable {
         self.add_type("int", Type::Int);
         self.add_type("char", Type::Char);
 
-        // Add system functions
-        self.add_sys_func("open", Type::Int);
-        self.add_sys_func("read", Type::Int);
-        self.add_sys_func("close", Type::Int);
-        self.add_sys_func("printf", Type::Int);
-        self.add_sys_func("malloc", Type::Ptr(Box::new(Type::Int)));
-        self.add_sys_func("free", Type::Int);
-        self.add_sys_func("memset", Type::Int);
-        self.add_sys_func("memcmp", Type::Int);
-        self.add_sys_func("exit", Type::Int);
+        // Add system functions with their signatures, so that calls to them are checked
+        let char_ptr = Type::Char.to_pointer();
+        let void_ptr = Type::Void.to_pointer();
+        self.add_sys_func("open", Type::Int, vec![char_ptr.clone(), Type::Int], false);
+        self.add_sys_func("read", Type::Int, vec![Type::Int, void_ptr.clone(), Type::Int], false);
+        self.add_sys_func("close", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("printf", Type::Int, vec![char_ptr.clone()], true);
+        self.add_sys_func("malloc", void_ptr.clone(), vec![Type::Int], false);
+        self.add_sys_func("free", Type::Int, vec![void_ptr.clone()], false);
+        self.add_sys_func("realloc", void_ptr.clone(), vec![void_ptr.clone(), Type::Int], false);
+        self.add_sys_func("memset", void_ptr.clone(), vec![void_ptr.clone(), Type::Int, Type::Int], false);
+        self.add_sys_func("memcmp", Type::Int, vec![void_ptr.clone(), void_ptr, Type::Int], false);
+        self.add_sys_func("exit", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("assert", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("getchar", Type::Int, Vec::new(), false);
+        self.add_sys_func("putchar", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("puts", Type::Int, vec![char_ptr], false);
+        self.add_sys_func("isdigit", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("isalpha", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("isspace", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("abs", Type::Int, vec![Type::Int], false);
+        self.add_sys_func("min", Type::Int, vec![Type::Int, Type::Int], false);
+        self.add_sys_func("max", Type::Int, vec![Type::Int, Type::Int], false);
+
+        // Add the flags accepted by open
+        for (name, value) in OPEN_FLAGS {
+            self.add_constant(name, value as i64);
+        }
     }
 
+    // Drop every user symbol and scope, keeping the builtins and the table's allocation
+    pub fn reset(&mut self) {
+        self.symbols.retain(|name, declarations| {
+            // Builtins are always the outermost declaration of their name
+            declarations.truncate(1);
+            let symbol = &declarations[0];
+            matches!(symbol.class, Class::Sys | Class::Num) || (symbol.class == Class::Global && matches!(name.as_str(), "int" | "char"))
+        });
+        self.scopes.truncate(1);
+        let symbols = &self.symbols;
+        self.scopes[0].retain(|name| symbols.contains_key(name));
+    }
+
+    // Every visible symbol, i.e. the innermost declaration of each name
     pub fn all_symbols(&self) -> impl Iterator<Item = (&String, &Symbol)> {
-        self.symbols.iter()
+        self.symbols.iter().filter_map(|(name, declarations)| declarations.last().map(|symbol| (name, symbol)))
+    }
+
+    // One line per visible symbol, sorted by name: name, class, type, and its address,
+    // frame offset or value
+    pub fn dump(&self) -> String {
+        let mut symbols: Vec<_> = self.all_symbols().map(|(_, symbol)| symbol).collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut out = String::new();
+        for symbol in symbols {
+            let location = match symbol.class {
+                Class::Local => format!("offset {}", symbol.offset),
+                Class::Global | Class::Function => format!("addr {}", symbol.val),
+                Class::Num => format!("value {}", symbol.val),
+                Class::Sys => "builtin".to_string(),
+            };
+            let class = format!("{:?}", symbol.class);
+            out.push_str(&format!("{:<16} {:<8} {:<20} {}\n", symbol.name, class, symbol.typ.to_string(), location));
+        }
+        out
     }
 
     fn add_type(&mut self, name: &str, typ: Type) {
@@ -60,68 +128,119 @@ impl SymbolTable {
             typ,
             val: 0,
             offset: 0,
+            span: (0, 0),
+            is_const: false,
         };
-        self.symbols.insert(name.to_string(), symbol);
-        self.scopes[0].push(name.to_string());
+        self.insert_builtin(symbol);
     }
 
-    fn add_sys_func(&mut self, name: &str, ret_type: Type) {
+    fn add_sys_func(&mut self, name: &str, ret: Type, params: Vec<Type>, variadic: bool) {
         let symbol = Symbol {
             name: name.to_string(),
             class: Class::Sys,
-            typ: ret_type,
+            typ: Type::Func { ret: Box::new(ret), params, variadic },
             val: 0, // Will be set to the appropriate system call ID
             offset: 0,
+            span: (0, 0),
+            is_const: false,
         };
-        self.symbols.insert(name.to_string(), symbol);
-        self.scopes[0].push(name.to_string());
+        self.insert_builtin(symbol);
+    }
+
+    fn add_constant(&mut self, name: &str, val: i64) {
+        let symbol = Symbol {
+            name: name.to_string(),
+            class: Class::Num,
+            typ: Type::Int,
+            val,
+            offset: 0,
+            span: (0, 0),
+            is_const: false,
+        };
+        self.insert_builtin(symbol);
+    }
+
+    // Builtins may be registered again after a reset; keep one entry each
+    fn insert_builtin(&mut self, symbol: Symbol) {
+        if !self.symbols.contains_key(&symbol.name) {
+            self.scopes[0].insert(symbol.name.clone());
+            self.symbols.insert(symbol.name.clone(), vec![symbol]);
+        }
     }
 
     pub fn enter_scope(&mut self) {
-        self.scopes.push(vec![]);
+        self.scopes.push(HashSet::new());
     }
 
     pub fn exit_scope(&mut self) {
         if let Some(scope) = self.scopes.pop() {
-            // Remove all symbols in the current scope
+            // Remove the declarations made in this scope, uncovering any they shadowed
             for name in scope {
-                self.symbols.remove(&name);
+                if let Some(declarations) = self.symbols.get_mut(&name) {
+                    declarations.pop();
+                    if declarations.is_empty() {
+                        self.symbols.remove(&name);
+                    }
+                }
             }
         }
     }
 
+    // Number of open scopes; 1 means only the global scope
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
     pub fn add_symbol(&mut self, symbol: Symbol) -> Result<(), String> {
         let name = symbol.name.clone();
 
-        // Check if symbol already exists in current scope
+        // Redefinition in the same scope is an error; shadowing an outer scope is fine
         if self.lookup_current_scope(&name).is_some() {
             return Err(format!("Symbol '{}' already defined in current scope", name));
         }
 
         // Add symbol to table and current scope
-        self.symbols.insert(name.clone(), symbol);
+        self.symbols.entry(name.clone()).or_default().push(symbol);
         if let Some(scope) = self.scopes.last_mut() {
-            scope.push(name);
+            scope.insert(name);
         }
 
         Ok(())
     }
 
+    // Find the innermost visible declaration of a name
     pub fn lookup(&self, name: &str) -> Option<&Symbol> {
-        self.symbols.get(name)
+        self.symbols.get(name).and_then(|declarations| declarations.last())
     }
 
     pub fn lookup_current_scope(&self, name: &str) -> Option<&Symbol> {
-        if let Some(scope) = self.scopes.last() {
-            if scope.contains(&name.to_string()) {
-                return self.symbols.get(name);
+        match self.scopes.last() {
+            Some(scope) if scope.contains(name) => self.lookup(name),
+            _ => None,
+        }
+    }
+
+    // Remove a global symbol while no other scope is open, returning it
+    pub fn remove_global(&mut self, name: &str) -> Option<Symbol> {
+        if self.scopes.len() != 1 || !self.scopes[0].remove(name) {
+            return None;
+        }
+        self.symbols.remove(name).and_then(|mut declarations| declarations.pop())
+    }
+
+    // Update the outermost (global) declaration of a name, even while it is shadowed
+    pub fn update_global(&mut self, name: &str, update_fn: impl FnOnce(&mut Symbol)) -> Result<(), String> {
+        match self.symbols.get_mut(name).and_then(|declarations| declarations.first_mut()) {
+            Some(symbol) => {
+                update_fn(symbol);
+                Ok(())
             }
+            None => Err(format!("Symbol '{}' not found", name)),
         }
-        None
     }
 
     pub fn update_symbol(&mut self, name: &str, update_fn: impl FnOnce(&mut Symbol)) -> Result<(), String> {
-        if let Some(symbol) = self.symbols.get_mut(name) {
+        if let Some(symbol) = self.symbols.get_mut(name).and_then(|declarations| declarations.last_mut()) {
             update_fn(symbol);
             Ok(())
         } else {
@@ -129,3 +248,41 @@ impl SymbolTable {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(name: &str, class: Class, offset: i32) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            class,
+            typ: Type::Int,
+            val: 0,
+            offset,
+            span: (0, 0),
+            is_const: false,
+        }
+    }
+
+    #[test]
+    fn test_inner_scope_shadows_outer() {
+        let mut table = SymbolTable::new();
+        table.add_symbol(variable("x", Class::Global, 0)).unwrap();
+
+        table.enter_scope();
+        table.add_symbol(variable("x", Class::Local, -1)).unwrap();
+        assert_eq!(table.lookup("x").unwrap().class, Class::Local);
+
+        table.exit_scope();
+        assert_eq!(table.lookup("x").unwrap().class, Class::Global);
+    }
+
+    #[test]
+    fn test_redefinition_in_same_scope() {
+        let mut table = SymbolTable::new();
+        table.enter_scope();
+        table.add_symbol(variable("x", Class::Local, -1)).unwrap();
+        assert!(table.add_symbol(variable("x", Class::Local, -2)).is_err());
+    }
+}
@@ -1,35 +1,239 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// Largest object a program may declare, which keeps sizes and frame offsets well within an i32
+pub const MAX_OBJECT_SIZE: i64 = 1 << 30;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
-    Char,
+    Void,
+    Char,  // Unsigned, like C4's char
+    SChar, // signed char
     Int,
     Ptr(Box<Type>),
+    Array(Box<Type>, usize), // Element type and element count
+    Func { ret: Box<Type>, params: Vec<Type>, variadic: bool },
+    Struct(Rc<StructDef>),
+}
+
+// A struct type, shared by every use of its tag. The layout is filled in once the
+// definition has been parsed, so members can point to the struct being defined
+pub struct StructDef {
+    pub name: String,
+    pub layout: RefCell<Option<StructLayout>>, // None while the struct is incomplete
+}
+
+// Members are laid out in declaration order with natural alignment: each one starts at a
+// multiple of its own alignment, and the size is padded to a multiple of the strictest
+#[derive(Debug, Clone)]
+pub struct StructLayout {
+    pub members: Vec<Member>,
+    pub size: i32,
+    pub align: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub typ: Type,
+    pub offset: i32, // Bytes from the start of the struct
+}
+
+impl StructDef {
+    // A struct known only by its tag so far
+    pub fn new(name: &str) -> Rc<Self> {
+        Rc::new(StructDef { name: name.to_string(), layout: RefCell::new(None) })
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.layout.borrow().is_some()
+    }
+
+    // Lay out the members, completing the struct
+    // Fails, leaving the struct incomplete, when it would be larger than MAX_OBJECT_SIZE
+    pub fn define(&self, members: Vec<(String, Type)>) -> Result<(), String> {
+        let mut offset: i64 = 0;
+        let mut align = 1;
+        let mut laid_out = Vec::new();
+        for (name, typ) in members {
+            let member_align = typ.align();
+            offset = (offset + member_align as i64 - 1) / member_align as i64 * member_align as i64;
+            let member = Member { name, offset: offset as i32, typ };
+            offset += member.typ.size() as i64;
+            if offset > MAX_OBJECT_SIZE {
+                return Err(format!("'struct {}' is too large", self.name));
+            }
+            align = align.max(member_align);
+            laid_out.push(member);
+        }
+        let size = (offset as i32 + align - 1) / align * align;
+        *self.layout.borrow_mut() = Some(StructLayout { members: laid_out, size, align });
+        Ok(())
+    }
+
+    // The member called `name`, if the struct is complete and has one
+    pub fn member(&self, name: &str) -> Option<Member> {
+        let layout = self.layout.borrow();
+        layout.as_ref()?.members.iter().find(|member| member.name == name).cloned()
+    }
+}
+
+// Tags name one struct per program, so they identify it
+impl PartialEq for StructDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+// Members may refer back to the struct itself, so only the tag is shown
+impl fmt::Debug for StructDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "struct {}", self.name)
+    }
 }
 
 impl Type {
     pub fn size(&self) -> i32 {
         match self {
-            Type::Char => 1,
+            Type::Void => 1, // Only reached through void *, which steps by bytes
+            Type::Char | Type::SChar => 1,
             Type::Int => 4,
             Type::Ptr(_) => 4, // Pointers are 4 bytes on 32-bit systems
+            Type::Array(elem, len) => elem.size() * *len as i32,
+            Type::Func { .. } => 1, // Only reached through function pointers, like void *
+            Type::Struct(def) => def.layout.borrow().as_ref().map_or(0, |layout| layout.size),
+        }
+    }
+
+    // The boundary a value of this type is placed on inside a struct
+    pub fn align(&self) -> i32 {
+        match self {
+            Type::Array(elem, _) => elem.align(),
+            Type::Struct(def) => def.layout.borrow().as_ref().map_or(1, |layout| layout.align),
+            other => other.size(),
+        }
+    }
+
+    // Whether objects of this type can be created: not void, and not a struct that has
+    // only been declared
+    pub fn is_complete(&self) -> bool {
+        match self.scalar() {
+            Type::Void => false,
+            Type::Struct(def) => def.is_complete(),
+            _ => true,
         }
     }
 
     pub fn is_primitive(&self) -> bool {
-        matches!(self, Type::Char | Type::Int)
+        matches!(self, Type::Char | Type::SChar | Type::Int)
     }
 
     pub fn is_pointer(&self) -> bool {
         matches!(self, Type::Ptr(_))
     }
 
+    pub fn is_array(&self) -> bool {
+        matches!(self, Type::Array(..))
+    }
+
     pub fn get_base_type(&self) -> Option<&Type> {
         match self {
-            Type::Ptr(base) => Some(base),
+            Type::Ptr(base) | Type::Array(base, _) => Some(base),
+            _ => None,
+        }
+    }
+
+    // The scalar type at the bottom of (possibly nested) arrays
+    pub fn scalar(&self) -> &Type {
+        match self {
+            Type::Array(elem, _) => elem.scalar(),
+            other => other,
+        }
+    }
+
+    // In expressions an array stands for a pointer to its first element, and a function
+    // for a pointer to itself
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(elem, _) => Type::Ptr(elem.clone()),
+            Type::Func { .. } => self.to_pointer(),
+            other => other.clone(),
+        }
+    }
+
+    // The function type called through this type, if it is a function or points to one
+    pub fn as_function(&self) -> Option<&Type> {
+        match self {
+            Type::Func { .. } => Some(self),
+            Type::Ptr(base) if matches!(**base, Type::Func { .. }) => Some(base),
+            _ => None,
+        }
+    }
+
+    // The type a call through this type produces
+    pub fn return_type(&self) -> Option<&Type> {
+        match self.as_function() {
+            Some(Type::Func { ret, .. }) => Some(ret),
             _ => None,
         }
     }
 
-    pub fn to_pointer(self) -> Self {
-        Type::Ptr(Box::new(self))
+    pub fn to_pointer(&self) -> Self {
+        Type::Ptr(Box::new(self.clone()))
+    }
+
+    // The type an operand takes part in arithmetic as: char is promoted to int
+    pub fn promote(&self) -> Type {
+        match self {
+            Type::Char | Type::SChar => Type::Int,
+            other => other.decay(),
+        }
+    }
+
+    // Whether a value of type `other` can be used where `self` is expected without a cast:
+    // arithmetic types mix freely, and pointers must agree on their target unless either
+    // one is void *
+    pub fn is_compatible(&self, other: &Type) -> bool {
+        match (self.decay(), other.decay()) {
+            (Type::Ptr(a), Type::Ptr(b)) => *a == Type::Void || *b == Type::Void || a == b,
+            (a, b) => (a.is_primitive() && b.is_primitive()) || a == b,
+        }
+    }
+}
+
+impl Type {
+    // Parameter types of a function type as written in C, e.g. "int, char *"
+    fn param_list(&self) -> String {
+        match self {
+            Type::Func { params, variadic, .. } => {
+                let mut list: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+                if *variadic {
+                    list.push("...".to_string());
+                } else if list.is_empty() {
+                    list.push("void".to_string());
+                }
+                list.join(", ")
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Char => write!(f, "char"),
+            Type::SChar => write!(f, "signed char"),
+            Type::Int => write!(f, "int"),
+            Type::Ptr(base) => match &**base {
+                Type::Func { ret, .. } => write!(f, "{} (*)({})", ret, base.param_list()),
+                _ => write!(f, "{} *", base),
+            },
+            Type::Array(elem, len) => write!(f, "{}[{}]", elem, len),
+            Type::Func { ret, .. } => write!(f, "{} ({})", ret, self.param_list()),
+            Type::Struct(def) => write!(f, "struct {}", def.name),
+        }
     }
 }
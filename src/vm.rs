@@ -1,45 +1,1210 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::codegen::{disassemble_instruction, Opcode, Program, NUM_OPCODES};
+
+// Size of a machine word in bytes
+pub const WORD: usize = 4;
+
+// Flags for open, with the values Linux uses; the low two bits are the access mode
+pub const O_RDONLY: i32 = 0;
+pub const O_WRONLY: i32 = 1;
+pub const O_RDWR: i32 = 2;
+pub const O_CREAT: i32 = 0o100;
+pub const O_TRUNC: i32 = 0o1000;
+pub const O_APPEND: i32 = 0o2000;
+
+// The open flags as compiled programs see them
+pub const OPEN_FLAGS: [(&str, i32); 6] = [
+    ("O_RDONLY", O_RDONLY),
+    ("O_WRONLY", O_WRONLY),
+    ("O_RDWR", O_RDWR),
+    ("O_CREAT", O_CREAT),
+    ("O_TRUNC", O_TRUNC),
+    ("O_APPEND", O_APPEND),
+];
+
+// Translate C open flags into the equivalent OpenOptions
+fn open_options(flags: i32) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    match flags & 3 {
+        O_WRONLY => options.write(true),
+        O_RDWR => options.read(true).write(true),
+        _ => options.read(true),
+    };
+    options
+        .append(flags & O_APPEND != 0)
+        .create(flags & O_CREAT != 0)
+        .truncate(flags & O_TRUNC != 0);
+    options
+}
+
+// How ADD/SUB/MUL/DIV/MOD/SHL treat results that do not fit in an i32, and how SHL/SHR treat
+// shift counts outside 0..31
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArithmeticMode {
+    #[default]
+    Wrapping, // Two's complement wrap-around, like C on common targets; shift counts are masked to 0..31
+    Checked,  // Overflow and out-of-range shift counts are reported as runtime errors
+}
+
+// What went wrong with an array or memory access
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessKind {
+    Memory,                           // Outside the VM's memory
+    Heap,                             // Inside the heap but not in a live block
+    Array { name: String, len: i32 }, // A checked array index
+}
+
+// Runtime errors reported by `VM::run`
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    StackOverflow,
+    StackUnderflow { op: Opcode },
+    DivByZero,
+    InvalidOpcode { opcode: i32, at: usize },
+    MissingOperand { at: usize },
+    OutOfBounds { kind: AccessKind, index: i64 },
+    Overflow { op: Opcode, lhs: i32, rhs: i32 },
+    InvalidFree(usize),
+    InvalidRealloc(usize),
+    AssertionFailed { at: usize },
+    CycleLimit(u64),
+    Syscall(String),                              // A system function failed or was misused
+    Located { line: usize, error: Box<VmError> }, // An error raised by code from a source line
+}
+
+impl VmError {
+    // The error itself, without the source line it was raised from
+    pub fn kind(&self) -> &VmError {
+        match self {
+            VmError::Located { error, .. } => error.kind(),
+            error => error,
+        }
+    }
+}
+
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::StackOverflow => write!(f, "Stack overflow"),
+            VmError::StackUnderflow { op } => write!(f, "Stack underflow in {:?}", op),
+            VmError::DivByZero => write!(f, "Division by zero"),
+            VmError::InvalidOpcode { opcode, at } => write!(f, "Invalid opcode {} at {}", opcode, at),
+            VmError::MissingOperand { at } => write!(f, "Missing operand at {}", at),
+            VmError::OutOfBounds { kind: AccessKind::Memory, index } => {
+                write!(f, "Memory access out of bounds at address {}", index)
+            }
+            VmError::OutOfBounds { kind: AccessKind::Heap, index } => write!(f, "invalid heap access at address {}", index),
+            VmError::OutOfBounds { kind: AccessKind::Array { name, len }, index } => {
+                write!(f, "array index {} out of bounds for '{}' of length {}", index, name, len)
+            }
+            VmError::Overflow { op, lhs, rhs } => write!(f, "Arithmetic overflow in {:?} ({}, {})", op, lhs, rhs),
+            VmError::InvalidFree(addr) => write!(f, "invalid free of address {}", addr),
+            VmError::InvalidRealloc(addr) => write!(f, "invalid realloc of address {}", addr),
+            VmError::AssertionFailed { at } => write!(f, "assertion failed at {}", at),
+            VmError::CycleLimit(limit) => write!(f, "Instruction limit of {} exceeded", limit),
+            VmError::Syscall(msg) => write!(f, "{}", msg),
+            VmError::Located { line, error } => write!(f, "{} (line {})", error, line),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<std::io::Error> for VmError {
+    fn from(err: std::io::Error) -> Self {
+        VmError::Syscall(err.to_string())
+    }
+}
+
+// Settings for compiling and running a program
+#[derive(Debug, Clone)]
+pub struct VmConfig {
+    pub stack_size: usize,
+    pub debug_mode: bool,
+    pub arithmetic: ArithmeticMode,
+    pub bounds_checks: bool, // Trap on array indexes outside the declared length
+    pub profile: bool,       // Count how often each opcode executes
+    pub max_cycles: Option<u64>, // Stop with an error after this many instructions
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            stack_size: 1024 * 1024,
+            debug_mode: false,
+            arithmetic: ArithmeticMode::default(),
+            bounds_checks: false,
+            profile: false,
+            max_cycles: None,
+        }
+    }
+}
 
 pub struct VM {
     code: Vec<i32>,
     data: Vec<u8>,
-    stack: VecDeque<i32>,
-    memory: Vec<i32>,
+    lines: Vec<usize>,            // Source line of each code word, when known
+    memory: Vec<u8>,              // Data segment, then heap, then stack (growing down)
+    heap_start: usize,            // First heap address, just past the data segment
+    heap_top: usize,              // Next free heap address
+    allocations: BTreeMap<usize, usize>, // Live heap blocks: start address -> size
+    files: HashMap<i32, File>,    // Open file descriptors
+    next_fd: i32,
+    ax: i32,
     pc: usize,
     sp: usize,
+    bp: usize,
+    arithmetic: ArithmeticMode,
+    trace: Option<Vec<String>>,   // Captured trace lines, when enabled
+    profile: Option<Box<[u64; NUM_OPCODES]>>, // Executions per opcode, when enabled
+    max_cycles: Option<u64>,      // Instruction budget for a run, when limited
+    input: Box<dyn Read>,         // Where getchar reads from; stdin by default
+    output: Box<dyn Write>,       // Where printf, putchar and puts write; stdout by default
     debug_mode: bool,
 }
 
+// Round an address up to the next word boundary
+fn align(addr: usize) -> usize {
+    (addr + WORD - 1) & !(WORD - 1)
+}
+
 impl VM {
     pub fn new(code: Vec<i32>, data: Vec<u8>, stack_size: usize, debug_mode: bool) -> Self {
+        // Address 0 stays reserved so that a null pointer never names the heap
+        let heap_start = align(data.len()).max(WORD);
         VM {
             code,
             data,
-            stack: VecDeque::new(),
-            memory: vec![0; stack_size],
+            lines: Vec::new(),
+            memory: vec![0; heap_start + stack_size],
+            heap_start,
+            heap_top: heap_start,
+            allocations: BTreeMap::new(),
+            files: HashMap::new(),
+            next_fd: 3,
+            ax: 0,
             pc: 0,
             sp: 0,
+            bp: 0,
+            arithmetic: ArithmeticMode::default(),
+            trace: None,
+            profile: None,
+            max_cycles: None,
+            input: Box::new(std::io::stdin()),
+            output: Box::new(std::io::stdout()),
             debug_mode,
         }
     }
 
-    pub fn run(&mut self) -> Result<i32, String> {
+    // Build a VM from a configuration; bounds checks only need the code compiled for them
+    pub fn with_config(code: Vec<i32>, data: Vec<u8>, config: &VmConfig) -> Self {
+        let mut vm = VM::new(code, data, config.stack_size, config.debug_mode);
+        vm.set_arithmetic_mode(config.arithmetic);
+        if config.profile {
+            vm.enable_profiling();
+        }
+        vm.max_cycles = config.max_cycles;
+        vm
+    }
+
+    // Build a VM for a compiled program; runtime errors then name the source line
+    pub fn from_program(program: Program, config: &VmConfig) -> Self {
+        let mut vm = VM::with_config(program.text, program.data, config);
+        vm.lines = program.lines;
+        vm
+    }
+
+    // Build a VM for a program compiled earlier and saved with `Program::to_image`
+    pub fn from_image(path: impl AsRef<Path>, config: &VmConfig) -> Result<Self, String> {
+        let path = path.as_ref();
+        let image = std::fs::read(path)
+            .map_err(|err| format!("cannot read image '{}': {}", path.display(), err))?;
+        let program = Program::from_image(&image)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(VM::from_program(program, config))
+    }
+
+    // Read the program's input from `input` instead of stdin
+    pub fn with_input(mut self, input: impl Read + 'static) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    // Send the program's output to `output` instead of stdout
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    // Choose how arithmetic overflow is handled
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.arithmetic = mode;
+    }
+
+    // Record the execution trace in memory as well; read it back with `trace`
+    pub fn capture_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    // Count executions per opcode; read the counts back with `profile`
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Box::new([0; NUM_OPCODES]));
+    }
+
+    // Executions per opcode during the last run, indexed by `opcode as usize - 1`
+    pub fn profile(&self) -> Option<&[u64; NUM_OPCODES]> {
+        self.profile.as_deref()
+    }
+
+    // How often `op` executed during the last run; 0 when profiling is off
+    pub fn executions(&self, op: Opcode) -> u64 {
+        self.profile().map_or(0, |counts| counts[op as usize - 1])
+    }
+
+    // Trace lines captured during the last run
+    pub fn trace(&self) -> &[String] {
+        self.trace.as_deref().unwrap_or(&[])
+    }
+
+    // Hex dump of memory bytes in `range`, 16 to a line with an ASCII column;
+    // the range is clipped to the end of memory
+    pub fn dump_data(&self, range: Range<usize>) -> String {
+        let end = range.end.min(self.memory.len());
+        let start = range.start.min(end);
+        let mut out = String::new();
+        for (row, chunk) in self.memory[start..end].chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = chunk.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            out.push_str(&format!("{:08x}: {:<47}  |{}|\n", start + row * 16, hex.join(" "), ascii));
+        }
+        out
+    }
+
+    // Word slots `range` counted up from sp (slot 0 is the top of the stack),
+    // one per line in hex and decimal; slots past the bottom of the stack are left out
+    pub fn dump_stack(&self, range: Range<usize>) -> String {
+        let mut out = String::new();
+        for slot in range {
+            let addr = self.sp + slot * WORD;
+            if addr + WORD > self.memory.len() {
+                break;
+            }
+            let mut bytes = [0; WORD];
+            bytes.copy_from_slice(&self.memory[addr..addr + WORD]);
+            let value = i32::from_le_bytes(bytes);
+            let marker = if addr == self.bp { "  <- bp" } else { "" };
+            out.push_str(&format!("sp+{:<3} {:08x}: {:08x} {:>11}{}\n", slot, addr, value, value, marker));
+        }
+        out
+    }
+
+    pub fn run(&mut self) -> Result<i32, VmError> {
+        // Start from a clean memory image with the data segment at address 0
+        self.memory.iter_mut().for_each(|byte| *byte = 0);
+        self.memory[..self.data.len()].copy_from_slice(&self.data);
+        self.heap_top = self.heap_start;
+        self.allocations.clear();
+        self.ax = 0;
         self.pc = 0;
-        self.sp = 0;
-        self.stack.clear();
+        self.sp = self.memory.len();
+        self.bp = self.sp;
+
+        // Returning from main lands just past the end of the code, which stops the loop
+        self.push(self.code.len() as i32)?;
 
+        if let Some(trace) = self.trace.as_mut() {
+            trace.clear();
+        }
+        if let Some(counts) = self.profile.as_mut() {
+            counts.fill(0);
+        }
+
+        let mut cycles = 0;
         while self.pc < self.code.len() {
+            if let Some(limit) = self.max_cycles {
+                if cycles == limit {
+                    return Err(VmError::CycleLimit(limit));
+                }
+                cycles += 1;
+            }
+            let at = self.pc;
             let instruction = self.code[self.pc];
-            self.execute_instruction(instruction)?;
             self.pc += 1;
+            let finished = self.execute_instruction(instruction)
+                .map_err(|err| self.locate(at, err))?;
+            self.trace_instruction(at);
+            if let Some(exit_code) = finished {
+                return Ok(exit_code);
+            }
+        }
+
+        Ok(self.ax)
+    }
+
+    // Execute one instruction; returns the exit code once the program has finished
+    fn execute_instruction(&mut self, instruction: i32) -> Result<Option<i32>, VmError> {
+        let op = Opcode::from_i32(instruction)
+            .ok_or(VmError::InvalidOpcode { opcode: instruction, at: self.pc - 1 })?;
+        if let Some(counts) = self.profile.as_mut() {
+            counts[op as usize - 1] += 1;
         }
 
-        Ok(self.stack.pop_back().unwrap_or(0))
+        match op {
+            Opcode::LEA => {
+                // Signed word offset from bp: bp+0 is the saved bp, bp+1 the return address,
+                // bp+2 upwards the arguments (last one first) and bp-1 downwards the locals
+                let offset = self.fetch()?;
+                self.ax = (self.bp as i64 + offset as i64 * WORD as i64) as i32;
+            }
+            Opcode::IMM => self.ax = self.fetch()?,
+            Opcode::JMP => self.pc = self.fetch()? as usize,
+            // A frame, from bp upwards: the caller's bp (pushed by ENT), the return address
+            // (pushed by JSR), then the arguments with the last one nearest; LEV unwinds both
+            Opcode::JSR => {
+                let target = self.fetch()?;
+                self.push(self.pc as i32)?;
+                self.pc = target as usize;
+            }
+            Opcode::JSRI => {
+                let args = usize::try_from(self.fetch()?).unwrap_or(usize::MAX);
+                let target = self.read_word(self.sp.saturating_add(args.saturating_mul(WORD)))?;
+                self.push(self.pc as i32)?;
+                self.pc = target as usize;
+            }
+            Opcode::BZ => {
+                let target = self.fetch()?;
+                if self.ax == 0 {
+                    self.pc = target as usize;
+                }
+            }
+            Opcode::BNZ => {
+                let target = self.fetch()?;
+                if self.ax != 0 {
+                    self.pc = target as usize;
+                }
+            }
+            Opcode::ENT => {
+                // Make sure the whole frame fits before touching the stack
+                let locals = usize::try_from(self.fetch()?).unwrap_or(usize::MAX).saturating_mul(WORD);
+                self.check_headroom(locals.saturating_add(WORD))?;
+                self.push(self.bp as i32)?;
+                self.bp = self.sp;
+                self.sp -= locals;
+            }
+            Opcode::ADJ => {
+                // The stack grows down, so dropping words raises sp and reserving lowers it
+                let words = self.fetch()? as i64;
+                let sp = self.sp as i64 + words * WORD as i64;
+                if sp < self.heap_top as i64 {
+                    return Err(VmError::StackOverflow);
+                }
+                if sp > self.memory.len() as i64 {
+                    return Err(VmError::StackUnderflow { op });
+                }
+                self.sp = sp as usize;
+            }
+            Opcode::LEV => {
+                self.sp = self.bp;
+                self.bp = self.pop()? as usize;
+                self.pc = self.pop()? as usize;
+            }
+            Opcode::LI => self.ax = self.read_word(self.ax as usize)?,
+            Opcode::LC => self.ax = self.read_byte(self.ax as usize)? as i32,
+            Opcode::SI => {
+                let addr = self.pop()? as usize;
+                self.write_word(addr, self.ax)?;
+            }
+            Opcode::SC => {
+                // The assignment's value is what was stored, so ax is narrowed like LC reads it
+                let addr = self.pop()? as usize;
+                self.write_byte(addr, self.ax as u8)?;
+                self.ax &= 0xff;
+            }
+            Opcode::LSC => self.ax = self.read_byte(self.ax as usize)? as i8 as i32,
+            Opcode::SSC => {
+                let addr = self.pop()? as usize;
+                self.write_byte(addr, self.ax as u8)?;
+                self.ax = self.ax as u8 as i8 as i32;
+            }
+            Opcode::PSH => self.push(self.ax)?,
+
+            Opcode::OR => self.ax |= self.pop()?,
+            Opcode::XOR => self.ax ^= self.pop()?,
+            Opcode::AND => self.ax &= self.pop()?,
+            Opcode::EQ => self.ax = (self.pop()? == self.ax) as i32,
+            Opcode::LNOT => self.ax = (self.ax == 0) as i32,
+            Opcode::NE => self.ax = (self.pop()? != self.ax) as i32,
+            Opcode::LT => self.ax = (self.pop()? < self.ax) as i32,
+            Opcode::GT => self.ax = (self.pop()? > self.ax) as i32,
+            Opcode::LE => self.ax = (self.pop()? <= self.ax) as i32,
+            Opcode::GE => self.ax = (self.pop()? >= self.ax) as i32,
+            Opcode::ULT => self.ax = ((self.pop()? as u32) < self.ax as u32) as i32,
+            Opcode::UGT => self.ax = (self.pop()? as u32 > self.ax as u32) as i32,
+            Opcode::ULE => self.ax = (self.pop()? as u32 <= self.ax as u32) as i32,
+            Opcode::UGE => self.ax = (self.pop()? as u32 >= self.ax as u32) as i32,
+            Opcode::SHL => {
+                let lhs = self.pop()?;
+                self.ax = self.arith(Opcode::SHL, lhs, self.ax, |a, b| a.wrapping_shl(b as u32), |a, b| a.checked_shl(b as u32))?;
+            }
+            Opcode::SHR => {
+                let lhs = self.pop()?;
+                self.ax = self.arith(Opcode::SHR, lhs, self.ax, |a, b| a.wrapping_shr(b as u32), |a, b| a.checked_shr(b as u32))?;
+            }
+            Opcode::ADD => {
+                let lhs = self.pop()?;
+                self.ax = self.arith(Opcode::ADD, lhs, self.ax, i32::wrapping_add, i32::checked_add)?;
+            }
+            Opcode::SUB => {
+                let lhs = self.pop()?;
+                self.ax = self.arith(Opcode::SUB, lhs, self.ax, i32::wrapping_sub, i32::checked_sub)?;
+            }
+            Opcode::MUL => {
+                let lhs = self.pop()?;
+                self.ax = self.arith(Opcode::MUL, lhs, self.ax, i32::wrapping_mul, i32::checked_mul)?;
+            }
+            Opcode::DIV => {
+                let lhs = self.pop()?;
+                if self.ax == 0 {
+                    return Err(VmError::DivByZero);
+                }
+                // Only INT_MIN / -1 overflows, wrapping to INT_MIN
+                self.ax = self.arith(Opcode::DIV, lhs, self.ax, i32::wrapping_div, i32::checked_div)?;
+            }
+            Opcode::MOD => {
+                let lhs = self.pop()?;
+                if self.ax == 0 {
+                    return Err(VmError::DivByZero);
+                }
+                self.ax = self.arith(Opcode::MOD, lhs, self.ax, i32::wrapping_rem, i32::checked_rem)?;
+            }
+
+            Opcode::OPEN => self.sys_open()?,
+            Opcode::READ => self.sys_read()?,
+            Opcode::CLOS => {
+                let fd = self.arg(0, 1)?;
+                self.ax = if self.files.remove(&fd).is_some() { 0 } else { -1 };
+            }
+            Opcode::PRTF => self.sys_printf()?,
+            Opcode::MALC => {
+                let size = self.arg(0, 1)?;
+                self.ax = self.malloc(size.max(0) as usize);
+            }
+            Opcode::FREE => {
+                // The bump allocator never reuses memory, but the block becomes inaccessible
+                let addr = self.arg(0, 1)? as usize;
+                if addr != 0 && self.allocations.remove(&addr).is_none() {
+                    return Err(VmError::InvalidFree(addr));
+                }
+            }
+            Opcode::RALC => {
+                let addr = self.arg(0, 2)? as usize;
+                let size = self.arg(1, 2)?.max(0) as usize;
+                self.ax = self.realloc(addr, size)?;
+            }
+            Opcode::MSET => {
+                let dest = self.arg(0, 3)? as usize;
+                let value = self.arg(1, 3)? as u8;
+                let count = self.arg(2, 3)?.max(0) as usize;
+                self.check_range(dest, count)?;
+                self.memory[dest..dest + count].fill(value);
+                self.ax = dest as i32;
+            }
+            Opcode::MCMP => {
+                let a = self.arg(0, 3)? as usize;
+                let b = self.arg(1, 3)? as usize;
+                let count = self.arg(2, 3)?.max(0) as usize;
+                self.check_range(a, count)?;
+                self.check_range(b, count)?;
+                self.ax = self.memory[a..a + count].iter()
+                    .zip(&self.memory[b..b + count])
+                    .map(|(x, y)| *x as i32 - *y as i32)
+                    .find(|diff| *diff != 0)
+                    .unwrap_or(0);
+            }
+            Opcode::EXIT => {
+                let exit_code = self.read_word(self.sp)?;
+                if self.debug_mode {
+                    println!("DEBUG: exit({})", exit_code);
+                }
+                return Ok(Some(exit_code));
+            }
+            Opcode::GETC => {
+                let mut byte = [0u8; 1];
+                self.ax = match self.input.read(&mut byte) {
+                    Ok(1) => byte[0] as i32,
+                    _ => -1, // EOF or read error
+                };
+            }
+            Opcode::PUTC => {
+                let c = self.arg(0, 1)?;
+                self.write_output(&[c as u8])?;
+                self.ax = c & 0xff;
+            }
+            Opcode::PUTS => {
+                // Write the string and a newline; the result is the number of bytes written
+                let mut line = self.read_c_string(self.arg(0, 1)? as usize)?;
+                line.push(b'\n');
+                self.write_output(&line)?;
+                self.ax = line.len() as i32;
+            }
+            Opcode::ISDG | Opcode::ISAL | Opcode::ISSP => {
+                // Classify a character code in the C locale; EOF and other values are in no class
+                let c = u8::try_from(self.arg(0, 1)?).unwrap_or(0);
+                self.ax = match op {
+                    Opcode::ISDG => c.is_ascii_digit(),
+                    Opcode::ISAL => c.is_ascii_alphabetic(),
+                    _ => matches!(c, b' ' | b'\t'..=b'\r'),
+                } as i32;
+            }
+            Opcode::ABS => {
+                // abs(INT_MIN) does not fit, which only checked arithmetic reports
+                let value = self.arg(0, 1)?;
+                self.ax = self.arith(Opcode::ABS, value, 0, |a, _| a.wrapping_abs(), |a, _| a.checked_abs())?;
+            }
+            Opcode::MIN => self.ax = self.arg(0, 2)?.min(self.arg(1, 2)?),
+            Opcode::MAX => self.ax = self.arg(0, 2)?.max(self.arg(1, 2)?),
+            Opcode::ASRT => {
+                if self.arg(0, 1)? == 0 {
+                    return Err(VmError::AssertionFailed { at: self.pc - 1 });
+                }
+            }
+            Opcode::NOP => {}
+            Opcode::BNDS => {
+                let guard = self.fetch()? as usize;
+                let len = self.read_word(guard)?;
+                if self.ax < 0 || self.ax >= len {
+                    let name = String::from_utf8_lossy(&self.read_c_string(guard + WORD)?).into_owned();
+                    return Err(VmError::OutOfBounds { kind: AccessKind::Array { name, len }, index: self.ax as i64 });
+                }
+            }
+        }
+
+        Ok(None)
     }
 
-    fn execute_instruction(&mut self, instruction: i32) -> Result<(), String> {
-        // TODO: Implement actual instruction execution
-		
+    // Add the source line of the instruction at `at` to an error message, if it is known
+    fn locate(&self, at: usize, err: VmError) -> VmError {
+        match self.lines.get(at) {
+            Some(&line) if line > 0 => VmError::Located { line, error: Box::new(err) },
+            _ => err,
+        }
+    }
+
+    // Print (and capture, if enabled) the instruction at `at` with the registers after it ran
+    fn trace_instruction(&mut self, at: usize) {
+        if !self.debug_mode && self.trace.is_none() {
+            return;
+        }
+        let (text, _) = disassemble_instruction(&self.code, at);
+        let line = format!("{:>4}: {:<16} ax={} sp={} bp={}", at, text, self.ax, self.sp, self.bp);
+        if self.debug_mode {
+            println!("{}", line);
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(line);
+        }
+    }
+
+    // Apply an arithmetic operation according to the selected overflow policy
+    fn arith(
+        &self,
+        op: Opcode,
+        lhs: i32,
+        rhs: i32,
+        wrapping: impl Fn(i32, i32) -> i32,
+        checked: impl Fn(i32, i32) -> Option<i32>,
+    ) -> Result<i32, VmError> {
+        match self.arithmetic {
+            ArithmeticMode::Wrapping => Ok(wrapping(lhs, rhs)),
+            ArithmeticMode::Checked => checked(lhs, rhs).ok_or(VmError::Overflow { op, lhs, rhs }),
+        }
+    }
+
+    // Read the operand following the current instruction
+    fn fetch(&mut self) -> Result<i32, VmError> {
+        let value = *self.code.get(self.pc)
+            .ok_or(VmError::MissingOperand { at: self.pc })?;
+        self.pc += 1;
+        Ok(value)
+    }
+
+    // Fail unless `bytes` more can be pushed without running into the heap
+    fn check_headroom(&self, bytes: usize) -> Result<(), VmError> {
+        match self.sp.checked_sub(bytes) {
+            Some(sp) if sp >= self.heap_top => Ok(()),
+            _ => Err(VmError::StackOverflow),
+        }
+    }
+
+    fn push(&mut self, value: i32) -> Result<(), VmError> {
+        self.check_headroom(WORD)?;
+        self.sp -= WORD;
+        self.write_word(self.sp, value)
+    }
+
+    fn pop(&mut self) -> Result<i32, VmError> {
+        let value = self.read_word(self.sp)?;
+        self.sp += WORD;
+        Ok(value)
+    }
+
+    // Argument `index` of a call with `count` arguments; they were pushed left to right
+    fn arg(&self, index: usize, count: usize) -> Result<i32, VmError> {
+        self.read_word(self.sp + (count - 1 - index) * WORD)
+    }
+
+    fn check_range(&self, addr: usize, len: usize) -> Result<(), VmError> {
+        let end = match addr.checked_add(len) {
+            Some(end) if end <= self.memory.len() => end,
+            _ => return Err(VmError::OutOfBounds { kind: AccessKind::Memory, index: addr as i64 }),
+        };
+
+        // Heap accesses must stay inside a block that is still allocated
+        if addr < self.heap_top && end > self.heap_start {
+            let inside = self.allocations.range(..=addr).next_back()
+                .is_some_and(|(start, size)| end <= start + size);
+            if !inside {
+                return Err(VmError::OutOfBounds { kind: AccessKind::Heap, index: addr as i64 });
+            }
+        }
         Ok(())
     }
+
+    fn read_word(&self, addr: usize) -> Result<i32, VmError> {
+        self.check_range(addr, WORD)?;
+        let mut bytes = [0; WORD];
+        bytes.copy_from_slice(&self.memory[addr..addr + WORD]);
+        Ok(i32::from_le_bytes(bytes))
+    }
+
+    fn write_word(&mut self, addr: usize, value: i32) -> Result<(), VmError> {
+        self.check_range(addr, WORD)?;
+        self.memory[addr..addr + WORD].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    fn read_byte(&self, addr: usize) -> Result<u8, VmError> {
+        self.check_range(addr, 1)?;
+        Ok(self.memory[addr])
+    }
+
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), VmError> {
+        self.check_range(addr, 1)?;
+        self.memory[addr] = value;
+        Ok(())
+    }
+
+    // Read a NUL-terminated string starting at addr
+    fn read_c_string(&self, addr: usize) -> Result<Vec<u8>, VmError> {
+        let mut bytes = Vec::new();
+        let mut at = addr;
+        loop {
+            let byte = self.read_byte(at)?;
+            if byte == 0 {
+                return Ok(bytes);
+            }
+            bytes.push(byte);
+            at += 1;
+        }
+    }
+
+    // Bump-allocate heap memory; returns 0 when the heap would run into the stack
+    fn malloc(&mut self, size: usize) -> i32 {
+        let addr = self.heap_top;
+        // Every block takes up at least a word, so even empty ones have an address of their own
+        let end = align(addr + size.max(1));
+        if end > self.sp {
+            return 0;
+        }
+        self.heap_top = end;
+        self.allocations.insert(addr, size);
+        addr as i32
+    }
+
+    // Move a heap block into a new one of `size` bytes, keeping as much of its contents as
+    // fits. A null pointer just allocates; when there is no room the old block is kept and
+    // 0 returned
+    fn realloc(&mut self, addr: usize, size: usize) -> Result<i32, VmError> {
+        if addr == 0 {
+            return Ok(self.malloc(size));
+        }
+        let old_size = *self.allocations.get(&addr).ok_or(VmError::InvalidRealloc(addr))?;
+        let new_addr = self.malloc(size);
+        if new_addr != 0 {
+            let kept = old_size.min(size);
+            self.memory.copy_within(addr..addr + kept, new_addr as usize);
+            self.allocations.remove(&addr);
+        }
+        Ok(new_addr)
+    }
+
+    fn sys_open(&mut self) -> Result<(), VmError> {
+        let path = self.read_c_string(self.arg(0, 2)? as usize)?;
+        let path = String::from_utf8_lossy(&path).into_owned();
+        let flags = self.arg(1, 2)?;
+        self.ax = match open_options(flags).open(&path) {
+            Ok(file) => {
+                let fd = self.next_fd;
+                self.next_fd += 1;
+                self.files.insert(fd, file);
+                fd
+            }
+            Err(_) => -1,
+        };
+        Ok(())
+    }
+
+    // read(fd, buf, count): read up to count bytes into VM memory at buf, returning how many
+    // were read, 0 at end of file or -1 for an unknown descriptor or a failed read. Descriptor
+    // 0 reads the VM's input
+    fn sys_read(&mut self) -> Result<(), VmError> {
+        let fd = self.arg(0, 3)?;
+        let buf = self.arg(1, 3)? as usize;
+        let count = self.arg(2, 3)?.max(0) as usize;
+        self.check_range(buf, count)?;
+        let dest = &mut self.memory[buf..buf + count];
+        let result = match fd {
+            0 => self.input.read(dest),
+            _ => match self.files.get_mut(&fd) {
+                Some(file) => file.read(dest),
+                None => {
+                    self.ax = -1;
+                    return Ok(());
+                }
+            },
+        };
+        self.ax = result.map_or(-1, |n| n as i32);
+        Ok(())
+    }
+
+    // Write program output, flushing so it interleaves with anything else on the terminal
+    fn write_output(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        self.output.write_all(bytes)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn sys_printf(&mut self) -> Result<(), VmError> {
+        // The compiler follows every call with ADJ <argc>, which tells us how many were pushed
+        let count = match (self.code.get(self.pc), self.code.get(self.pc + 1)) {
+            (Some(op), Some(n)) if *op == Opcode::ADJ as i32 => *n as usize,
+            _ => 1,
+        };
+        if count == 0 {
+            return Err(VmError::Syscall("printf requires a format string".to_string()));
+        }
+
+        let format = self.read_c_string(self.arg(0, count)? as usize)?;
+        let args = (1..count).map(|i| self.arg(i, count)).collect::<Result<Vec<_>, _>>()?;
+        let out = self.format_printf(&format, &args)?;
+
+        self.write_output(&out)?;
+        self.ax = out.len() as i32;
+        Ok(())
+    }
+
+    // Expand a printf format string: %[-][0][width][.precision][l|h] followed by d, i, u, o, p, c, s or %.
+    // The width and precision may also be `*`, which takes them from the arguments
+    fn format_printf(&self, format: &[u8], args: &[i32]) -> Result<Vec<u8>, VmError> {
+        let mut out = Vec::new();
+        let mut next_arg = 0;
+        let mut i = 0;
+        while i < format.len() {
+            if format[i] != b'%' || i + 1 >= format.len() {
+                out.push(format[i]);
+                i += 1;
+                continue;
+            }
+            let spec_start = i;
+            i += 1;
+
+            // Never read past the arguments that were actually pushed
+            let mut take_arg = || {
+                let value = args.get(next_arg).copied().ok_or_else(|| {
+                    VmError::Syscall(format!("printf: format string expects more than {} argument(s)", args.len()))
+                });
+                next_arg += 1;
+                value
+            };
+
+            // Flags
+            let mut left_align = false;
+            let mut zero_pad = false;
+            while i < format.len() && matches!(format[i], b'-' | b'0') {
+                if format[i] == b'-' {
+                    left_align = true;
+                } else {
+                    zero_pad = true;
+                }
+                i += 1;
+            }
+
+            // Minimum field width; `*` takes it from the next argument, where a negative
+            // width means left alignment
+            let mut width = 0;
+            if i < format.len() && format[i] == b'*' {
+                i += 1;
+                let value = take_arg()?;
+                left_align |= value < 0;
+                width = value.unsigned_abs() as usize;
+            }
+            while i < format.len() && format[i].is_ascii_digit() {
+                width = width * 10 + (format[i] - b'0') as usize;
+                i += 1;
+            }
+
+            // Precision; `.*` takes it from the next argument, and a negative one is ignored
+            let mut precision = None;
+            if i < format.len() && format[i] == b'.' {
+                i += 1;
+                if i < format.len() && format[i] == b'*' {
+                    i += 1;
+                    precision = usize::try_from(take_arg()?).ok();
+                } else {
+                    let mut p = 0;
+                    while i < format.len() && format[i].is_ascii_digit() {
+                        p = p * 10 + (format[i] - b'0') as usize;
+                        i += 1;
+                    }
+                    precision = Some(p);
+                }
+            }
+
+            // Length modifiers; long is a single word on this 32-bit machine, like int
+            while i < format.len() && matches!(format[i], b'l' | b'h') {
+                i += 1;
+            }
+
+            if i >= format.len() {
+                // Incomplete specifier, print it as written
+                out.extend_from_slice(&format[spec_start..]);
+                break;
+            }
+            let spec = format[i];
+            i += 1;
+            if spec == b'%' {
+                out.push(b'%');
+                continue;
+            }
+            if !matches!(spec, b'd' | b'i' | b'u' | b'o' | b'p' | b'c' | b's') {
+                // Unknown conversion, print it as written
+                out.extend_from_slice(&format[spec_start..i]);
+                continue;
+            }
+
+            let value = take_arg()?;
+            let (text, numeric) = match spec {
+                b'd' | b'i' => ((value as i64).to_string().into_bytes(), true),
+                b'u' => ((value as u32).to_string().into_bytes(), true),
+                b'o' => (format!("{:o}", value as u32).into_bytes(), true),
+                b'p' => (format!("{:#x}", value as u32).into_bytes(), false), // Addresses are 32-bit
+                b'c' => (vec![value as u8], false),
+                b's' => {
+                    let mut text = self.read_c_string(value as usize)?;
+                    if let Some(p) = precision {
+                        text.truncate(p);
+                    }
+                    (text, false)
+                }
+                _ => unreachable!(),
+            };
+
+            let padding = width.saturating_sub(text.len());
+            if left_align {
+                out.extend_from_slice(&text);
+                out.resize(out.len() + padding, b' ');
+            } else if zero_pad && numeric {
+                // Zeros go between the sign and the digits
+                let (sign, digits) = text.split_at(if text.first() == Some(&b'-') { 1 } else { 0 });
+                out.extend_from_slice(sign);
+                out.resize(out.len() + padding, b'0');
+                out.extend_from_slice(digits);
+            } else {
+                out.resize(out.len() + padding, b' ');
+                out.extend_from_slice(&text);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // i32::MAX + 1
+    fn overflowing_add() -> Vec<i32> {
+        vec![
+            Opcode::IMM as i32, i32::MAX,
+            Opcode::PSH as i32,
+            Opcode::IMM as i32, 1,
+            Opcode::ADD as i32,
+        ]
+    }
+
+    #[test]
+    fn test_wrapping_add() {
+        let mut vm = VM::new(overflowing_add(), Vec::new(), 1024, false);
+        assert_eq!(vm.run(), Ok(i32::MIN));
+    }
+
+    #[test]
+    fn test_checked_add_overflow() {
+        let mut vm = VM::new(overflowing_add(), Vec::new(), 1024, false);
+        vm.set_arithmetic_mode(ArithmeticMode::Checked);
+        assert!(vm.run().is_err());
+    }
+
+    #[test]
+    fn test_lea_reaches_arguments_and_locals() {
+        // f(30, 12) with one local set to 100; returns the second argument plus the local
+        let code = vec![
+            Opcode::IMM as i32, 30, Opcode::PSH as i32,
+            Opcode::IMM as i32, 12, Opcode::PSH as i32,
+            Opcode::JSR as i32, 12,
+            Opcode::ADJ as i32, 2,
+            Opcode::PSH as i32, Opcode::EXIT as i32,
+            // f:
+            Opcode::ENT as i32, 1,
+            Opcode::LEA as i32, -1, Opcode::PSH as i32, Opcode::IMM as i32, 100, Opcode::SI as i32,
+            Opcode::LEA as i32, 2, Opcode::LI as i32, Opcode::PSH as i32,
+            Opcode::LEA as i32, -1, Opcode::LI as i32,
+            Opcode::ADD as i32,
+            Opcode::LEV as i32,
+        ];
+        let mut vm = VM::new(code, Vec::new(), 1024, false);
+        assert_eq!(vm.run(), Ok(112));
+    }
+
+    #[test]
+    fn test_nested_calls_return_through_every_frame() {
+        // main returns f(3), where f(x) = g(x) + 1 and g(x) keeps x * 10 in a local
+        let code = vec![
+            Opcode::IMM as i32, 3, Opcode::PSH as i32,
+            Opcode::JSR as i32, 9,
+            Opcode::ADJ as i32, 1,
+            Opcode::PSH as i32, Opcode::EXIT as i32,
+            // f:
+            Opcode::ENT as i32, 0,
+            Opcode::LEA as i32, 2, Opcode::LI as i32, Opcode::PSH as i32,
+            Opcode::JSR as i32, 24,
+            Opcode::ADJ as i32, 1,
+            Opcode::PSH as i32, Opcode::IMM as i32, 1, Opcode::ADD as i32,
+            Opcode::LEV as i32,
+            // g:
+            Opcode::ENT as i32, 1,
+            Opcode::LEA as i32, -1, Opcode::PSH as i32,
+            Opcode::LEA as i32, 2, Opcode::LI as i32, Opcode::PSH as i32,
+            Opcode::IMM as i32, 10, Opcode::MUL as i32, Opcode::SI as i32,
+            Opcode::LEA as i32, -1, Opcode::LI as i32,
+            Opcode::LEV as i32,
+        ];
+        let mut vm = VM::new(code, Vec::new(), 1024, false);
+        vm.capture_trace();
+        assert_eq!(vm.run(), Ok(31));
+
+        // Each LEV lands just after the JSR that made its frame, with the caller's bp back
+        let after = |at: usize| vm.trace().iter().position(|line| line.starts_with(&format!("{:>4}: ", at))).unwrap();
+        let lev_g = vm.trace().iter().position(|line| line.starts_with("  40: ")).unwrap();
+        let lev_f = vm.trace().iter().position(|line| line.starts_with("  23: ")).unwrap();
+        assert_eq!(after(17), lev_g + 1);
+        assert_eq!(after(5), lev_f + 1);
+        assert!(vm.trace()[lev_f].ends_with("bp=1028"), "{}", vm.trace()[lev_f]);
+    }
+
+    #[test]
+    fn test_adj_reserves_and_drops_words() {
+        // Below the return sentinel at 1024, reserve two words and drop them again
+        let code = vec![
+            Opcode::ADJ as i32, -2,
+            Opcode::IMM as i32, 9,
+            Opcode::ADJ as i32, 2,
+        ];
+        let mut vm = VM::new(code, Vec::new(), 1024, false);
+        vm.capture_trace();
+        assert_eq!(vm.run(), Ok(9));
+        assert!(vm.trace()[0].ends_with("sp=1016 bp=1028"), "{}", vm.trace()[0]);
+        assert!(vm.trace()[2].ends_with("sp=1024 bp=1028"), "{}", vm.trace()[2]);
+    }
+
+    #[test]
+    fn test_dump_stack_shows_pushed_values() {
+        // No EXIT, so the pushed words stay on the stack after the run
+        let code = vec![
+            Opcode::IMM as i32, 7, Opcode::PSH as i32,
+            Opcode::IMM as i32, -9, Opcode::PSH as i32,
+        ];
+        let mut vm = VM::new(code, Vec::new(), 64, false);
+        assert_eq!(vm.run(), Ok(-9));
+        let dump = vm.dump_stack(0..3);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", dump);
+        assert!(lines[0].starts_with("sp+0") && lines[0].ends_with("fffffff7          -9"), "{}", dump);
+        assert!(lines[1].starts_with("sp+1") && lines[1].ends_with("00000007           7"), "{}", dump);
+        // The return sentinel pushed by run sits just below the frame pointer
+        assert!(lines[2].ends_with("           6"), "{}", dump);
+
+        // Slots past the bottom of the stack are left out
+        assert_eq!(vm.dump_stack(0..10).lines().count(), 3);
+    }
+
+    #[test]
+    fn test_dump_data_shows_bytes_and_text() {
+        let mut vm = VM::new(Vec::new(), b"hi\n".to_vec(), 64, false);
+        assert_eq!(vm.run(), Ok(0));
+        assert_eq!(vm.dump_data(0..4), format!("00000000: {:<47}  |hi..|\n", "68 69 0a 00"));
+        assert_eq!(vm.dump_data(0..20).lines().count(), 2);
+        assert_eq!(vm.dump_data(1000..2000), "");
+    }
+
+    #[test]
+    fn test_adj_past_either_end_of_the_stack() {
+        let mut vm = VM::new(vec![Opcode::ADJ as i32, -1000], Vec::new(), 1024, false);
+        assert_eq!(vm.run(), Err(VmError::StackOverflow));
+        let mut vm = VM::new(vec![Opcode::ADJ as i32, 2], Vec::new(), 1024, false);
+        let err = vm.run().unwrap_err();
+        assert_eq!(err, VmError::StackUnderflow { op: Opcode::ADJ });
+        assert_eq!(err.to_string(), "Stack underflow in ADJ");
+    }
+
+    #[test]
+    fn test_oversized_frames_overflow_cleanly() {
+        for locals in [i32::MAX, -1, 300] {
+            let mut vm = VM::new(vec![Opcode::ENT as i32, locals], Vec::new(), 1024, false);
+            assert_eq!(vm.run(), Err(VmError::StackOverflow), "ENT {}", locals);
+        }
+
+        // A frame that fits exactly leaves sp at the end of the heap
+        let mut vm = VM::new(vec![Opcode::ENT as i32, 254], Vec::new(), 1024, false);
+        assert_eq!(vm.run(), Ok(0));
+
+        // An argument count that reaches past the stack is a bad access, not a panic
+        let mut vm = VM::new(vec![Opcode::JSRI as i32, -1], Vec::new(), 1024, false);
+        assert!(matches!(vm.run(), Err(VmError::OutOfBounds { .. })));
+    }
+
+    #[test]
+    fn test_nop_is_skipped() {
+        let code = vec![
+            Opcode::IMM as i32, 5,
+            Opcode::NOP as i32,
+            Opcode::NOP as i32,
+        ];
+        let mut vm = VM::new(code, Vec::new(), 1024, false);
+        assert_eq!(vm.run(), Ok(5));
+    }
+
+    #[test]
+    fn test_zero_instruction_is_an_error() {
+        let code = vec![Opcode::IMM as i32, 5, 0, Opcode::NOP as i32];
+        let mut vm = VM::new(code, Vec::new(), 1024, false);
+        let err = vm.run().unwrap_err();
+        assert_eq!(err, VmError::InvalidOpcode { opcode: 0, at: 2 });
+        assert_eq!(err.to_string(), "Invalid opcode 0 at 2");
+    }
+
+    fn printf_output(format: &str, args: &[i32]) -> String {
+        let vm = VM::new(Vec::new(), Vec::new(), 1024, false);
+        String::from_utf8(vm.format_printf(format.as_bytes(), args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_printf_zero_padding() {
+        assert_eq!(printf_output("%05d", &[42]), "00042");
+        assert_eq!(printf_output("%05d", &[-42]), "-0042");
+    }
+
+    #[test]
+    fn test_printf_width_and_alignment() {
+        assert_eq!(printf_output("%-5d|", &[42]), "42   |");
+        assert_eq!(printf_output("%5d|", &[42]), "   42|");
+        assert_eq!(printf_output("%d%%", &[7]), "7%");
+    }
+
+    #[test]
+    fn test_printf_string_precision() {
+        let data = b"hello\0".to_vec();
+        let mut vm = VM::new(Vec::new(), data, 1024, false);
+        // Running an empty program loads the data segment into memory
+        vm.run().unwrap();
+        assert_eq!(vm.format_printf(b"[%.3s][%-6s]", &[0, 0]).unwrap(), b"[hel][hello ]");
+    }
+
+    #[test]
+    fn test_printf_width_and_precision_arguments() {
+        assert_eq!(printf_output("%*d|", &[4, 7]), "   7|");
+        assert_eq!(printf_output("%*d|%0*d", &[-4, 7, 3, 5]), "7   |005");
+        assert!(VM::new(Vec::new(), Vec::new(), 1024, false).format_printf(b"%*d", &[4]).is_err());
+
+        let data = b"hello\0".to_vec();
+        let mut vm = VM::new(Vec::new(), data, 1024, false);
+        vm.run().unwrap();
+        assert_eq!(vm.format_printf(b"[%.*s][%*.*s][%.*s]", &[3, 0, 4, 2, 0, -1, 0]).unwrap(), b"[hel][  he][hello]");
+    }
+
+    #[test]
+    fn test_trace_shows_operands_and_registers() {
+        let code = vec![Opcode::IMM as i32, 5];
+        let mut vm = VM::new(code, Vec::new(), 1024, false);
+        vm.capture_trace();
+        assert_eq!(vm.run(), Ok(5));
+        assert_eq!(vm.trace().len(), 1);
+        assert!(vm.trace()[0].contains("IMM 5"));
+        assert!(vm.trace()[0].contains("ax=5"));
+    }
+
+    #[test]
+    fn test_printf_char_and_negative() {
+        assert_eq!(printf_output("%c", &[65]), "A");
+        assert_eq!(printf_output("%d", &[-7]), "-7");
+        assert_eq!(printf_output("[%3c]", &[b'x' as i32]), "[  x]");
+    }
+
+    #[test]
+    fn test_printf_missing_argument() {
+        let vm = VM::new(Vec::new(), Vec::new(), 1024, false);
+        assert!(vm.format_printf(b"%d %d", &[1]).is_err());
+    }
+
+    #[test]
+    fn test_printf_unsigned_and_long() {
+        assert_eq!(printf_output("%u", &[-1]), "4294967295");
+        assert_eq!(printf_output("%ld %li", &[i32::MIN, 12]), "-2147483648 12");
+        assert_eq!(printf_output("%lu|%08lu", &[-2, 42]), "4294967294|00000042");
+        assert_eq!(printf_output("%q", &[]), "%q");
+    }
+
+    #[test]
+    fn test_printf_octal_and_pointer() {
+        assert_eq!(printf_output("%o", &[8]), "10");
+        assert_eq!(printf_output("%o|%04o", &[-1, 7]), "37777777777|0007");
+        assert_eq!(printf_output("%p", &[0x1234]), "0x1234");
+        assert_eq!(printf_output("[%8p]", &[255]), "[    0xff]");
+    }
+
+    #[test]
+    fn test_unsigned_comparison() {
+        // 0x80000000 < 0x7fffffff as signed values, but not as addresses
+        let compare = |op: Opcode| {
+            let code = vec![
+                Opcode::IMM as i32, i32::MIN,
+                Opcode::PSH as i32,
+                Opcode::IMM as i32, i32::MAX,
+                op as i32,
+            ];
+            VM::new(code, Vec::new(), 1024, false).run().unwrap()
+        };
+        assert_eq!(compare(Opcode::GT), 0);
+        assert_eq!(compare(Opcode::UGT), 1);
+        assert_eq!(compare(Opcode::ULT), 0);
+        assert_eq!(compare(Opcode::UGE), 1);
+        assert_eq!(compare(Opcode::ULE), 0);
+    }
 }
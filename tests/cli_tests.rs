@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::PathBuf;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Write `source` to a temporary file and run the compiler binary on it, feeding `input` to stdin
+fn run_cli_with_input(name: &str, source: &str, flags: &[&str], input: &[u8]) -> Output {
+    let path: PathBuf = std::env::temp_dir().join(format!("c4_cli_{}_{}.c", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_OS-in-rust"))
+        .arg(&path)
+        .args(flags)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_file(&path).ok();
+    output
+}
+
+fn run_cli(name: &str, source: &str, flags: &[&str]) -> Output {
+    run_cli_with_input(name, source, flags, b"")
+}
+
+#[test]
+fn test_check_valid_program() {
+    // Running this would fail with a division by zero
+    let output = run_cli("check_ok", "int main() { int x; x = 0; return 1 / x; }", &["--check"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout, "OK\n");
+}
+
+#[test]
+fn test_check_invalid_program() {
+    let output = run_cli("check_err", "int main() { return (1 + ; }", &["--check"]);
+    assert_ne!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Compilation error"));
+}
+
+#[test]
+fn test_exit_code_low_bits() {
+    let output = run_cli("exit_code", "int main() { return 300; }", &[]);
+    assert_eq!(output.status.code(), Some(300 & 0xff));
+}
+
+#[test]
+fn test_putchar() {
+    let output = run_cli("putchar", "int main() { putchar(65); putchar(66); putchar(10); return putchar(67); }", &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "AB\nC");
+    assert_eq!(output.status.code(), Some(67));
+}
+
+#[test]
+fn test_getchar() {
+    let output = run_cli_with_input("getchar", "int main() { return getchar(); }", &[], b"Zq");
+    assert_eq!(output.status.code(), Some('Z' as i32));
+
+    // EOF reads as -1, which exits with status 255
+    let output = run_cli_with_input("getchar_eof", "int main() { return getchar(); }", &[], b"");
+    assert_eq!(output.status.code(), Some(255));
+}
+
+#[test]
+fn test_adjacent_string_literals() {
+    let output = run_cli("concat", "int main() { printf(\"a\" \"b\" \"\\n\"); return 0; }", &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "ab\n");
+}
+
+#[test]
+fn test_compile_to_image_then_run_it() {
+    let image = std::env::temp_dir().join(format!("c4_cli_image_{}.c4b", std::process::id()));
+    let output = run_cli("image", "int main() { printf(\"from image\\n\"); return 3; }", &["-o", image.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_OS-in-rust")).arg(&image).output().unwrap();
+    fs::remove_file(&image).ok();
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "from image\n");
+}
+
+#[test]
+fn test_puts() {
+    let output = run_cli("puts", "int main() { char *s = \"world\"; puts(\"hello\"); return puts(s); }", &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "hello\nworld\n");
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn test_printf_pointer_and_octal() {
+    let source = r#"int first; int second; int main() { printf("[%p %o]\n", &second, 8); return &second; }"#;
+    let output = run_cli("printf_pointer", source, &[]);
+    let address = output.status.code().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, format!("[{:#x} 10]\n", address));
+}
+
+#[test]
+fn test_dump_symbols() {
+    let source = "int count = 3;\nint twice(int n) { return n * 2; }\nint main() { return twice(count); }\n";
+    let output = run_cli("dump_symbols", source, &["--dump-symbols"]);
+    assert_eq!(output.status.code(), Some(6));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let class_of = |name: &str| {
+        stderr.lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .find(|fields| fields[0] == name)
+            .map(|fields| fields[1].to_string())
+    };
+    assert_eq!(class_of("count").as_deref(), Some("Global"), "{}", stderr);
+    assert_eq!(class_of("twice").as_deref(), Some("Function"), "{}", stderr);
+    assert_eq!(class_of("printf").as_deref(), Some("Sys"), "{}", stderr);
+    assert!(stderr.lines().any(|line| line.starts_with("twice") && line.contains("int (int)")), "{}", stderr);
+
+    // Without the flag nothing is printed
+    let output = run_cli("no_dump_symbols", source, &[]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_target_info() {
+    let output = Command::new(env!("CARGO_BIN_EXE_OS-in-rust")).arg("--target-info").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout.lines().any(|line| line == "word-bits: 32"), "{}", stdout);
+    assert!(stdout.lines().any(|line| line.split_whitespace().eq(["printf", "PRTF"])), "{}", stdout);
+}
+
+#[test]
+fn test_dead_code_adds_nothing_to_the_output() {
+    let output = run_cli("dead_code", "int main() { return 3; printf(\"x\"); }", &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(!stdout.contains("unreachable"), "{}", stdout);
+}
+
+#[test]
+fn test_compiling_prints_no_debug_output() {
+    let source = r#"
+        char *greeting;
+        int twice(int n) { return n + n; }
+        int main() {
+            int x;
+            greeting = "hi";
+            x = twice(2);
+            if (x) { printf("%s %d\n", greeting, x); }
+            return 0;
+        }
+    "#;
+    let output = run_cli("no_debug_output", source, &["--check"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("DEBUG"), "{}", stdout);
+}
@@ -0,0 +1,725 @@
+use c4_rust::codegen::{CodeGenerator, Opcode};
+use c4_rust::lexer::{Token, TokenKind};
+use c4_rust::parser::{types::Type, Parser};
+use c4_rust::vm::VM;
+
+#[test]
+fn test_array_size_constant_expression() {
+    let source = "int a[2 * 3]; int main() { return 0; }";
+    let mut parser = Parser::new(source.as_bytes());
+    let (_, data) = parser.parse().unwrap();
+
+    let symbol = parser.symbol_table.lookup("a").unwrap();
+    assert_eq!(symbol.typ, Type::Array(Box::new(Type::Int), 6));
+    assert_eq!(data.len(), 24);
+}
+
+#[test]
+fn test_array_size_sizeof() {
+    let source = "char buf[sizeof(int) * 4]; int main() { return 0; }";
+    let mut parser = Parser::new(source.as_bytes());
+    parser.parse().unwrap();
+
+    let symbol = parser.symbol_table.lookup("buf").unwrap();
+    assert_eq!(symbol.typ, Type::Array(Box::new(Type::Char), 16));
+}
+
+#[test]
+fn test_array_size_must_be_constant() {
+    let source = "int main() { int x; int a[x]; return 0; }";
+    let mut parser = Parser::new(source.as_bytes());
+    assert!(parser.parse().is_err());
+}
+
+#[test]
+fn test_array_size_must_be_positive() {
+    let source = "int a[1 - 3]; int main() { return 0; }";
+    let mut parser = Parser::new(source.as_bytes());
+    assert!(parser.parse().is_err());
+}
+
+#[test]
+fn test_function_scope_closed_after_parse() {
+    let source = "int f(int a) { int tmp; return a; } int main() { return f(1); }";
+    let mut parser = Parser::new(source.as_bytes());
+    parser.parse().unwrap();
+
+    assert_eq!(parser.symbol_table.depth(), 1);
+    assert!(parser.symbol_table.lookup("tmp").is_none());
+    assert!(parser.symbol_table.lookup("a").is_none());
+}
+
+#[test]
+fn test_local_not_visible_in_next_function() {
+    let source = "int f() { int tmp; tmp = 1; return tmp; } int main() { return tmp; }";
+    let mut parser = Parser::new(source.as_bytes());
+    assert!(parser.parse().is_err());
+}
+
+#[test]
+fn test_branch_operands_are_instruction_addresses() {
+    let source = r#"
+        int main() {
+            int i;
+            i = 0;
+            if (i) i = 1; else i = 2;
+            while (i < 10) i = i + 1;
+            return i > 3 && i < 20 || i == 0;
+        }
+    "#;
+    let mut parser = Parser::new(source.as_bytes());
+    let (code, _) = parser.parse().unwrap();
+
+    // Collect the address of every instruction, then check each branch lands on one
+    let mut starts = Vec::new();
+    let mut targets = Vec::new();
+    let mut at = 0;
+    while at < code.len() {
+        starts.push(at);
+        let op = Opcode::from_i32(code[at]).expect("valid opcode");
+        if matches!(op, Opcode::JMP | Opcode::BZ | Opcode::BNZ) {
+            targets.push(code[at + 1] as usize);
+        }
+        at += if op.has_operand() { 2 } else { 1 };
+    }
+    assert!(!targets.is_empty());
+    for target in targets {
+        assert!(target == code.len() || starts.contains(&target), "branch into the middle of an instruction: {}", target);
+    }
+}
+
+// Extract the `start..end` byte range from an error message
+fn error_span(err: &str) -> (usize, usize) {
+    let range = err.rsplit("at bytes ").next().unwrap();
+    let (start, end) = range.split_once("..").unwrap();
+    (start.parse().unwrap(), end.parse().unwrap())
+}
+
+#[test]
+fn test_error_span_inside_nested_expression() {
+    let source = "int main() { return (1 + (2 * ;)); }";
+    let mut parser = Parser::new(source.as_bytes());
+    let err = parser.parse().unwrap_err().to_string();
+
+    let (start, end) = error_span(&err);
+    let inner = source.find("(2 *").unwrap();
+    assert!(start > inner && end <= source.find("));").unwrap(), "{}", err);
+    assert_eq!(&source[start..end], ";");
+}
+
+#[test]
+fn test_symbol_spans() {
+    let source = "int counter; int main() { return counter; }";
+    let mut parser = Parser::new(source.as_bytes());
+    parser.parse().unwrap();
+
+    let symbol = parser.symbol_table.lookup("counter").unwrap();
+    assert_eq!(&source[symbol.span.0..symbol.span.1], "counter");
+    let main = parser.symbol_table.lookup("main").unwrap();
+    assert_eq!(&source[main.span.0..main.span.1], "main");
+}
+
+#[test]
+fn test_expression_span() {
+    let source = "int main() { return 1 + 2 * 3; }";
+    let mut parser = Parser::new(source.as_bytes());
+    parser.parse().unwrap();
+
+    let (start, end) = parser.expr_span;
+    assert_eq!(&source[start..end], "1 + 2 * 3");
+}
+
+#[test]
+fn test_reset_parser_between_programs() {
+    let first = "int value; int main() { value = 3; return value * 2; }";
+    let second = "int twice(int x) { return x + x; } int main() { return twice(20); }";
+
+    let mut parser = Parser::new(first.as_bytes());
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(VM::new(code, data, 1024 * 64, false).run(), Ok(6));
+
+    parser.reset(second.as_bytes());
+    assert_eq!(parser.symbol_table.depth(), 1);
+    assert!(parser.symbol_table.lookup("value").is_none());
+    assert!(parser.symbol_table.lookup("printf").is_some());
+
+    let (code, data) = parser.parse().unwrap();
+    assert!(data.is_empty());
+    assert_eq!(VM::new(code, data, 1024 * 64, false).run(), Ok(40));
+}
+
+#[test]
+fn test_no_code_after_return() {
+    let live = "int main() { int x; x = 1; return x; }";
+    let dead = "int main() { int x; x = 1; return x; x = 2; while (x) { x = x - 1; } printf(\"never\"); }";
+
+    let (live_code, _) = Parser::new(live.as_bytes()).parse().unwrap();
+    let (dead_code, _) = Parser::new(dead.as_bytes()).parse().unwrap();
+    assert_eq!(live_code, dead_code);
+}
+
+#[test]
+fn test_no_code_after_break() {
+    let live = "int main() { int i; while (1) { i = 1; break; } return i; }";
+    let dead = "int main() { int i; while (1) { i = 1; break; i = 2; break; } return i; }";
+
+    let (live_code, _) = Parser::new(live.as_bytes()).parse().unwrap();
+    let (dead_code, _) = Parser::new(dead.as_bytes()).parse().unwrap();
+    assert_eq!(live_code, dead_code);
+    assert_eq!(VM::new(dead_code, Vec::new(), 1024 * 64, false).run(), Ok(1));
+}
+
+#[test]
+fn test_negated_condition_inverts_branch() {
+    let source = "int main() { int done; done = 0; while (!done) done = 1; return done; }";
+    let (code, _) = Parser::new(source.as_bytes()).parse().unwrap();
+
+    let mut ops = Vec::new();
+    let mut at = 0;
+    while at < code.len() {
+        let op = Opcode::from_i32(code[at]).unwrap();
+        ops.push(op);
+        at += if op.has_operand() { 2 } else { 1 };
+    }
+    assert!(ops.contains(&Opcode::BNZ));
+    assert!(!ops.contains(&Opcode::BZ));
+    assert!(!ops.contains(&Opcode::EQ));
+}
+
+#[test]
+fn test_bounds_records_are_shared_per_array() {
+    let source = "int main() { int a[3]; int b[2]; a[0] = 1; a[1] = 2; a[2] = a[0]; b[1] = a[1]; return b[1]; }";
+    let mut parser = Parser::new(source.as_bytes());
+    parser.bounds_checks = true;
+    let (code, data) = parser.parse().unwrap();
+    assert_eq!(data, b"\x03\0\0\0a\0\x02\0\0\0b\0");
+    assert_eq!(VM::new(code, data, 1024 * 64, false).run(), Ok(2));
+}
+
+#[test]
+fn test_adjacent_string_literals_are_joined() {
+    let source = "int main() { printf(\"a\" \"b\"\n \"\\n\"); return 0; }";
+    let (_, data) = Parser::new(source.as_bytes()).parse().unwrap();
+    assert_eq!(data, b"ab\n\0");
+}
+
+#[test]
+fn test_assignment_to_rvalue_is_rejected() {
+    for source in [
+        "int main() { int x; 5 = x; return 0; }",
+        "int main() { int a; int b; int c; (a + b) = c; return 0; }",
+        "int f() { return 1; } int main() { f() = 2; return 0; }",
+        "int main() { int a[2]; a = 0; return 0; }",
+        "int main() { int a; int b; int c; c = 1; (c ? a : b) = 1; return 0; }",
+        "int main() { int a; int b; (a || b) = 1; return 0; }",
+    ] {
+        let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+        assert_eq!(err, "cannot assign to rvalue", "source: {}", source);
+    }
+}
+
+#[test]
+fn test_conditional_is_not_an_lvalue() {
+    let source = "int main() { int a; int b; int c; int *p; c = 1; p = &(c ? a : b); return 0; }";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err();
+    assert_eq!(err.to_string(), "Cannot take the address of a non-lvalue");
+
+    let source = "int main() { int a; int b; int c; c = 1; (c ? a : b)++; return 0; }";
+    assert!(Parser::new(source.as_bytes()).parse().is_err());
+
+    // Dereferencing a conditional pointer gives an lvalue again
+    let source = "int main() { int a; int b; int c; c = 0; *(c ? &a : &b) = 5; return b; }";
+    let (code, data) = Parser::new(source.as_bytes()).parse().unwrap();
+    assert_eq!(VM::new(code, data, 1024 * 64, false).run(), Ok(5));
+}
+
+#[test]
+fn test_assignment_to_lvalues_is_accepted() {
+    let source = "int main() { int x; int *p; int a[2]; p = &x; *p = 3; a[1] = x; (x) = 4; return a[1]; }";
+    let (code, data) = Parser::new(source.as_bytes()).parse().unwrap();
+    assert_eq!(VM::new(code, data, 1024 * 64, false).run(), Ok(3));
+}
+
+#[test]
+fn test_define_sets_array_size() {
+    let source = "#define N 5\nint a[N];\nint main() { a[N - 1] = 7; return a[4]; }";
+    let mut parser = Parser::new(source.as_bytes());
+    let (code, data) = parser.parse().unwrap();
+
+    let symbol = parser.symbol_table.lookup("a").unwrap();
+    assert_eq!(symbol.typ, Type::Array(Box::new(Type::Int), 5));
+    assert_eq!(VM::new(code, data, 1024 * 64, false).run(), Ok(7));
+}
+
+#[test]
+fn test_char_arithmetic_promotes_to_int() {
+    let source = "int main() { char c = 5; int x = c + 1; return sizeof(c + 1) * 10 + x; }";
+    let (code, data) = Parser::new(source.as_bytes()).parse().unwrap();
+    assert_eq!(VM::new(code, data, 1024 * 64, false).run(), Ok(46));
+}
+
+#[test]
+fn test_mismatched_pointer_assignment_is_rejected() {
+    let source = "int main() { char c = 5; int *p = &c; return 0; }";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert_eq!(err, "incompatible types: cannot assign char * to int *");
+
+    let source = "int main() { char c; int *p; p = &c; return 0; }";
+    assert!(Parser::new(source.as_bytes()).parse().is_err());
+}
+
+#[test]
+fn test_void_pointer_is_compatible_with_any_pointer() {
+    let source = "int main() { char *s = malloc(4); int *p = malloc(8); void *v = s; p = v; return 0; }";
+    assert!(Parser::new(source.as_bytes()).parse().is_ok());
+
+    assert!(Type::Int.is_compatible(&Type::Char));
+    assert!(Type::Int.to_pointer().is_compatible(&Type::Array(Box::new(Type::Int), 3)));
+    assert!(!Type::Int.to_pointer().is_compatible(&Type::Char.to_pointer()));
+    assert_eq!(Type::Char.promote(), Type::Int);
+}
+
+#[test]
+fn test_missing_main_is_an_error() {
+    let source = "int start() { return 0; }";
+    assert_eq!(Parser::new(source.as_bytes()).parse().unwrap_err().to_string(), "no main function defined");
+
+    // A prototype or a variable called main does not give the program an entry point
+    for source in ["int main(); int f() { return 0; }", "int main;"] {
+        assert_eq!(Parser::new(source.as_bytes()).parse().unwrap_err().to_string(), "no main function defined");
+    }
+}
+
+#[test]
+fn test_empty_source_has_no_main() {
+    for source in ["", "   \n\t\r\n  "] {
+        assert_eq!(Parser::new(source.as_bytes()).parse().unwrap_err().to_string(), "no main function defined");
+    }
+}
+
+#[test]
+fn test_assignment_pushes_address_before_value() {
+    let source = "int g; int main() { g = 5; return 0; }";
+    let (code, _) = Parser::new(source.as_bytes()).parse().unwrap();
+    let store = [Opcode::IMM as i32, 0, Opcode::PSH as i32, Opcode::IMM as i32, 5, Opcode::SI as i32];
+    assert!(code.windows(store.len()).any(|window| window == store), "code: {:?}", code);
+}
+
+#[test]
+fn test_call_with_too_few_arguments() {
+    let source = "int add(int a, int b) { return a + b; } int main() { return add(1); }";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert!(err.starts_with("too few arguments to function 'add': expected 2, got 1"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_call_with_too_many_arguments() {
+    let source = "int one() { return 1; } int main() { int (*fp)(int); return one(2) + fp(1, 2); }";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert!(err.starts_with("too many arguments to function 'one': expected 0, got 1"), "unexpected error: {}", err);
+
+    // Calls through function pointers are checked against the pointer's type
+    let source = "int main() { int (*fp)(int); return fp(1, 2); }";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert!(err.starts_with("too many arguments to function 'fp': expected 1, got 2"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_builtin_calls_are_checked() {
+    for (source, expected) in [
+        ("int main() { return min(1); }", "too few arguments to function 'min': expected 2, got 1"),
+        ("int main() { return abs(); }", "too few arguments to function 'abs': expected 1, got 0"),
+        ("int main() { return max(1, 2, 3); }", "too many arguments to function 'max': expected 2, got 3"),
+        ("int main() { char b[4]; memset(b, 65); return 0; }", "too few arguments to function 'memset': expected 3, got 2"),
+        ("int main() { return printf(); }", "too few arguments to function 'printf': expected 1, got 0"),
+    ] {
+        let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+        assert!(err.starts_with(expected), "{}: {}", source, err);
+    }
+
+    // printf takes any number of arguments after its format
+    let source = r#"int main() { printf("%d %d %d", 1, 2, 3); return 0; }"#;
+    assert!(Parser::new(source.as_bytes()).parse().is_ok());
+}
+
+#[test]
+fn test_variadic_prototype() {
+    let source = r#"
+        int sum(int n, ...);
+        int (*log_fn)(char *fmt, ...);
+        int main() { return log_fn("%d %d", 1, 2); }
+    "#;
+    let mut parser = Parser::new(source.as_bytes());
+    parser.parse().unwrap();
+
+    let sum = parser.symbol_table.lookup("sum").unwrap();
+    assert!(sum.is_variadic());
+    assert_eq!(sum.typ.to_string(), "int (int, ...)");
+    assert_eq!(parser.symbol_table.lookup("log_fn").unwrap().typ.to_string(), "int (*)(char *, ...)");
+
+    // The fixed parameters are still required
+    let source = "int sum(int n, ...); int main() { return sum(); }";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert!(err.starts_with("too few arguments to function 'sum': expected 1, got 0"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_calling_a_declared_but_undefined_function() {
+    let cases = [
+        ("int sum(int n, ...); int main() { return sum(1, 2, 3); }", "undefined function 'sum'"),
+        ("int f(); int main() { return f(); }", "undefined function 'f'"),
+        ("int f(int x); int (*fp)(int); int main() { fp = f; return 0; }", "undefined function 'f'"),
+    ];
+    for (source, message) in cases {
+        let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+        assert!(err.starts_with(message), "{}: {}", source, err);
+    }
+
+    // A definition further down is fine
+    assert!(Parser::new(b"int f(); int main() { return f(); } int f() { return 1; }").parse().is_ok());
+}
+
+#[test]
+fn test_ellipsis_must_end_parameter_list() {
+    for source in ["int f(...); int main() { return 0; }", "int f(int a, ..., int b); int main() { return 0; }"] {
+        assert!(Parser::new(source.as_bytes()).parse().is_err(), "accepted: {}", source);
+    }
+}
+
+#[test]
+fn test_expression_results() {
+    use c4_rust::parser::symbol_table::{Class, Symbol};
+
+    let parse = |source: &str| {
+        let mut parser = Parser::new(source.as_bytes());
+        for (name, val) in [("a", 8), ("b", 12)] {
+            let symbol = Symbol { name: name.to_string(), class: Class::Global, typ: Type::Int, val, offset: 0, span: (0, 0), is_const: false };
+            parser.symbol_table.add_symbol(symbol).unwrap();
+        }
+        parser.parse_expression().unwrap()
+    };
+
+    // A call keeps its class; adding to it gives a plain value
+    let call = parse("f()");
+    assert_eq!((call.class, call.typ, call.is_lvalue), (Some(Class::Function), Type::Int, false));
+    let sum = parse("f() + 1");
+    assert_eq!((sum.class, sum.typ, sum.is_lvalue), (None, Type::Int, false));
+
+    let var = parse("a");
+    assert_eq!((var.class, var.value, var.is_lvalue), (Some(Class::Global), 8, true));
+    let sum = parse("a + b");
+    assert_eq!((sum.class, sum.value, sum.is_lvalue), (None, 0, false));
+    assert!(parse("*&a").is_lvalue);
+    assert_eq!(parse("42").value, 42);
+}
+
+#[test]
+fn test_inner_call_completes_before_outer_arguments() {
+    let source = "int inner(int x) { return x; } int outer(int a, int b) { return a + b; } int main() { return outer(inner(1), 2); }";
+    let mut parser = Parser::new(source.as_bytes());
+    let (code, _) = parser.parse().unwrap();
+    let address = |name: &str| parser.symbol_table.lookup(name).unwrap().val as i32;
+    let (inner, outer) = (address("inner"), address("outer"));
+    let call = [
+        Opcode::IMM as i32, 1, Opcode::PSH as i32, Opcode::JSR as i32, inner, Opcode::ADJ as i32, 1,
+        Opcode::PSH as i32, Opcode::IMM as i32, 2, Opcode::PSH as i32, Opcode::JSR as i32, outer, Opcode::ADJ as i32, 2,
+    ];
+    assert!(code.windows(call.len()).any(|window| window == call), "code: {:?}", code);
+}
+
+#[test]
+fn test_stray_tokens_after_program_are_rejected() {
+    let source = "int main(){return 0;} }";
+    let mut parser = Parser::new(source.as_bytes());
+    let err = parser.parse().unwrap_err().to_string();
+    assert!(err.contains("unexpected token at top level"), "{}", err);
+
+    let mut parser = Parser::new(b"int main(){return 0;} ;");
+    assert!(parser.parse().unwrap_err().to_string().contains("unexpected token at top level"));
+}
+
+#[test]
+fn test_malformed_character_constants_are_rejected() {
+    let mut parser = Parser::new(b"int main() { return ''; }");
+    assert!(parser.parse().unwrap_err().to_string().contains("empty character constant"));
+
+    let mut parser = Parser::new(b"int main() { return 'ab'; }");
+    assert!(parser.parse().unwrap_err().to_string().contains("multi-character character constant"));
+}
+
+#[test]
+fn test_program_size_accessors() {
+    let program = Parser::new(b"int main() { return 0; }").compile().unwrap();
+    // The startup sequence (8 instructions), ENT, IMM 0 and LEV, and the LEV that ends
+    // every function in case control falls off its end
+    assert_eq!(program.instruction_count(), 12);
+    assert_eq!(program.text.len(), 18);
+    assert_eq!(program.data_size(), 0);
+
+    // "hi" and its terminator
+    let program = Parser::new(br#"int main() { printf("hi"); return 0; }"#).compile().unwrap();
+    assert_eq!(program.data_size(), 3);
+}
+
+#[test]
+fn test_deep_nesting_is_a_clean_error() {
+    let source = format!("int main() {{ return {}1{}; }}", "(".repeat(5000), ")".repeat(5000));
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert!(err.contains("expression too deeply nested"), "{}", err);
+
+    let source = format!("int main() {{ {}return 0;{} }}", "{".repeat(5000), "}".repeat(5000));
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert!(err.contains("statement too deeply nested"), "{}", err);
+}
+
+#[test]
+fn test_nesting_limit_is_configurable() {
+    let source = format!("int main() {{ return {}1{}; }}", "(".repeat(150), ")".repeat(150));
+    assert!(Parser::new(source.as_bytes()).parse().is_ok());
+
+    let mut parser = Parser::new(source.as_bytes());
+    parser.max_depth = 100;
+    assert!(parser.parse().unwrap_err().to_string().contains("too deeply nested"));
+}
+
+#[test]
+fn test_parse_error_records_the_expected_token() {
+    let source = "int main() {\n  if (1 { return 0; }\n  return 1;\n}";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err();
+    assert!(err.expected.contains(&TokenKind::Token(Token::CloseParen)), "{:?}", err);
+    assert_eq!(err.found, Token::OpenBrace);
+    assert_eq!((err.line, err.col), (2, 9));
+    assert_eq!(err.to_string(), "Expected ')' after if condition");
+
+    // Errors that are not about a missing token still say where parsing stopped
+    let err = Parser::new(b"int main() {\n  return 1 = 2;\n}").parse().unwrap_err();
+    assert!(err.expected.is_empty());
+    assert_eq!(err.to_string(), "cannot assign to rvalue");
+    assert_eq!(err.line, 2);
+
+    let err = Parser::new(b"int main() { int x; x = f(1 2); }").parse().unwrap_err();
+    assert_eq!(err.expected, vec![TokenKind::Token(Token::Comma), TokenKind::Token(Token::CloseParen)]);
+    assert_eq!(err.found, Token::Num(2));
+}
+
+#[test]
+fn test_struct_layout_uses_natural_alignment() {
+    let source = r#"
+        struct pair { int a; char b; };
+        struct mixed { char c; int n; char tail[3], more; };
+        int main() { return sizeof(struct pair) * 100 + sizeof(struct mixed); }
+    "#;
+    let mut parser = Parser::new(source.as_bytes());
+    let (code, data) = parser.parse().unwrap();
+
+    let pair = parser.structs.get("pair").unwrap();
+    assert_eq!(Type::Struct(pair.clone()).size(), 8);
+    assert_eq!(pair.member("a").unwrap().offset, 0);
+    assert_eq!(pair.member("b").unwrap().offset, 4);
+    assert_eq!(pair.member("b").unwrap().typ, Type::Char);
+    assert!(pair.member("c").is_none());
+
+    // The int is pushed to a word boundary, and the size padded to the next one
+    let mixed = parser.structs.get("mixed").unwrap();
+    let offsets: Vec<i32> = ["c", "n", "tail", "more"].iter().map(|name| mixed.member(name).unwrap().offset).collect();
+    assert_eq!(offsets, vec![0, 4, 8, 11]);
+    assert_eq!(Type::Struct(mixed.clone()).size(), 12);
+
+    let mut vm = VM::new(code, data, 1024, false);
+    assert_eq!(vm.run(), Ok(812));
+}
+
+#[test]
+fn test_struct_variables_and_self_reference() {
+    let source = r#"
+        struct node { int value; struct node *next; };
+        struct node head;
+        int main() { struct node local; struct node *p; return sizeof(local) + sizeof(p); }
+    "#;
+    let mut parser = Parser::new(source.as_bytes());
+    let (code, data) = parser.parse().unwrap();
+
+    let head = parser.symbol_table.lookup("head").unwrap();
+    assert_eq!(head.typ.to_string(), "struct node");
+    assert_eq!(head.typ.size(), 8);
+    let next = parser.structs.get("node").unwrap().member("next").unwrap();
+    assert_eq!(next.typ, head.typ.to_pointer());
+    assert_eq!(data.len(), 8);
+
+    let mut vm = VM::new(code, data, 1024, false);
+    assert_eq!(vm.run(), Ok(12));
+}
+
+#[test]
+fn test_invalid_struct_declarations() {
+    let cases = [
+        ("struct s { int a; }; struct s { int b; }; int main() { return 0; }", "redefinition of 'struct s'"),
+        ("struct s { int a; char a; }; int main() { return 0; }", "duplicate member 'a' in 'struct s'"),
+        ("struct s { struct s inner; }; int main() { return 0; }", "member 'inner' has incomplete type 'struct s'"),
+        ("struct s; struct s { }; int main() { return 0; }", "'struct s' has no members"),
+        ("struct s *p; struct s v; int main() { return 0; }", "variable 'v' has incomplete type 'struct s'"),
+        ("struct s { int a; }; struct s f() { return 0; } int main() { return 0; }", "function 'f' cannot return a struct by value"),
+        ("struct s { int a; }; int main() { struct s v = 1; return 0; }", "struct initializers are not supported"),
+    ];
+    for (source, message) in cases {
+        let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+        assert!(err.starts_with(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn test_diagnostics_report_independent_errors() {
+    let source = "int main() {\n    int x;\n    x = 1 +;\n    y = 2;\n    return x;\n}\n";
+    let (program, errors) = c4_rust::compile_with_diagnostics(source.as_bytes());
+    assert!(program.is_none());
+    let reported: Vec<(usize, String)> = errors.iter().map(|err| (err.line, err.to_string())).collect();
+    assert_eq!(reported.len(), 2, "{:?}", reported);
+    assert_eq!(reported[0].0, 3);
+    assert!(reported[0].1.starts_with("Unexpected token in expression: Semi"), "{:?}", reported);
+    assert_eq!(reported[1].0, 4);
+    assert!(reported[1].1.starts_with("Undefined identifier: y"), "{:?}", reported);
+
+    // Without recovery, compiling stops at the first of them
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err();
+    assert_eq!(err.line, 3);
+}
+
+#[test]
+fn test_diagnostics_recover_across_declarations_and_passes() {
+    // A bad global, a bad statement in a nested block, and then two errors only found
+    // while generating code
+    let source = r#"
+        int a[0];
+        int f() { if (1) { return 1 +; } return 0; }
+        int main() { return f(); }
+    "#;
+    let (_, errors) = c4_rust::compile_with_diagnostics(source.as_bytes());
+    let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages.len(), 2, "{:?}", messages);
+    assert!(messages[0].starts_with("Array size must be positive"), "{:?}", messages);
+
+    let source = "int f() { break; return 0; }\nint main() { continue; return f(); }\n";
+    let (program, errors) = c4_rust::compile_with_diagnostics(source.as_bytes());
+    assert!(program.is_none());
+    let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages, vec!["'break' outside of a loop", "'continue' outside of a loop"]);
+    assert_eq!((errors[0].line, errors[1].line), (1, 2));
+
+    // An unterminated body is reported once, not once per enclosing block
+    let (_, errors) = c4_rust::compile_with_diagnostics(b"int main() { while (1) { if (1) { return 0;");
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+}
+
+#[test]
+fn test_diagnostics_of_a_valid_program() {
+    let (program, errors) = c4_rust::compile_with_diagnostics(b"int main() { return 3; }");
+    assert!(errors.is_empty());
+    let mut vm = VM::from_program(program.unwrap(), &Default::default());
+    assert_eq!(vm.run(), Ok(3));
+}
+
+#[test]
+fn test_assignment_to_const_is_rejected() {
+    let cases = [
+        ("int main() { const int x = 5; x = 6; return x; }", "assignment to read-only variable 'x'"),
+        ("const int limit = 3; int main() { limit = 4; return 0; }", "assignment to read-only variable 'limit'"),
+        ("int main() { int const x = 5; x++; return x; }", "increment of read-only variable 'x'"),
+        ("int main() { const int x = 5; --x; return x; }", "decrement of read-only variable 'x'"),
+        ("int main() { char c; char * const p = &c; p = 0; return 0; }", "assignment to read-only variable 'p'"),
+    ];
+    for (source, message) in cases {
+        let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn test_data_regions_are_contiguous() {
+    let mut code = CodeGenerator::new();
+    let a = code.allocate_data(4).unwrap();
+    let s = code.store_string("hi").unwrap();
+    let b = code.allocate_data(8).unwrap();
+    let t = code.store_string("").unwrap();
+    assert_eq!((a, s, b, t), (0, 4, 7, 15));
+    assert_eq!(code.data_offset(), 16);
+    assert_eq!(&code.data[..], b"\0\0\0\0hi\0\0\0\0\0\0\0\0\0\0");
+}
+
+#[test]
+fn test_data_segment_limit() {
+    let mut code = CodeGenerator::new();
+    code.max_data = 8;
+    assert_eq!(code.allocate_data(4), Ok(0));
+    assert!(code.store_string("toolong").unwrap_err().contains("data segment exceeds the limit of 8 bytes"));
+    assert!(code.allocate_data(usize::MAX).is_err());
+    assert_eq!(code.store_string("abc"), Ok(4));
+    assert_eq!(code.data_offset(), 8);
+
+    let mut parser = Parser::new(b"int big[100]; int main() { return 0; }");
+    parser.code.max_data = 64;
+    let err = parser.parse().unwrap_err().to_string();
+    assert!(err.contains("data segment exceeds the limit of 64 bytes"), "{}", err);
+}
+
+#[test]
+fn test_stray_colon() {
+    // An identifier followed by ':' labels the statement
+    let source = "int main() { int x; loop: x = 1; if (x < 1) goto loop; return x; }";
+    assert!(Parser::new(source.as_bytes()).parse().is_ok());
+
+    let cases = [
+        ("int main() { int x; x = 1 : 2; return x; }", "unexpected ':'"),
+        ("int main() { int x; 3: x = 1; return x; }", "unexpected ':'"),
+        ("int main() { : return 0; }", "unexpected ':'"),
+        ("int main() { return 1 ? 2 : 3 : 4; }", "unexpected ':'"),
+        ("int x : 3; int main() { return 0; }", "unexpected ':'"),
+        ("struct s { int a : 3; }; int main() { return 0; }", "bit-fields are not supported"),
+    ];
+    for (source, message) in cases {
+        let err = Parser::new(source.as_bytes()).parse().unwrap_err();
+        assert!(err.to_string().starts_with(message), "{}: {}", source, err);
+        assert_eq!(err.found, Token::Colon, "{}", source);
+    }
+}
+
+#[test]
+fn test_oversized_objects_are_rejected() {
+    let cases = [
+        ("int a[1073741824]; int main() { return 0; }", "array of 1073741824 elements of type 'int' is too large"),
+        ("int a[536870912][8]; int main() { return 0; }", "array of 536870912 elements of type 'int[8]' is too large"),
+        ("struct s { int a[268435455]; int b[268435455]; }; int main() { return 0; }", "'struct s' is too large"),
+        ("int main() { char a[1073741824]; char b[4]; return 0; }", "local variables take up too much stack space"),
+    ];
+    for (source, message) in cases {
+        let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn test_compile_never_panics_on_malformed_input() {
+    let mut inputs: Vec<Vec<u8>> = [
+        "", "\0", "'", "\"", "#define", "int main(){return 1?", "int a[-1];", "int a[0x80000000];",
+        "struct s{struct s x;};", "int main(){return sizeof(struct q);}", "int main(){ int x; return x.y; }",
+        "int main() { return -2147483648 + 99999999999999999999 + 0x; }", "int x[(-2147483647 - 1) / -1];",
+        "int a[4294967297];", "int a[2] = {1,2,3}; char s[2] = \"hello\";", "int main() { goto x; }",
+        "int main() { break; continue; }", "int main() { int x; x = &1; x++ ++; }", "void main() { return 1; }",
+    ].iter().map(|s| s.as_bytes().to_vec()).collect();
+    inputs.push(vec![0xff, 0xfe, b'{', 0x80]);
+
+    // Truncations of a valid program end at every possible point
+    let program = "struct p { int x; }; int g[3] = {1, 2}; int main() { struct p s; s.x = g[1]; loop: if (!s.x) goto loop; return s.x ? 1 : 0; }";
+    inputs.extend((0..program.len()).map(|end| program.as_bytes()[..end].to_vec()));
+
+    for input in inputs {
+        let compiled = std::panic::catch_unwind(|| Parser::new(&input).compile().is_ok());
+        assert!(compiled.is_ok(), "compiling {:?} panicked", String::from_utf8_lossy(&input));
+    }
+}
+
+#[test]
+fn test_void_value_cannot_initialize_a_local() {
+    let source = "void f() { } int main() { int x = f(); return x; }";
+    let err = Parser::new(source.as_bytes()).parse().unwrap_err().to_string();
+    assert!(err.starts_with("void value not ignored as it ought to be"), "unexpected error: {}", err);
+}
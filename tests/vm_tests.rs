@@ -0,0 +1,1636 @@
+use c4_rust::{compile_and_run, compile_and_run_with};
+use c4_rust::codegen::Opcode;
+use c4_rust::parser::Parser;
+use c4_rust::vm::{ArithmeticMode, VmConfig, VmError, VM};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+fn run(source: &str) -> Result<i32, String> {
+    compile_and_run(source.as_bytes(), false)
+}
+
+fn run_bounds_checked(source: &str) -> Result<i32, String> {
+    compile_and_run_with(source.as_bytes(), &VmConfig { bounds_checks: true, ..VmConfig::default() })
+}
+
+// An output sink the test can still read after handing it to a VM
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Run a program on the given input with its output captured, returning the exit code and
+// the bytes written
+fn run_captured(source: &str, input: &'static [u8]) -> (Result<i32, VmError>, Vec<u8>) {
+    let output = SharedOutput::default();
+    let program = Parser::new(source.as_bytes()).compile().unwrap();
+    let mut vm = VM::from_program(program, &VmConfig::default())
+        .with_input(input)
+        .with_output(output.clone());
+    let result = vm.run();
+    let bytes = output.0.borrow().clone();
+    (result, bytes)
+}
+
+#[test]
+fn test_else_if_chain() {
+    let source = r#"
+        int first() { return 100; }
+        int second() { return 200; }
+        int pick(int n) {
+            if (n == 1) return 10;
+            else if (n == 2) return 20;
+            else if (n == 3) return 30;
+            else return 40;
+        }
+        int call(int a) {
+            if (a) return first(); else return second();
+        }
+        int main() {
+            if (pick(1) != 10) return 1;
+            if (pick(2) != 20) return 2;
+            if (pick(3) != 30) return 3;
+            if (pick(7) != 40) return 4;
+            return call(1) + call(0);
+        }
+    "#;
+    assert_eq!(run(source), Ok(300));
+}
+
+#[test]
+fn test_local_array_with_constant_size() {
+    let source = r#"
+        int main() {
+            int a[2 + 3];
+            int i;
+            i = 0;
+            while (i < 5) {
+                a[i] = i * 10;
+                i = i + 1;
+            }
+            return a[1] + a[4];
+        }
+    "#;
+    assert_eq!(run(source), Ok(50));
+}
+
+#[test]
+fn test_locals_do_not_leak_between_functions() {
+    let source = r#"
+        int first(int n) {
+            int tmp;
+            tmp = n * 2;
+            return tmp;
+        }
+        int second(int n) {
+            int tmp;
+            tmp = n + 100;
+            return tmp;
+        }
+        int main() {
+            return first(4) + second(1);
+        }
+    "#;
+    assert_eq!(run(source), Ok(109));
+}
+
+#[test]
+fn test_for_continue_runs_post_expression() {
+    let source = r#"
+        int main() {
+            int i;
+            int steps;
+            int odd;
+            steps = 0;
+            odd = 0;
+            for (i = 0; i < 10; i = i + 1) {
+                steps = steps + 1;
+                if (i % 2 == 0) continue;
+                odd = odd + 1;
+            }
+            return steps * 100 + odd * 10 + i;
+        }
+    "#;
+    // Every iteration reaches the post-expression, including the ones that `continue`
+    assert_eq!(run(source), Ok(1060));
+}
+
+#[test]
+fn test_break_and_continue_in_while() {
+    let source = r#"
+        int main() {
+            int i;
+            int sum;
+            i = 0;
+            sum = 0;
+            while (1) {
+                i = i + 1;
+                if (i > 10) break;
+                if (i % 3 == 0) continue;
+                sum = sum + i;
+            }
+            return sum;
+        }
+    "#;
+    // 1+2+4+5+7+8+10
+    assert_eq!(run(source), Ok(37));
+}
+
+#[test]
+fn test_break_outside_loop_is_an_error() {
+    assert!(run("int main() { break; return 0; }").is_err());
+}
+
+#[test]
+fn test_address_of_array_element() {
+    let source = r#"
+        int main() {
+            int arr[5];
+            int i;
+            for (i = 0; i < 5; i = i + 1) arr[i] = i + 1;
+            memset(&arr[1], 0, 4 * 3);
+            return arr[0] * 10000 + arr[1] * 1000 + arr[2] * 100 + arr[3] * 10 + arr[4];
+        }
+    "#;
+    assert_eq!(run(source), Ok(10005));
+}
+
+#[test]
+fn test_memset_returns_its_destination() {
+    let source = r#"
+        int main() {
+            char buf[8];
+            char *p;
+            buf[5] = 'z';
+            p = memset(buf, 'a', 5);
+            return p == buf && p[0] == 'a' && buf[4] == 'a' && buf[5] == 'z';
+        }
+    "#;
+    assert_eq!(run(source), Ok(1));
+}
+
+#[test]
+fn test_address_of_char_array_element() {
+    let source = r#"
+        int main() {
+            char buf[4];
+            char *p;
+            buf[0] = 'a';
+            buf[1] = 'b';
+            p = &buf[1];
+            *p = 'z';
+            return buf[1] + (p - &buf[0]);
+        }
+    "#;
+    assert_eq!(run(source), Ok('z' as i32 + 1));
+}
+
+#[test]
+fn test_main_return_value_is_exit_code() {
+    let source = r#"
+        int helper(int x) {
+            return x + 1;
+        }
+        int main() {
+            return helper(41);
+        }
+    "#;
+    assert_eq!(run(source), Ok(42));
+}
+
+#[test]
+fn test_returning_from_a_helper_resumes_the_caller() {
+    // LEV must restore the caller's bp and pc, so main's locals survive each call and
+    // execution carries on after it
+    let source = r#"
+        int twice(int x) {
+            int y;
+            y = x + x;
+            return y;
+        }
+        int main() {
+            int a; int b;
+            a = 5;
+            b = twice(a);
+            b = b + twice(b);
+            printf("%d %d\n", a, b);
+            return a + b;
+        }
+    "#;
+    let (result, output) = run_captured(source, b"");
+    assert_eq!(result, Ok(35));
+    assert_eq!(output, b"5 30\n");
+}
+
+#[test]
+fn test_entry_point_is_main_regardless_of_order() {
+    let source = r#"
+        int main() {
+            return third() * 100 + second() * 10 + first();
+        }
+        int third() { return 3; }
+        int second() { return 2; }
+        int first() { return 1; }
+    "#;
+    assert_eq!(run(source), Ok(321));
+}
+
+#[test]
+fn test_main_receives_argc_and_argv() {
+    let source = r#"
+        int main(int argc, char **argv) {
+            if (argv) return 1;
+            return argc;
+        }
+    "#;
+    assert_eq!(run(source), Ok(0));
+}
+
+#[test]
+fn test_char_parameters_and_return_values() {
+    let source = r#"
+        char first(char *s) { return s[0]; }
+        int widen(char c) { return c; }
+        char narrow(int x) { return x; }
+        int main() {
+            if (first("hello") != 'h') return 1;
+            if (widen(300) != 44) return 2;
+            if (narrow(321) != 65) return 3;
+            return first("xyz");
+        }
+    "#;
+    assert_eq!(run(source), Ok('x' as i32));
+}
+
+#[test]
+fn test_assert() {
+    assert_eq!(run("int main() { assert(1); assert(2 > 1); return 5; }"), Ok(5));
+
+    let result = run("int main() { int x; x = 0; assert(x); return 5; }");
+    assert!(result.unwrap_err().contains("assertion failed"));
+}
+
+#[test]
+fn test_if_zero_skips_body() {
+    let source = r#"
+        int main() {
+            int x;
+            x = 1;
+            if (0) {
+                x = 100;
+                return x;
+            }
+            if (0) x = 200; else x = x + 1;
+            return x;
+        }
+    "#;
+    assert_eq!(run(source), Ok(2));
+}
+
+#[test]
+fn test_increment_and_decrement() {
+    let source = r#"
+        int main() {
+            int x;
+            int y;
+            x = 5;
+            y = x++;
+            if (y != 5 || x != 6) return 1;
+            y = ++x;
+            if (y != 7 || x != 7) return 2;
+            y = x--;
+            if (y != 7 || x != 6) return 3;
+            y = --x;
+            if (y != 5 || x != 5) return 4;
+            return 0;
+        }
+    "#;
+    assert_eq!(run(source), Ok(0));
+}
+
+#[test]
+fn test_pointer_increment_stride() {
+    let source = r#"
+        int main() {
+            int a[4];
+            int *p;
+            int before;
+            int after;
+            p = a;
+            before = p;
+            p++;
+            after = p;
+            return after - before;
+        }
+    "#;
+    assert_eq!(run(source), Ok(4));
+
+    let source = r#"
+        int main() {
+            char *c;
+            int before;
+            int after;
+            c = "text";
+            before = c;
+            ++c;
+            c++;
+            c--;
+            after = c;
+            return (after - before) * 100 + *c;
+        }
+    "#;
+    assert_eq!(run(source), Ok(100 + 'e' as i32));
+}
+
+#[test]
+fn test_pointer_comparison_is_unsigned() {
+    let source = r#"
+        int main() {
+            int *high;
+            int *low;
+            int a;
+            int b;
+            high = 0x80000000;
+            low = 0x7fffffff;
+            a = 0x80000000;
+            b = 0x7fffffff;
+            return (high > low) * 10 + (a > b);
+        }
+    "#;
+    assert_eq!(run(source), Ok(10));
+}
+
+#[test]
+fn test_block_local_shadows_global() {
+    let source = r#"
+        int x;
+        int main() {
+            int inner;
+            x = 5;
+            {
+                int x;
+                x = 7;
+                inner = x;
+            }
+            return x * 10 + inner;
+        }
+    "#;
+    assert_eq!(run(source), Ok(57));
+}
+
+#[test]
+fn test_duplicate_local_in_same_block() {
+    assert!(run("int main() { int x; int x; return 0; }").is_err());
+}
+
+#[test]
+fn test_heap_allocation_access() {
+    let source = r#"
+        int main() {
+            int *p;
+            p = malloc(8);
+            *p = 3;
+            p[1] = 4;
+            return *p + p[1];
+        }
+    "#;
+    assert_eq!(run(source), Ok(7));
+}
+
+#[test]
+fn test_use_after_free_is_an_error() {
+    let source = r#"
+        int main() {
+            int *p;
+            p = malloc(8);
+            *p = 3;
+            free(p);
+            return *p;
+        }
+    "#;
+    assert!(run(source).unwrap_err().contains("invalid heap access"));
+}
+
+#[test]
+fn test_empty_allocations_are_distinct() {
+    let source = r#"
+        int main() {
+            int *p;
+            int *q;
+            p = malloc(0);
+            q = malloc(4);
+            if (p == q) return 1;
+            free(p);
+            *q = 7;
+            return *q;
+        }
+    "#;
+    assert_eq!(run(source), Ok(7));
+}
+
+#[test]
+fn test_access_past_allocation_is_an_error() {
+    let source = r#"
+        int main() {
+            int *p;
+            p = malloc(6);
+            p[1] = 1;
+            return 0;
+        }
+    "#;
+    assert!(run(source).unwrap_err().contains("invalid heap access"));
+}
+
+#[test]
+fn test_realloc_keeps_earlier_contents() {
+    let source = r#"
+        int main() {
+            int *p;
+            int *q;
+            p = malloc(8);
+            p[0] = 3;
+            p[1] = 4;
+            q = realloc(p, 16);
+            q[2] = 5;
+            q[3] = 6;
+            return q[0] * 1000 + q[1] * 100 + q[2] * 10 + q[3];
+        }
+    "#;
+    assert_eq!(run(source), Ok(3456));
+}
+
+#[test]
+fn test_realloc_frees_the_old_block() {
+    let source = r#"
+        int main() {
+            int *p;
+            p = malloc(8);
+            realloc(p, 16);
+            return *p;
+        }
+    "#;
+    assert!(run(source).unwrap_err().contains("invalid heap access"));
+}
+
+#[test]
+fn test_realloc_of_null_allocates() {
+    let source = r#"
+        int main() {
+            int *p;
+            p = realloc(0, 4);
+            *p = 9;
+            return *p;
+        }
+    "#;
+    assert_eq!(run(source), Ok(9));
+}
+
+#[test]
+fn test_realloc_of_unallocated_address_is_an_error() {
+    let source = r#"
+        int main() {
+            int x;
+            realloc(&x, 8);
+            return 0;
+        }
+    "#;
+    assert!(run(source).unwrap_err().contains("invalid realloc"));
+}
+
+#[test]
+fn test_assignment_in_loop_condition() {
+    // `next` stands in for getchar, reading from a fixed string until it runs out
+    let source = r#"
+        char *input;
+        int pos;
+        int next() {
+            int c;
+            c = input[pos];
+            if (c == 0) return -1;
+            pos = pos + 1;
+            return c;
+        }
+        int main() {
+            int c;
+            int count;
+            input = "abc";
+            count = 0;
+            while ((c = next()) != -1) count = count * 1000 + c;
+            return count == 97098099 && c == -1;
+        }
+    "#;
+    assert_eq!(run(source), Ok(1));
+}
+
+#[test]
+fn test_assignment_value_as_operand() {
+    let source = r#"
+        int main() {
+            int a;
+            int b;
+            char c;
+            a = b = 5;
+            if ((b = 0)) return 1;
+            if ((c = 300) != 44) return 2;
+            return a * 10 + (b = 3) + b;
+        }
+    "#;
+    assert_eq!(run(source), Ok(56));
+}
+
+#[test]
+fn test_empty_function_bodies() {
+    let source = r#"
+        int n;
+        void nothing() {}
+        int f() {}
+        int main() { nothing(); f(); n = 4; nothing(); return n; }
+    "#;
+    assert_eq!(run(source), Ok(4));
+}
+
+#[test]
+fn test_chained_conditionals_are_right_associative() {
+    assert_eq!(run("int main() { return 1 ? 2 : 0 ? 3 : 4; }"), Ok(2));
+    assert_eq!(run("int main() { return 0 ? 2 : 0 ? 3 : 4; }"), Ok(4));
+    assert_eq!(run("int main() { return 0 ? 2 : 1 ? 3 : 4; }"), Ok(3));
+    // A conditional in the middle needs no parentheses either
+    assert_eq!(run("int main() { return 1 ? 0 ? 5 : 6 : 7; }"), Ok(6));
+
+    let source = r#"
+        int grade(int n) { return n >= 90 ? 'A' : n >= 80 ? 'B' : n >= 70 ? 'C' : 'F'; }
+        int main() { return grade(95) == 'A' && grade(85) == 'B' && grade(75) == 'C' && grade(5) == 'F'; }
+    "#;
+    assert_eq!(run(source), Ok(1));
+}
+
+#[test]
+fn test_escaped_bytes_in_strings() {
+    // Each escape is a single byte, including those above 127
+    let source = r#"
+        int main() {
+            char *s;
+            s = "\351\x41\0z";
+            return s[0] == 233 && s[1] == 'A' && s[2] == 0 && s[3] == 'z' && s[4] == 0;
+        }
+    "#;
+    assert_eq!(run(source), Ok(1));
+}
+
+#[test]
+fn test_braceless_if_else_chain() {
+    let source = r#"
+        int r;
+        int one() { r = 1; return 0; }
+        int two() { r = 2; return 0; }
+        int three() { r = 3; return 0; }
+        int pick(int a, int b) {
+            if (a) one(); else if (b) two(); else three();
+            return r;
+        }
+        int main() {
+            return pick(1, 0) * 100 + pick(0, 1) * 10 + pick(0, 0);
+        }
+    "#;
+    assert_eq!(run(source), Ok(123));
+}
+
+#[test]
+fn test_dangling_else_binds_to_nearest_if() {
+    let source = r#"
+        int f(int a, int b) {
+            int x;
+            x = 0;
+            if (a)
+                if (b) x = 1;
+                else x = 2;
+            return x;
+        }
+        int main() {
+            return f(1, 1) * 100 + f(1, 0) * 10 + f(0, 1);
+        }
+    "#;
+    assert_eq!(run(source), Ok(120));
+}
+
+#[test]
+fn test_logical_not_conditions() {
+    let source = r#"
+        int main() {
+            int done;
+            int steps;
+            int flags;
+            done = 0;
+            steps = 0;
+            while (!done) {
+                steps++;
+                if (steps == 3) done = 1;
+            }
+            flags = 0;
+            if (!0) flags = flags + 1;
+            if (!steps) flags = flags + 10;
+            if (!done && steps) flags = flags + 100;
+            if (!done == 0) flags = flags + 1000;
+            if (!(steps - 3)) flags = flags + 10000;
+            return flags + !steps + !!steps;
+        }
+    "#;
+    assert_eq!(run(source), Ok(11002));
+}
+
+#[test]
+fn test_binary_operand_order() {
+    assert_eq!(run("int main() { return 7 - 3; }"), Ok(4));
+    assert_eq!(run("int main() { return 10 / 2; }"), Ok(5));
+    assert_eq!(run("int main() { return 7 % 4; }"), Ok(3));
+    assert_eq!(run("int main() { return 1 << 3; }"), Ok(8));
+    assert_eq!(run("int main() { return 2 < 5; }"), Ok(1));
+
+    let source = r#"
+        int main() {
+            int a;
+            int b;
+            a = 20;
+            b = 6;
+            return (a - b) * 100 + a / b * 10 + (b - a > 0);
+        }
+    "#;
+    assert_eq!(run(source), Ok(1430));
+}
+
+// x sits just above a on the stack, so a[5] reads it when nothing checks the index
+const ONE_PAST_THE_END: &str = r#"
+    int main() {
+        int x;
+        int a[5];
+        x = 7;
+        return a[5];
+    }
+"#;
+
+#[test]
+fn test_index_past_end_unchecked_by_default() {
+    assert_eq!(run(ONE_PAST_THE_END), Ok(7));
+}
+
+#[test]
+fn test_index_past_end_traps_when_checked() {
+    let err = run_bounds_checked(ONE_PAST_THE_END).unwrap_err();
+    assert!(err.contains("out of bounds for 'a'"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_checked_indexing_within_bounds() {
+    let source = r#"
+        int g[3];
+        int main() {
+            int a[4];
+            int i;
+            i = 0;
+            while (i < 4) { a[i] = i * 2; i++; }
+            g[2] = a[3];
+            return g[2] + a[1];
+        }
+    "#;
+    assert_eq!(run_bounds_checked(source), Ok(8));
+}
+
+#[test]
+fn test_negative_index_traps_when_checked() {
+    let source = r#"
+        int g[3];
+        int main() { int i; i = -1; return g[i]; }
+    "#;
+    let err = run_bounds_checked(source).unwrap_err();
+    assert!(err.contains("array index -1 out of bounds for 'g' of length 3"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_open_creates_writable_file() {
+    let path = std::env::temp_dir().join(format!("c4_open_test_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let source = format!(r#"
+        int main() {{
+            int fd;
+            fd = open("{}", O_WRONLY | O_CREAT);
+            if (fd < 0) return 1;
+            close(fd);
+            return 0;
+        }}
+    "#, path.display());
+
+    assert_eq!(run(&source), Ok(0));
+    let metadata = std::fs::metadata(&path).expect("open should have created the file");
+    assert!(!metadata.permissions().readonly());
+    std::fs::OpenOptions::new().write(true).open(&path).expect("file should be writable");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_open_missing_file_read_only_fails() {
+    let source = r#"
+        int main() { return open("/nonexistent/c4/file", O_RDONLY); }
+    "#;
+    assert_eq!(run(source), Ok(-1));
+}
+
+#[test]
+fn test_read_fills_buffer_from_file() {
+    let path = std::env::temp_dir().join(format!("c4_read_test_{}", std::process::id()));
+    std::fs::write(&path, b"hello, file").unwrap();
+    let source = format!(r#"
+        int main() {{
+            int fd; int n; int rest; char buf[16];
+            memset(buf, 0, 16);
+            fd = open("{}", O_RDONLY);
+            if (fd < 0) return 1;
+            n = read(fd, buf, 5);
+            rest = read(fd, buf + 5, 10);
+            printf("%d %d %s|%d\n", n, rest, buf, read(fd, buf, 10));
+            close(fd);
+            return read(fd, buf, 1);
+        }}
+    "#, path.display());
+
+    let (result, output) = run_captured(&source, b"");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap(), -1); // The descriptor is closed
+    assert_eq!(String::from_utf8(output).unwrap(), "5 6 hello, file|0\n");
+}
+
+#[test]
+fn test_read_from_standard_input() {
+    let source = r#"
+        int main() {
+            char buf[8];
+            int n;
+            n = read(0, buf, 8);
+            buf[n] = 0;
+            printf("%s", buf);
+            return n;
+        }
+    "#;
+    let (result, output) = run_captured(source, b"abc");
+    assert_eq!(result.unwrap(), 3);
+    assert_eq!(output, b"abc");
+}
+
+#[test]
+fn test_runtime_error_reports_source_line() {
+    let source = "int main() {\n    int a;\n    a = 0;\n    return 10 / a;\n}\n";
+    let err = run(source).unwrap_err();
+    assert_eq!(err, "Division by zero (line 4)");
+}
+
+#[test]
+fn test_runtime_error_reports_operator_line() {
+    // The division is attributed to the line of the '/', not of its operands
+    let source = "int main() {\n    int a;\n    a = 0;\n    return 10\n        /\n        a;\n}\n";
+    let err = run(source).unwrap_err();
+    assert_eq!(err, "Division by zero (line 5)");
+}
+
+#[test]
+fn test_array_initializer_list() {
+    let source = r#"
+        int main() {
+            int a[3] = {1, 2, 3};
+            return a[2];
+        }
+    "#;
+    assert_eq!(run(source), Ok(3));
+}
+
+#[test]
+fn test_partial_array_initializer_zero_fills() {
+    let source = r#"
+        int g[4] = {5, 6};
+        int main() {
+            int a[5] = {1, 2};
+            a[4] = a[4] + g[3];
+            return a[0] * 1000 + a[1] * 100 + a[2] * 10 + a[4] + g[1];
+        }
+    "#;
+    assert_eq!(run(source), Ok(1206));
+}
+
+#[test]
+fn test_nested_array_initializer() {
+    let source = r#"
+        int mat[2][2] = {{1, 2}, {3, 4}};
+        int main() {
+            char grid[2][3] = {{1}, {4, 5, 6}};
+            return mat[1][0] * 100 + mat[0][1] * 10 + grid[1][2] + grid[0][2];
+        }
+    "#;
+    assert_eq!(run(source), Ok(326));
+}
+
+#[test]
+fn test_too_many_array_initializers() {
+    let source = r#"
+        int main() {
+            int a[2] = {1, 2, 3};
+            return 0;
+        }
+    "#;
+    let err = run(source).unwrap_err();
+    assert!(err.contains("Too many initializers for array of length 2"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_global_scalar_initializer() {
+    let source = r#"
+        int count = 40 + 2;
+        char letter = 'x';
+        int main() { return count + letter - 'x'; }
+    "#;
+    assert_eq!(run(source), Ok(42));
+}
+
+#[test]
+fn test_void_function_called_for_side_effect() {
+    // Any word leaked per call would overflow the 4 KiB stack long before the loop ends
+    let source = r#"
+        int total;
+        void add(int n) { total = total + n; }
+        void reset(void) { total = 0; }
+        int main() {
+            int i;
+            reset();
+            i = 0;
+            while (i < 5000) { add(2); i++; }
+            return total / 1000;
+        }
+    "#;
+    let config = VmConfig { stack_size: 4096, ..VmConfig::default() };
+    assert_eq!(compile_and_run_with(source.as_bytes(), &config), Ok(10));
+}
+
+#[test]
+fn test_void_value_cannot_be_used() {
+    let source = r#"
+        void f() { }
+        int main() { int x; x = f(); return x; }
+    "#;
+    assert_eq!(run(source), Err("void value not ignored as it ought to be".to_string()));
+}
+
+#[test]
+fn test_void_variable_is_rejected() {
+    let source = "int main() { void v; return 0; }";
+    assert_eq!(run(source), Err("variable 'v' declared void".to_string()));
+}
+
+#[test]
+fn test_bare_return_in_void_function() {
+    let source = r#"
+        int hits;
+        void bump(int n) {
+            if (n < 0) return;
+            hits = hits + n;
+            return;
+        }
+        int main() { bump(3); bump(-1); bump(4); return hits; }
+    "#;
+    assert_eq!(run(source), Ok(7));
+}
+
+#[test]
+fn test_return_value_required_in_non_void_function() {
+    let source = "int f() { return; } int main() { return f(); }";
+    let err = run(source).unwrap_err();
+    assert!(err.starts_with("return with no value in function returning non-void"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_return_value_rejected_in_void_function() {
+    let source = "void f() { return 1; } int main() { f(); return 0; }";
+    let err = run(source).unwrap_err();
+    assert!(err.starts_with("return with a value in function returning void"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_profile_counts_loop_opcodes() {
+    let source = r#"
+        int main() {
+            int i;
+            i = 0;
+            while (i < 10) i = i + 1;
+            return i;
+        }
+    "#;
+    let program = Parser::new(source.as_bytes()).compile().unwrap();
+    let mut vm = VM::from_program(program, &VmConfig { profile: true, ..VmConfig::default() });
+    assert_eq!(vm.run(), Ok(10));
+
+    // One addition and one jump back to the condition per iteration
+    assert_eq!(vm.executions(Opcode::ADD), 10);
+    assert_eq!(vm.executions(Opcode::JMP), 10);
+    assert_eq!(vm.executions(Opcode::BZ), 11);
+}
+
+#[test]
+fn test_profile_is_off_by_default() {
+    let program = Parser::new(b"int main() { return 1 + 2; }").compile().unwrap();
+    let mut vm = VM::from_program(program, &VmConfig::default());
+    assert_eq!(vm.run(), Ok(3));
+    assert!(vm.profile().is_none());
+    assert_eq!(vm.executions(Opcode::ADD), 0);
+}
+
+#[test]
+fn test_argument_and_local_in_same_frame() {
+    let source = r#"
+        int f(int a, int b) {
+            int x;
+            int y;
+            x = 100;
+            y = 1000;
+            return a + b * 10 + x + y;
+        }
+        int main() { return f(3, 4); }
+    "#;
+    assert_eq!(run(source), Ok(1143));
+}
+
+#[test]
+fn test_continued_directive_keeps_error_lines() {
+    let source = "#define ZERO \\\n    0\nint main() {\n    int z;\n    z = ZERO;\n    return 1 / z;\n}\n";
+    assert_eq!(run(source), Err("Division by zero (line 6)".to_string()));
+}
+
+#[test]
+fn test_static_global_is_usable() {
+    let source = r#"
+        static int x = 3;
+        static int twice(int n) { return n * 2; }
+        int main() { x = twice(x); return x; }
+    "#;
+    assert_eq!(run(source), Ok(6));
+}
+
+#[test]
+fn test_extern_declarations() {
+    let source = r#"
+        extern int y;
+        extern int count;
+        extern int helper(int n);
+        int main() { count = 5; return helper(count) + y; }
+        int y = 30;
+        int helper(int n) { return n + 1; }
+    "#;
+    assert_eq!(run(source), Ok(36));
+}
+
+#[test]
+fn test_extern_initializer_is_rejected() {
+    let err = run("extern int y = 1; int main() { return y; }").unwrap_err();
+    assert_eq!(err, "extern variable 'y' cannot have an initializer");
+}
+
+#[test]
+fn test_global_defined_twice_is_rejected() {
+    assert!(run("extern int y; int y; int y; int main() { return 0; }").is_err());
+    assert!(run("extern int y; char y; int main() { return 0; }").is_err());
+}
+
+#[test]
+fn test_store_into_global_and_local() {
+    let source = r#"
+        int g;
+        char buf[4];
+        int main() {
+            int local;
+            char c;
+            g = 1000;
+            local = 234;
+            buf[0] = 1; buf[1] = 2; buf[2] = 3; buf[3] = 4;
+            buf[1] = 513;   // only the low byte is stored
+            c = 7;
+            return g + local + buf[0] * 1000 + buf[1] * 100 + buf[2] * 10 + buf[3] + c;
+        }
+    "#;
+    assert_eq!(run(source), Ok(1000 + 234 + 1000 + 100 + 30 + 4 + 7));
+}
+
+#[test]
+fn test_char_assignment_value_is_narrowed() {
+    let source = "int main() { char c; int x; x = (c = 300); return x; }";
+    assert_eq!(run(source), Ok(44));
+}
+
+#[test]
+fn test_call_through_function_pointer() {
+    let source = r#"
+        int twice(int n) { return n * 2; }
+        int add(int a, int b) { return a + b; }
+        int later(int n);
+        int (*global_op)(int, int);
+        int apply(int (*f)(int), int x) { return f(x); }
+        int main() {
+            int (*fp)(int);
+            fp = twice;
+            global_op = &add;
+            return fp(5) + (*fp)(1) + global_op(100, 200) + apply(later, 3);
+        }
+        int later(int n) { return n + 1000; }
+    "#;
+    assert_eq!(run(source), Ok(10 + 2 + 300 + 1003));
+}
+
+#[test]
+fn test_function_pointer_type_mismatch() {
+    let source = r#"
+        int add(int a, int b) { return a + b; }
+        int main() { int (*fp)(int); fp = add; return 0; }
+    "#;
+    let err = run(source).unwrap_err();
+    assert_eq!(err, "incompatible types: cannot assign int (*)(int, int) to int (*)(int)");
+}
+
+#[test]
+fn test_calling_a_non_function_is_rejected() {
+    let err = run("int main() { int x; x = 1; return x(2); }").unwrap_err();
+    assert!(err.starts_with("Called object is not a function"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_image_round_trip_runs_like_the_compiled_program() {
+    let source = r#"
+        char *greeting = "hi";
+        int counts[3] = {4, 5, 6};
+        int main() { return greeting[1] + counts[2]; }
+    "#;
+    let expected = run(source).unwrap();
+
+    let program = Parser::new(source.as_bytes()).compile().unwrap();
+    let path = std::env::temp_dir().join(format!("c4_image_test_{}.c4b", std::process::id()));
+    std::fs::write(&path, program.to_image()).unwrap();
+    let result = VM::from_image(&path, &VmConfig::default()).and_then(|mut vm| vm.run().map_err(|err| err.to_string()));
+    std::fs::remove_file(&path).ok();
+    assert_eq!(result, Ok(expected));
+}
+
+#[test]
+fn test_image_header_is_validated() {
+    use c4_rust::codegen::{Program, IMAGE_VERSION};
+
+    let program = Parser::new(b"int main() { return 1 / 0; }").compile().unwrap();
+    let mut image = program.to_image();
+    let loaded = Program::from_image(&image).unwrap();
+    assert_eq!((&loaded.text, &loaded.data, &loaded.lines), (&program.text, &program.data, &program.lines));
+
+    image[4..8].copy_from_slice(&(IMAGE_VERSION + 1).to_le_bytes());
+    assert_eq!(Program::from_image(&image).err(), Some(format!("unsupported image version {} (expected {})", IMAGE_VERSION + 1, IMAGE_VERSION)));
+    assert_eq!(Program::from_image(b"ELF\0").err(), Some("not a compiled image: bad magic number".to_string()));
+    assert_eq!(Program::from_image(&program.to_image()[..20]).err(), Some("truncated image".to_string()));
+}
+
+#[test]
+fn test_stack_is_balanced_at_every_lev() {
+    let source = r#"
+        int g(int a) { return a * 2; }
+        int f(int x) { int a; int b; a = g(x); b = g(a + 1); return a + b; }
+        int main() { return f(3); }
+    "#;
+    let program = Parser::new(source.as_bytes()).compile().unwrap();
+    let mut vm = VM::from_program(program, &VmConfig::default());
+    vm.capture_trace();
+    assert_eq!(vm.run(), Ok(6 + 14));
+
+    // Just before each LEV only the function's locals remain below bp
+    let field = |line: &str, name: &str| -> i64 {
+        line.split_whitespace().find_map(|word| word.strip_prefix(name)).unwrap().parse().unwrap()
+    };
+    let trace = vm.trace();
+    let reserved: Vec<i64> = (1..trace.len())
+        .filter(|&i| trace[i].contains("LEV"))
+        .map(|i| field(&trace[i - 1], "bp=") - field(&trace[i - 1], "sp="))
+        .collect();
+    assert_eq!(reserved, [0, 0, 8, 0]);
+}
+
+#[test]
+fn test_runtime_errors_are_typed() {
+    let program = Parser::new(b"int main() {\n  int zero = 0;\n  return 1 / zero;\n}").compile().unwrap();
+    let err = VM::from_program(program, &VmConfig::default()).run().unwrap_err();
+    assert!(matches!(err.kind(), VmError::DivByZero), "{:?}", err);
+    assert!(matches!(err, VmError::Located { line: 3, .. }), "{:?}", err);
+    assert_eq!(err.to_string(), "Division by zero (line 3)");
+}
+
+#[test]
+fn test_cycle_limit_stops_endless_loops() {
+    let program = Parser::new(b"int main() { while (1) {} return 0; }").compile().unwrap();
+    let config = VmConfig { max_cycles: Some(1000), ..VmConfig::default() };
+    let err = VM::from_program(program, &config).run().unwrap_err();
+    assert_eq!(err.kind(), &VmError::CycleLimit(1000));
+    assert_eq!(err.to_string(), "Instruction limit of 1000 exceeded");
+}
+
+#[test]
+fn test_left_associative_chains() {
+    let cases = [
+        ("10 - 3 - 2", 5),
+        ("16 / 2 / 2", 4),
+        ("100 % 7 % 3", 2),
+        ("256 >> 2 >> 1", 32),
+        ("1 << 3 << 2", 32),
+        ("20 - 4 + 3 - 1", 18),
+        ("64 / 4 * 2 / 8", 4),
+        ("2 - 3 * 4 - 5", -15),
+    ];
+    for (expr, expected) in cases {
+        let source = format!("int main() {{ int a = 0; a = {}; return a; }}", expr);
+        assert_eq!(run(&source), Ok(expected), "{}", expr);
+    }
+
+    // Constant expressions fold the same way
+    assert_eq!(run("int g = 10 - 3 - 2; int main() { return g + 16 / 2 / 2; }"), Ok(9));
+}
+
+#[test]
+fn test_operator_precedence() {
+    let cases = [
+        ("2 + 3 * 4", 14),
+        ("(2 + 3) * 4", 20),
+        ("10 - 2 * 3", 4),
+        ("2 * 3 + 4 * 5", 26),
+        ("1 + 2 << 3", 24),
+        ("1 < 2 == 1", 1),
+        ("7 & 3 == 3", 1),
+        ("1 | 2 ^ 3 & 6", 1),
+        ("1 || 0 && 0", 1),
+        ("2 * 3 - 10 % 4", 4),
+        ("!0 + 1", 2),
+        ("-2 * 3 + 10", 4),
+        ("-(2 + 3) * 2", -10),
+        ("10 - -3", 13),
+    ];
+    for (expr, expected) in cases {
+        let source = format!("int main() {{ return {}; }}", expr);
+        assert_eq!(run(&source), Ok(expected), "{}", expr);
+
+        // The same shapes with variables, so nothing can be folded at compile time
+        let vars = expr.replace("10", "ten").replace('2', "two").replace('3', "three");
+        let source = format!("int main() {{ int ten; int two; int three; ten = 10; two = 2; three = 3; return {}; }}", vars);
+        assert_eq!(run(&source), Ok(expected), "{}", vars);
+    }
+}
+
+#[test]
+fn test_sizeof_array_is_its_total_size() {
+    let source = r#"
+        char g[7];
+        int grid[3][2];
+        int main() {
+            char a[10];
+            char *p;
+            int b[4];
+            int m[2][3];
+            return sizeof(a) == 10 && sizeof(p) == 4 && sizeof(b) == 16 && sizeof(g) == 7
+                && sizeof(m) == 24 && sizeof(m[1]) == 12 && sizeof(grid) == 24 && sizeof((a)) == 10;
+        }
+    "#;
+    assert_eq!(run(source), Ok(1));
+
+    // Once the array has decayed, the operand is just a pointer or an element
+    let source = r#"
+        int main() {
+            char a[10];
+            int b[4];
+            return sizeof(a + 1) * 1000 + sizeof(&a[0]) * 100 + sizeof(a[0]) * 10 + sizeof(b[0]);
+        }
+    "#;
+    assert_eq!(run(source), Ok(4414));
+}
+
+#[test]
+fn test_character_classification() {
+    assert_eq!(run("int main() { return isdigit('5'); }"), Ok(1));
+    assert_eq!(run("int main() { return isdigit('a'); }"), Ok(0));
+    assert_eq!(run("int main() { return isalpha('a') + isalpha('Z') * 2 + isalpha('5') * 4; }"), Ok(3));
+    assert_eq!(run("int main() { return isspace(' ') + isspace('\\n') + isspace('\\t') + isspace('x'); }"), Ok(3));
+
+    // EOF and values outside a byte belong to no class
+    assert_eq!(run("int main() { return isdigit(-1) + isspace(256 + ' '); }"), Ok(0));
+
+    // Counting the digits of a string
+    let source = r#"
+        int main() {
+            char *s = "a1b22 c333";
+            int n = 0;
+            while (*s) { if (isdigit(*s)) n = n + 1; s = s + 1; }
+            return n;
+        }
+    "#;
+    assert_eq!(run(source), Ok(6));
+}
+
+#[test]
+fn test_unbounded_recursion_overflows_the_stack() {
+    let source = "int down(int n) { return down(n + 1) + 1; }\nint main() { return down(0); }";
+    let program = Parser::new(source.as_bytes()).compile().unwrap();
+    let err = VM::from_program(program, &VmConfig::default()).run().unwrap_err();
+    assert_eq!(err.kind(), &VmError::StackOverflow);
+
+    // Large frames are caught before they are entered
+    let source = "int deep(int n) { int big[50000]; big[0] = n; return deep(n + 1); }\nint main() { return deep(0); }";
+    let program = Parser::new(source.as_bytes()).compile().unwrap();
+    let err = VM::from_program(program, &VmConfig::default()).run().unwrap_err();
+    assert_eq!(err.kind(), &VmError::StackOverflow);
+}
+
+#[test]
+fn test_signed_and_unsigned_char_loads() {
+    // The same 0xFF byte reads back as -1 through signed char and 255 otherwise
+    let source = r#"
+        char bytes[1] = {255};
+        int main() {
+            void *raw = bytes;
+            signed char *s = raw;
+            unsigned char *u = bytes;
+            return (*s == -1) + (*u == 255) * 2 + (bytes[0] == 255) * 4;
+        }
+    "#;
+    assert_eq!(run(source), Ok(7));
+
+    let source = r#"
+        signed char narrow(int x) { return x; }
+        int main() {
+            signed char c = -1;
+            unsigned char u = -1;
+            signed char d;
+            int stored = (d = 200);
+            return (c == -1) + (u == 255) * 2 + (stored == -56) * 4 + (narrow(383) == 127) * 8 + (narrow(129) == -127) * 16;
+        }
+    "#;
+    assert_eq!(run(source), Ok(31));
+}
+
+#[test]
+fn test_unsigned_int_is_rejected() {
+    let err = run("int main() { unsigned x; return 0; }").unwrap_err();
+    assert!(err.starts_with("unsigned int is not supported"), "{}", err);
+    assert_eq!(run("int main() { signed x = -3; signed int y = 2; return x * y; }"), Ok(-6));
+}
+
+#[test]
+fn test_integer_literal_range() {
+    assert_eq!(run("int main() { return -2147483648 == 1 << 31; }"), Ok(1));
+    assert_eq!(run("int main() { return 0xFFFFFFFF; }"), Ok(-1));
+    assert_eq!(run("int main() { return 4294967295 == -1; }"), Ok(1));
+
+    // Anything wider would need long, which does not exist, so it is not silently truncated
+    for literal in ["5000000000", "0x100000000", "99999999999999999999999999"] {
+        let err = run(&format!("int main() {{ return {}; }}", literal)).unwrap_err();
+        let expected = format!("integer literal {} is too large for int (long is not supported)", literal);
+        assert!(err.starts_with(&expected), "{}", err);
+    }
+}
+
+#[test]
+fn test_nested_calls_as_arguments() {
+    let source = r#"
+        int inner(int x) { return x * 10; }
+        int outer(int a, int b) { return a - b; }
+        int three(int a, int b, int c) { return a * 100 + b * 10 + c; }
+        int main() {
+            return outer(inner(1), 2) + three(inner(0) + 1, outer(5, inner(0)), three(0, 0, outer(9, 7)));
+        }
+    "#;
+    assert_eq!(run(source), Ok(8 + 152));
+}
+
+#[test]
+fn test_output_goes_to_the_injected_sink() {
+    let (result, output) = run_captured(r#"int main() { printf("hi"); return 0; }"#, b"");
+    assert_eq!(result, Ok(0));
+    assert_eq!(output, b"hi");
+
+    let source = r#"int main() { putchar('a'); puts("bc"); printf("%d", 12); return 0; }"#;
+    assert_eq!(run_captured(source, b"").1, b"abc\n12");
+}
+
+#[test]
+fn test_getchar_reads_the_injected_input() {
+    let source = r#"
+        int main() {
+            int a; int b; int c;
+            a = getchar(); b = getchar(); c = getchar();
+            return a == 'x' && b == 'y' && c == '\n' && getchar() == -1 && getchar() == -1;
+        }
+    "#;
+    assert_eq!(run_captured(source, b"xy\n").0, Ok(1));
+
+    // Echo the input back, stopping at the end of it
+    let source = "int main() { int c; while ((c = getchar()) != -1) putchar(c); return 0; }";
+    assert_eq!(run_captured(source, b"echo"), (Ok(0), b"echo".to_vec()));
+}
+
+#[test]
+fn test_struct_member_access() {
+    // b sits at offset 4 and is a char, so storing 300 through it keeps only the low byte
+    let source = r#"
+        struct pair { int a; char b; int c; };
+        struct pair g;
+        int main() {
+            struct pair s;
+            struct pair *p;
+            s.a = 1000;
+            s.b = 'x';
+            s.c = -7;
+            p = &s;
+            p->b = 300;
+            g.c = p->a + s.c;
+            if (s.b != 44 || (*p).a != 1000 || &p->c - &s.a != 2) return 1;
+            return g.c;
+        }
+    "#;
+    assert_eq!(run(source), Ok(993));
+}
+
+#[test]
+fn test_struct_members_through_pointers() {
+    let source = r#"
+        struct node { int value; struct node *next; char tag[4]; };
+        int sum(struct node *n) {
+            int total;
+            total = 0;
+            while (n) {
+                total = total + n->value;
+                n = n->next;
+            }
+            return total;
+        }
+        int main() {
+            struct node *a;
+            struct node b;
+            a = malloc(sizeof(struct node));
+            a->value = 5;
+            a->next = &b;
+            b.value = 37;
+            b.next = 0;
+            b.tag[2] = 'q';
+            a->value++;
+            return sum(a) + (b.tag[2] == 'q') * 100 + sizeof(b.tag);
+        }
+    "#;
+    assert_eq!(run(source), Ok(147));
+}
+
+#[test]
+fn test_invalid_member_access() {
+    let cases = [
+        ("struct s { int a; }; int main() { struct s v; return v.b; }", "'struct s' has no member named 'b'"),
+        ("struct s { int a; }; int main() { struct s v; return v->a; }", "invalid type argument of '->' (have 'struct s')"),
+        ("int main() { int x; return x.a; }", "request for member 'a' in something not a structure"),
+        ("struct s *p; int main() { return p->a; }", "dereferencing pointer to incomplete type 'struct s'"),
+        ("struct s { int a; }; int main() { struct s v; return v + 1; }", "used struct type value where scalar is required"),
+        ("struct s { int a; }; int main() { struct s v; struct s w; v = w; return 0; }", "used struct type value where scalar is required"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err();
+        assert!(err.starts_with(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn test_forward_goto_skips_a_statement() {
+    let source = r#"
+        int main() {
+            int x;
+            x = 1;
+            goto done;
+            x = 2;
+        done:
+            return x;
+        }
+    "#;
+    assert_eq!(run(source), Ok(1));
+}
+
+#[test]
+fn test_goto_loops_and_labels_after_return() {
+    // The backward jump forms a loop, and the code after `return` stays reachable through
+    // the label inside the nested block
+    let source = r#"
+        int main() {
+            int i; int sum;
+            i = 0; sum = 0;
+        again:
+            sum = sum + i;
+            i++;
+            if (i < 5) goto again;
+            goto out;
+            return 99;
+            {
+            out:
+                return sum;
+            }
+        }
+    "#;
+    assert_eq!(run(source), Ok(10));
+}
+
+#[test]
+fn test_invalid_goto() {
+    let cases = [
+        ("int main() { goto nowhere; return 0; }", "label 'nowhere' used but not defined"),
+        ("int main() { a: ; a: return 0; }", "duplicate label 'a'"),
+        ("int f() { x: return 0; } int main() { goto x; }", "label 'x' used but not defined"),
+        ("int main() { goto 5; }", "Expected label after 'goto'"),
+    ];
+    for (source, message) in cases {
+        let err = run(source).unwrap_err();
+        assert!(err.starts_with(message), "{}: {}", source, err);
+    }
+}
+
+#[test]
+fn test_shift_counts_are_masked() {
+    // Shift counts are taken modulo 32, as the hardware does, so no count can crash the VM
+    let source = r#"
+        int main() {
+            int one; int big; int neg;
+            one = 1; big = 40; neg = -1;
+            if ((one << 31) != -2147483647 - 1) return 1;
+            if ((one << big) != 256) return 2;
+            if ((one << neg) != -2147483647 - 1) return 3;
+            if ((-16 >> 2) != -4) return 4;
+            if ((4096 >> big) != 16) return 5;
+            if ((-1 >> neg) != -1) return 6;
+            return 0;
+        }
+    "#;
+    assert_eq!(run(source), Ok(0));
+}
+
+#[test]
+fn test_checked_mode_rejects_out_of_range_shift_counts() {
+    let config = VmConfig { arithmetic: ArithmeticMode::Checked, ..VmConfig::default() };
+    let run_checked = |source: &str| compile_and_run_with(source.as_bytes(), &config);
+    assert_eq!(run_checked("int main() { int n; n = 31; return (1 << n) == -2147483647 - 1; }"), Ok(1));
+    assert_eq!(run_checked("int main() { int n; n = 3; return -64 >> n; }"), Ok(-8));
+    for shift in ["1 << n", "1 >> n", "1 << -n"] {
+        let source = format!("int main() {{ int n; n = 32; return {}; }}", shift);
+        let err = run_checked(&source).unwrap_err();
+        assert!(err.starts_with("Arithmetic overflow in SH"), "{}: {}", shift, err);
+    }
+}
+
+#[test]
+fn test_unary_minus_of_a_variable() {
+    assert_eq!(run("int main() { int x = 5; return -x; }"), Ok(-5));
+
+    let source = r#"
+        int neg(int n) { return -n; }
+        int main() {
+            int x; char c; int *p;
+            x = 7; c = 200; p = &x;
+            if (-c != -200) return 1;
+            if (-*p + +x != 0) return 2;
+            if (-neg(x) != 7) return 3;
+            if (-(x ? 1 : 2) != -1) return 4;
+            if (- -x != 7) return 5;
+            return -x * 2;
+        }
+    "#;
+    assert_eq!(run(source), Ok(-14));
+}
+
+#[test]
+fn test_unary_minus_of_an_address_is_rejected() {
+    for operand in ["f", "\"s\"", "a", "p"] {
+        let source = format!("int f() {{ return 1; }} int a[2]; int main() {{ int x; int *p; x = -{}; return x; }}", operand);
+        let err = run(&source).unwrap_err();
+        assert!(err.contains("wrong type argument to unary minus"), "-{}: {}", operand, err);
+    }
+
+    // Constants are still folded, named ones included
+    assert_eq!(run("int main() { return -O_WRONLY + -'a' + -sizeof(int); }"), Ok(-1 - 97 - 4));
+}
+
+#[test]
+fn test_abs_min_max() {
+    assert_eq!(run("int main() { return abs(-3); }"), Ok(3));
+    assert_eq!(run("int main() { return min(4, 7); }"), Ok(4));
+    assert_eq!(run("int main() { return max(4, 7); }"), Ok(7));
+
+    let source = r#"
+        int main() {
+            int x; x = -12;
+            return abs(x) * 100 + min(x, -20) + max(abs(x), min(5, 9)) + abs(0);
+        }
+    "#;
+    assert_eq!(run(source), Ok(1192));
+
+    // abs of the most negative int wraps unless arithmetic is checked
+    let source = "int main() { return abs(-2147483647 - 1) < 0; }";
+    assert_eq!(run(source), Ok(1));
+    let config = VmConfig { arithmetic: ArithmeticMode::Checked, ..VmConfig::default() };
+    assert!(compile_and_run_with(source.as_bytes(), &config).unwrap_err().starts_with("Arithmetic overflow in ABS"));
+
+    // A program's own definition still takes precedence
+    assert_eq!(run("int max(int a, int b) { return 99; } int main() { return max(1, 2); }"), Ok(99));
+    // while a prototype only declares the builtin
+    assert_eq!(run("int abs(int n); int main() { return abs(-4); }"), Ok(4));
+}
+
+#[test]
+fn test_const_variables() {
+    assert_eq!(run("int main() { const int x = 5; return x; }"), Ok(5));
+    assert_eq!(run("const int base = 40; int main() { return base + 2; }"), Ok(42));
+
+    // A pointer to const can itself be reassigned, and const parameters are accepted
+    let source = r#"
+        int length(const char *s) { const char *p; p = s; while (*p) p++; return p - s; }
+        int twice(const int n) { return n * 2; }
+        int main() { return length("abc") + twice(10); }
+    "#;
+    assert_eq!(run(source), Ok(23));
+    assert_eq!(run("int main() { return sizeof(const int); }"), Ok(4));
+}
+
+#[test]
+fn test_equality_operators() {
+    assert_eq!(run("int main() { return 3 == 3; }"), Ok(1));
+    assert_eq!(run("int main() { return 3 != 4; }"), Ok(1));
+    assert_eq!(run("int main() { return (3 == 4) + (3 != 3) * 2; }"), Ok(0));
+
+    // The left operand is pushed before the right one is evaluated, so both may have side effects
+    let source = r#"
+        int n;
+        int next() { n = n + 1; return n; }
+        int main() {
+            char c; int a[2]; int *p; int *q;
+            p = a; q = a + 1;
+            c = (p == q) + (p != q) * 2 + (p + 1 == q) * 4;
+            return c * 10 + (next() == next() - 1);
+        }
+    "#;
+    assert_eq!(run(source), Ok(61));
+}
+
+#[test]
+fn test_global_array_shared_between_functions() {
+    let source = r#"
+        int counts[10];
+        void fill() { int i; for (i = 0; i < 10; i++) counts[i] = i * i; }
+        int third() { return counts[3]; }
+        int main() { fill(); return third() * 100 + counts[9]; }
+    "#;
+    assert_eq!(run(source), Ok(981));
+}
+
+#[test]
+fn test_logical_not() {
+    assert_eq!(run("int main() { return !5; }"), Ok(0));
+    assert_eq!(run("int main() { return !0; }"), Ok(1));
+    assert_eq!(run("int main() { int x; char *p; x = -3; p = 0; return !x + !!x * 2 + !p * 4 + !(x + 3) * 8; }"), Ok(14));
+    assert_eq!(run("int a[!0 + !7 + 2]; int main() { return sizeof(a); }"), Ok(12));
+
+    // `!` is a single instruction rather than a comparison with zero
+    let program = Parser::new(b"int main() { int x; x = 5; return !x; }").compile().unwrap();
+    assert!(program.text.contains(&(Opcode::LNOT as i32)));
+    assert!(!program.text.contains(&(Opcode::EQ as i32)));
+}
+
+#[test]
+fn test_printf_star_width_and_precision() {
+    let source = r#"
+        int main() {
+            printf("%.*s|", 3, "hello");
+            printf("%*d|", 4, 7);
+            printf("%-*d|%.*s\n", 3, 1, 2, "xyz");
+            return 0;
+        }
+    "#;
+    let (result, output) = run_captured(source, b"");
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(String::from_utf8(output).unwrap(), "hel|   7|1  |xy\n");
+}
+
+#[test]
+fn test_int_min_divided_by_minus_one() {
+    let divide = "int main() { int a; a = -2147483647 - 1; return a / -1 == a; }";
+    let remainder = "int main() { int a; a = -2147483647 - 1; return a % -1; }";
+    assert_eq!(run(divide), Ok(1));
+    assert_eq!(run(remainder), Ok(0));
+
+    let config = VmConfig { arithmetic: ArithmeticMode::Checked, ..VmConfig::default() };
+    let err = compile_and_run_with(divide.as_bytes(), &config).unwrap_err();
+    assert!(err.starts_with("Arithmetic overflow in DIV (-2147483648, -1)"), "{}", err);
+    let err = compile_and_run_with(remainder.as_bytes(), &config).unwrap_err();
+    assert!(err.starts_with("Arithmetic overflow in MOD (-2147483648, -1)"), "{}", err);
+    assert_eq!(compile_and_run_with(b"int main() { int a; a = -7; return a / 2 * 10 + a % 2; }", &config), Ok(-31));
+}